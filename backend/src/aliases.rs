@@ -1,4 +1,5 @@
-use rand::{seq::SliceRandom, SeedableRng};
+use crate::AliasFormat;
+use rand::{seq::SliceRandom, Rng, SeedableRng};
 use rand_chacha::ChaCha20Rng;
 use std::convert::{TryFrom, TryInto};
 
@@ -6,6 +7,10 @@ use std::convert::{TryFrom, TryInto};
 const ADJECTIVES: &[&str] = &include!(concat!(env!("OUT_DIR"), "/adjectives.rs"));
 // List of English noun words
 const NOUNS: &[&str] = &include!(concat!(env!("OUT_DIR"), "/nouns.rs"));
+// The Bitcoin base58 alphabet, which leaves out look-alike characters.
+const BASE58: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+/// Formats with less entropy than this are rejected, as their aliases could be guessed.
+pub const MIN_ALIAS_ENTROPY_BITS: f64 = 48.0;
 
 #[derive(Clone, Debug)]
 pub struct Randomness([u8; 32]);
@@ -29,21 +34,174 @@ impl Randomness {
 }
 
 pub struct AliasGenerator {
-    rng: ChaCha20Rng,
+    /// `None` until the generator is seeded.
+    rng: Option<ChaCha20Rng>,
 }
 
 impl AliasGenerator {
     /// Creates a new `AliasGenerator`.
     pub fn new(randomness: Randomness) -> Self {
         Self {
-            rng: ChaCha20Rng::from_seed(randomness.get()),
+            rng: Some(ChaCha20Rng::from_seed(randomness.get())),
         }
     }
 
-    /// Returns the next unique alias from this `AliasGenerator`.
-    pub fn next(&mut self) -> String {
-        let adjective = ADJECTIVES.choose(&mut self.rng).unwrap();
-        let noun = NOUNS.choose(&mut self.rng).unwrap();
-        format!("{adjective}-{noun}")
+    /// Creates an `AliasGenerator` that generates no aliases until it is
+    /// seeded, as no secure randomness is available yet.
+    pub fn unseeded() -> Self {
+        Self { rng: None }
+    }
+
+    /// Replaces the seed, e.g. with randomness that wasn't available when
+    /// the generator was created.
+    pub fn reseed(&mut self, randomness: Randomness) {
+        self.rng = Some(ChaCha20Rng::from_seed(randomness.get()));
+    }
+
+    /// Returns the next alias from this `AliasGenerator`, or `None` if it
+    /// isn't seeded yet. It is up to the caller to check that the alias isn't
+    /// in use yet.
+    pub fn next(&mut self, format: &AliasFormat) -> Option<String> {
+        let rng = self.rng.as_mut()?;
+        Some(match *format {
+            AliasFormat::Words {
+                num_words,
+                suffix_digits,
+            } => {
+                // Adjectives followed by a noun, e.g. "brave-quiet-mountain-0421".
+                let mut words: Vec<String> = (1..num_words.max(1))
+                    .map(|_| ADJECTIVES.choose(rng).unwrap().to_string())
+                    .collect();
+                words.push(NOUNS.choose(rng).unwrap().to_string());
+                if suffix_digits > 0 {
+                    let suffix: String = (0..suffix_digits)
+                        .map(|_| char::from(b'0' + rng.gen_range(0, 10)))
+                        .collect();
+                    words.push(suffix);
+                }
+                words.join("-")
+            }
+            AliasFormat::Base58 { length } => (0..length.max(1))
+                .map(|_| char::from(*BASE58.choose(rng).unwrap()))
+                .collect(),
+        })
+    }
+}
+
+impl AliasFormat {
+    /// How many bits of entropy the aliases of this format have.
+    pub fn entropy_bits(&self) -> f64 {
+        match *self {
+            AliasFormat::Words {
+                num_words,
+                suffix_digits,
+            } => {
+                f64::from(num_words.max(1) - 1) * (ADJECTIVES.len() as f64).log2()
+                    + (NOUNS.len() as f64).log2()
+                    + f64::from(suffix_digits) * 10f64.log2()
+            }
+            AliasFormat::Base58 { length } => {
+                f64::from(length.max(1)) * (BASE58.len() as f64).log2()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        AliasError, AliasRetirement, AliasTarget, RetiredAlias, State, MAX_ALIAS_ATTEMPTS,
+    };
+
+    fn generator(seed: u8) -> AliasGenerator {
+        AliasGenerator::new(Randomness([seed; 32]))
+    }
+
+    #[test]
+    fn aliases_follow_the_format() {
+        let mut generator = generator(0);
+
+        let alias = generator
+            .next(&AliasFormat::Words {
+                num_words: 3,
+                suffix_digits: 4,
+            })
+            .unwrap();
+        let parts: Vec<&str> = alias.split('-').collect();
+        assert_eq!(parts.len(), 4);
+        assert!(ADJECTIVES.contains(&parts[0]) && ADJECTIVES.contains(&parts[1]));
+        assert!(NOUNS.contains(&parts[2]));
+        assert!(parts[3].len() == 4 && parts[3].chars().all(|c| c.is_ascii_digit()));
+
+        let alias = generator.next(&AliasFormat::Base58 { length: 22 }).unwrap();
+        assert_eq!(alias.len(), 22);
+        assert!(alias.bytes().all(|b| BASE58.contains(&b)));
+    }
+
+    #[test]
+    fn reseeding_changes_the_aliases() {
+        let format = AliasFormat::default();
+        let mut reseeded = generator(0);
+        reseeded.reseed(Randomness([1; 32]));
+
+        assert_eq!(reseeded.next(&format), generator(1).next(&format));
+        assert_ne!(generator(0).next(&format), generator(1).next(&format));
+    }
+
+    #[test]
+    fn aliases_in_use_are_skipped() {
+        let mut state = State {
+            alias_generator: generator(0),
+            ..Default::default()
+        };
+        let taken = generator(0).next(&state.config.alias_format).unwrap();
+        state.group_alias_index.insert(taken.clone(), 0);
+
+        let alias = state.generate_alias().unwrap();
+        assert_ne!(alias, taken);
+        assert!(!state.file_alias_index.contains_key(&alias));
+    }
+
+    #[test]
+    fn unseeded_generators_generate_no_aliases() {
+        let mut state = State {
+            alias_generator: AliasGenerator::unseeded(),
+            ..Default::default()
+        };
+        assert_eq!(state.generate_alias(), Err(AliasError::Unavailable));
+
+        state.alias_generator.reseed(Randomness([0; 32]));
+        assert!(state.generate_alias().is_ok());
+    }
+
+    #[test]
+    fn alias_generation_gives_up_when_aliases_collide() {
+        let mut state = State {
+            alias_generator: generator(0),
+            ..Default::default()
+        };
+        let mut taken = generator(0);
+        for _ in 0..MAX_ALIAS_ATTEMPTS {
+            let alias = taken.next(&state.config.alias_format).unwrap();
+            state.retired_aliases.insert(
+                alias,
+                RetiredAlias {
+                    target: AliasTarget::File(0),
+                    reason: AliasRetirement::Closed,
+                    retired_at: 0,
+                },
+            );
+        }
+
+        assert_eq!(state.generate_alias(), Err(AliasError::Unavailable));
+    }
+
+    #[test]
+    fn entropy_grows_with_the_format() {
+        let default_bits = AliasFormat::default().entropy_bits();
+        assert!(default_bits > MIN_ALIAS_ENTROPY_BITS && default_bits < 54.0);
+        assert!(AliasFormat::Base58 { length: 8 }.entropy_bits() < MIN_ALIAS_ENTROPY_BITS);
+        assert!(AliasFormat::Base58 { length: 9 }.entropy_bits() > MIN_ALIAS_ENTROPY_BITS);
     }
 }
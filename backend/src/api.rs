@@ -1,120 +1,198 @@
 // Add the new module
+mod admin;
 mod canister_management;
 mod delete_file;
 mod download_file;
+mod folder;
 mod get_alias_info;
 mod get_group_by_alias;
 mod get_request_groups;
 mod get_requests;
 mod get_users;
+mod invitations;
+mod key_rotation;
+mod list_files;
 mod multi_request;
+mod notifications;
 mod rename_file;
+mod request_alias;
 mod request_file;
 mod share_file;
+mod storage_quota;
 mod template;
 mod upload_file;
 mod upload_file_atomic;
+mod upload_session;
 mod user_info;
 
 // Re-export functions from the new module
+pub use admin::{
+    add_admin, apply_canister_args, authorize_admin, force_delete_file, get_storage_stats,
+    is_suspended, list_admins, list_all_users, remove_admin, set_config, suspend_user,
+    unsuspend_user,
+};
 pub use canister_management::{
     get_user_canisters, register_canister, rename_canister, unregister_canister_internal,
 };
 
-use crate::{FileContent, State, UploadFileContinueRequest};
+use crate::{get_time, sha256, FileContent, State, UploadFileContinueRequest, UploadFileError};
+use candid::Principal;
 pub use delete_file::delete_file;
 pub use download_file::download_file;
+pub use folder::{
+    create_folder, delete_folder, list_folder, move_file, move_folder, rename_folder,
+};
 pub use get_alias_info::get_alias_info;
 pub use get_group_by_alias::get_group_by_alias;
 pub use get_request_groups::get_request_groups;
 pub use get_requests::get_requests;
 pub use get_users::get_users;
+pub use invitations::{
+    accept_invitation, cancel_invitation, create_invitation, get_invitations, register_user,
+    share_file_by_username,
+};
+pub(crate) use key_rotation::holds_revoked_key;
+pub use key_rotation::{
+    commit_reencryption, get_files_to_reencrypt, migrate_to_per_file_keys, upload_reencrypted_chunk,
+};
+pub use list_files::{list_requests, list_shared_files};
 pub use multi_request::multi_request;
+pub use notifications::{dismiss_notification, get_notifications, send_reminders};
 pub use rename_file::rename_file;
-pub use request_file::request_file;
-pub use share_file::{get_shared_files, revoke_share, share_file};
+pub(crate) use request_alias::is_expired;
+pub use request_alias::{disable_alias, expire_aliases, prune_retired_aliases, regenerate_alias};
+pub use request_file::{request_file, request_file_with_options};
+pub use share_file::{
+    expire_shares, get_shared_files, revoke_share, revoke_shares, share_file,
+    share_file_with_permission, share_files,
+};
+use storage_quota::{charge_storage, check_quota};
+pub use storage_quota::{get_storage_usage, set_default_quota, set_user_quota};
 pub use template::{delete_template, get_template, get_user_templates}; // Added delete_template
+pub(crate) use upload_file::authorize_alias;
 pub use upload_file::upload_file;
-pub use upload_file_atomic::{upload_file_atomic, UploadFileAtomicRequest};
+pub use upload_file_atomic::{
+    expire_reserved_file_ids, reserve_file_id, upload_file_atomic, UploadFileAtomicRequest,
+};
+pub(crate) use upload_session::UPLOAD_SESSION_TTL;
+pub use upload_session::{
+    abort_upload, get_upload_progress, reclaim_expired_uploads, MAX_CHUNK_SIZE, MAX_NUM_CHUNKS,
+    MIN_CHUNK_SIZE,
+};
 pub use user_info::set_user_info;
 pub use user_info::username_exists;
 
-pub fn upload_file_continue(request: UploadFileContinueRequest, state: &mut State) {
-    // Update the file's contents.
+pub fn upload_file_continue(
+    request: UploadFileContinueRequest,
+    caller: Principal,
+    state: &mut State,
+) -> Result<(), UploadFileError> {
+    // Reject the chunk if it doesn't match the hash declared by the client.
+    if let Some(expected_hash) = &request.sha256 {
+        if *expected_hash != sha256(&request.contents) {
+            return Err(UploadFileError::HashMismatch);
+        }
+    }
+
     let file_id = request.file_id;
     let chunk_id = request.chunk_id;
 
-    let updated_file_data = match state.file_data.remove(&file_id) {
-        Some(mut file) => {
-            let updated_contents = match file.content {
-                FileContent::PartiallyUploaded {
-                    num_chunks,
-                    file_type,
-                    // owner_key,
-                    // Remove shared_keys as it's no longer needed
-                    // shared_keys,
-                } => {
-                    // Add the chunk to the partially uploaded file.
-                    assert!(chunk_id < num_chunks, "invalid chunk id");
-                    assert!(
-                        !state.file_contents.contains_key(&(file_id, chunk_id)),
-                        "chunk already uploaded"
-                    );
-
-                    // Add the chunk.
-                    state
-                        .file_contents
-                        .insert((file_id, chunk_id), request.contents);
-
-                    if state.num_chunks_uploaded(file_id) == num_chunks {
-                        // The file is complete. Assemble the file.
-                        FileContent::Uploaded {
-                            file_type,
-                            // owner_key,
-                            // Remove shared_keys as it's no longer needed
-                            // shared_keys,
-                            num_chunks,
-                        }
-                    } else {
-                        FileContent::PartiallyUploaded {
-                            num_chunks,
-                            file_type,
-                            // owner_key,
-                            // Remove shared_keys as it's no longer needed
-                            // shared_keys
-                        }
-                    }
-                }
-                f => panic!("expected a partially uploaded file. Found: {f:?}"),
-            };
+    let mut file = state
+        .file_data
+        .get(&file_id)
+        .ok_or(UploadFileError::NotRequested)?;
+    let (num_chunks, file_type) = match file.content {
+        FileContent::PartiallyUploaded {
+            num_chunks,
+            file_type,
+            // owner_key,
+            // Remove shared_keys as it's no longer needed
+            // shared_keys,
+        } => (num_chunks, file_type),
+        FileContent::Pending { .. } => return Err(UploadFileError::UploadNotStarted),
+        FileContent::Uploaded { .. } => return Err(UploadFileError::AlreadyUploaded),
+    };
 
-            file.content = updated_contents;
-            file
+    // Validate the chunk against the partially uploaded file.
+    if chunk_id >= num_chunks {
+        return Err(UploadFileError::ChunkOutOfRange);
+    }
+    if state.file_contents.contains_key(&(file_id, chunk_id)) {
+        return Err(UploadFileError::ChunkAlreadyUploaded);
+    }
+    let chunk_len = request.contents.len() as u64;
+    // Uploads that started before sessions were tracked have no declared sizes.
+    let session = state.upload_sessions.get(&file_id);
+    // Requested files are continued with the alias they were started with,
+    // other files only by their owner.
+    match session.as_ref().and_then(|session| session.alias.as_ref()) {
+        Some(file_alias) => {
+            let alias = request.alias.as_deref().unwrap_or_default();
+            authorize_alias(state, file_id, file_alias, alias)?;
         }
-        None => panic!("file doesn't exist"),
-    };
+        None if !state.is_owner(caller, file_id) => {
+            return Err(UploadFileError::PermissionError);
+        }
+        None => {}
+    }
+    if let Some(session) = &session {
+        if !session.fits_chunk(chunk_id, chunk_len) {
+            return Err(UploadFileError::InvalidChunkSize);
+        }
+    }
+    let requester = file.metadata.requester_principal;
+    check_quota(state, requester, chunk_len, 0)?;
 
-    // Insert the file back into the state.
-    assert_eq!(state.file_data.insert(file_id, updated_file_data), None);
+    // Keep the session alive while chunks keep arriving.
+    if let Some(mut session) = session {
+        session.expires_at = get_time() + UPLOAD_SESSION_TTL;
+        state.insert_upload_session(file_id, session);
+    }
+
+    // Add the chunk.
+    charge_storage(state, requester, chunk_len, 0);
+    state
+        .file_contents
+        .insert((file_id, chunk_id), request.contents);
+
+    if state.num_chunks_uploaded(file_id) == num_chunks {
+        // The file is complete. Assemble the file.
+        state.hash_file_contents(file_id, &mut file.metadata);
+        file.content = FileContent::Uploaded {
+            file_type,
+            // owner_key,
+            // Remove shared_keys as it's no longer needed
+            // shared_keys,
+            num_chunks,
+        };
+        state.file_data.insert(file_id, file);
+        state.remove_upload_session(file_id);
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::api::user_info::get_user_key;
-    use crate::{api::set_user_info, get_time, File, FileMetadata, User};
+    use crate::api::{reserve_file_id, user_info::get_user_key};
+    use crate::{api::set_user_info, get_time, root_hash, File, FileMetadata, KeyDerivation, User};
     use candid::Principal;
     use maplit::btreemap;
+    use std::collections::BTreeMap;
     // Not used as we aren't storing encrypted_keys while sharing anymore
     // use std::collections::BTreeMap;
 
+    const OWNER: Principal = Principal::from_slice(&[0, 1, 2]);
+    const CHUNK: usize = MIN_CHUNK_SIZE as usize;
+
     #[test]
     fn chunked_upload() {
         let mut state = State::default();
 
         set_user_info(
             &mut state,
-            Principal::anonymous(),
+            OWNER,
             User {
                 username: "John".to_string(),
                 public_key: vec![1, 2, 3],
@@ -123,28 +201,39 @@ mod test {
 
         // Upload the first chunk.
         let file_id = upload_file_atomic(
-            Principal::anonymous(),
+            OWNER,
             UploadFileAtomicRequest {
                 num_chunks: 3,
                 name: "file_name".to_string(),
-                content: vec![1, 2, 3],
+                content: vec![1; CHUNK],
                 // owner_key: vec![1, 2, 3],
                 file_type: "image/jpeg".to_string(),
+                total_size: Some(2 * MIN_CHUNK_SIZE + 2),
+                chunk_size: Some(MIN_CHUNK_SIZE),
+                file_id: reserve_file_id(&mut state, OWNER).unwrap().file_id,
             },
             &mut state,
-        );
+        )
+        .unwrap();
 
         // The first chunk is added to the state.
         assert_eq!(
-            state.file_data,
+            state.file_data.iter().collect::<BTreeMap<_, _>>(),
             btreemap! {
                 0 => File {
                     metadata: FileMetadata {
                         file_name: "file_name".to_string(),
-                        user_public_key: get_user_key(&state, Principal::anonymous()),
-                        requester_principal: Principal::anonymous(),
+                        user_public_key: get_user_key(&state, OWNER),
+                        requester_principal: OWNER,
                         requested_at: get_time(),
                         uploaded_at: Some(get_time()),
+                        folder_id: None,
+                        chunk_hashes: vec![],
+                        root_hash: None,
+                        key_derivation: KeyDerivation::PerFile { key_version: 0 },
+                        pending_key_version: None,
+                        alias_expires_at: None,
+                        due_at: None,
                     },
                     content: FileContent::PartiallyUploaded {
                         num_chunks: 3,
@@ -156,7 +245,7 @@ mod test {
                 }
             }
         );
-        assert_eq!(state.file_contents.get(&(file_id, 0)), Some(vec![1, 2, 3]));
+        assert_eq!(state.file_contents.get(&(file_id, 0)), Some(vec![1; CHUNK]));
         assert_eq!(state.num_chunks_uploaded(file_id), 1);
 
         // Upload the second chunk.
@@ -164,22 +253,33 @@ mod test {
             UploadFileContinueRequest {
                 file_id,
                 chunk_id: 1,
-                contents: vec![4, 5, 6],
+                contents: vec![4; CHUNK],
+                sha256: None,
+                alias: None,
             },
+            OWNER,
             &mut state,
-        );
+        )
+        .unwrap();
 
         // The second chunk is added to the state.
         assert_eq!(
-            state.file_data,
+            state.file_data.iter().collect::<BTreeMap<_, _>>(),
             btreemap! {
                 0 => File {
                     metadata: FileMetadata {
                         file_name: "file_name".to_string(),
-                        user_public_key: get_user_key(&state, Principal::anonymous()),
-                        requester_principal: Principal::anonymous(),
+                        user_public_key: get_user_key(&state, OWNER),
+                        requester_principal: OWNER,
                         requested_at: get_time(),
                         uploaded_at: Some(get_time()),
+                        folder_id: None,
+                        chunk_hashes: vec![],
+                        root_hash: None,
+                        key_derivation: KeyDerivation::PerFile { key_version: 0 },
+                        pending_key_version: None,
+                        alias_expires_at: None,
+                        due_at: None,
                     },
                     content: FileContent::PartiallyUploaded {
                         num_chunks: 3,
@@ -191,8 +291,8 @@ mod test {
                 }
             }
         );
-        assert_eq!(state.file_contents.get(&(file_id, 0)), Some(vec![1, 2, 3]));
-        assert_eq!(state.file_contents.get(&(file_id, 1)), Some(vec![4, 5, 6]));
+        assert_eq!(state.file_contents.get(&(file_id, 0)), Some(vec![1; CHUNK]));
+        assert_eq!(state.file_contents.get(&(file_id, 1)), Some(vec![4; CHUNK]));
         assert_eq!(state.num_chunks_uploaded(file_id), 2);
 
         // Upload the third and final chunk.
@@ -200,22 +300,34 @@ mod test {
             UploadFileContinueRequest {
                 file_id,
                 chunk_id: 2,
-                contents: vec![7, 8, 9, 10],
+                contents: vec![7, 8],
+                sha256: Some(sha256(&[7, 8])),
+                alias: None,
             },
+            OWNER,
             &mut state,
-        );
+        )
+        .unwrap();
 
-        // The last chunk is added to the state.
+        // The last chunk is added to the state, and the file's hashes are computed.
+        let chunk_hashes = vec![sha256(&[1; CHUNK]), sha256(&[4; CHUNK]), sha256(&[7, 8])];
         assert_eq!(
-            state.file_data,
+            state.file_data.iter().collect::<BTreeMap<_, _>>(),
             btreemap! {
                 0 => File {
                     metadata: FileMetadata {
                         file_name: "file_name".to_string(),
-                        user_public_key: get_user_key(&state, Principal::anonymous()),
-                        requester_principal: Principal::anonymous(),
+                        user_public_key: get_user_key(&state, OWNER),
+                        requester_principal: OWNER,
                         requested_at: get_time(),
                         uploaded_at: Some(get_time()),
+                        folder_id: None,
+                        chunk_hashes: chunk_hashes.clone(),
+                        root_hash: Some(root_hash(&chunk_hashes)),
+                        key_derivation: KeyDerivation::PerFile { key_version: 0 },
+                        pending_key_version: None,
+                        alias_expires_at: None,
+                        due_at: None,
                     },
                     content: FileContent::Uploaded {
                         file_type: "image/jpeg".to_string(),
@@ -227,12 +339,59 @@ mod test {
                 }
             }
         );
-        assert_eq!(state.file_contents.get(&(file_id, 0)), Some(vec![1, 2, 3]));
-        assert_eq!(state.file_contents.get(&(file_id, 1)), Some(vec![4, 5, 6]));
+        assert_eq!(state.file_contents.get(&(file_id, 0)), Some(vec![1; CHUNK]));
+        assert_eq!(state.file_contents.get(&(file_id, 1)), Some(vec![4; CHUNK]));
+        assert_eq!(state.file_contents.get(&(file_id, 2)), Some(vec![7, 8]));
+        assert_eq!(state.num_chunks_uploaded(file_id), 3);
+    }
+
+    #[test]
+    fn chunk_with_mismatched_hash_is_rejected() {
+        let mut state = State::default();
+        set_user_info(
+            &mut state,
+            OWNER,
+            User {
+                username: "John".to_string(),
+                public_key: vec![1, 2, 3],
+            },
+        );
+
+        let file_id = upload_file_atomic(
+            OWNER,
+            UploadFileAtomicRequest {
+                num_chunks: 2,
+                name: "file_name".to_string(),
+                content: vec![1; CHUNK],
+                file_type: "image/jpeg".to_string(),
+                total_size: Some(2 * MIN_CHUNK_SIZE),
+                chunk_size: Some(MIN_CHUNK_SIZE),
+                file_id: reserve_file_id(&mut state, OWNER).unwrap().file_id,
+            },
+            &mut state,
+        )
+        .unwrap();
+
         assert_eq!(
-            state.file_contents.get(&(file_id, 2)),
-            Some(vec![7, 8, 9, 10])
+            upload_file_continue(
+                UploadFileContinueRequest {
+                    file_id,
+                    chunk_id: 1,
+                    contents: vec![4; CHUNK],
+                    sha256: Some(sha256(&[4, 5, 7])),
+                    alias: None,
+                },
+                OWNER,
+                &mut state,
+            ),
+            Err(UploadFileError::HashMismatch)
         );
-        assert_eq!(state.num_chunks_uploaded(file_id), 3);
+
+        // The chunk isn't stored and the file is still partially uploaded.
+        assert_eq!(state.file_contents.get(&(file_id, 1)), None);
+        assert!(matches!(
+            state.file_data.get(&file_id).unwrap().content,
+            FileContent::PartiallyUploaded { .. }
+        ));
     }
 }
@@ -0,0 +1,426 @@
+use super::delete_file::remove_file;
+use crate::aliases::MIN_ALIAS_ENTROPY_BITS;
+use crate::{
+    is_controller, AdminError, AdminUserInfo, CanisterArgs, CanisterConfig, State, StorageStats,
+};
+use candid::Principal;
+
+/// Checks that the caller is an admin. The canister's controllers always are.
+pub fn authorize_admin(state: &State, caller: Principal) -> Result<(), AdminError> {
+    if is_controller(&caller) || state.admins.contains_key(&caller) {
+        Ok(())
+    } else {
+        Err(AdminError::PermissionError)
+    }
+}
+
+pub fn is_suspended(state: &State, user: Principal) -> bool {
+    state.suspended_users.contains_key(&user)
+}
+
+pub fn add_admin(state: &mut State, caller: Principal, user: Principal) -> Result<(), AdminError> {
+    authorize_admin(state, caller)?;
+    state.admins.insert(user, ());
+    Ok(())
+}
+
+pub fn remove_admin(
+    state: &mut State,
+    caller: Principal,
+    user: Principal,
+) -> Result<(), AdminError> {
+    authorize_admin(state, caller)?;
+    state
+        .admins
+        .remove(&user)
+        .map(|_| ())
+        .ok_or(AdminError::NotFound)
+}
+
+pub fn list_admins(state: &State, caller: Principal) -> Result<Vec<Principal>, AdminError> {
+    authorize_admin(state, caller)?;
+    Ok(state.admins.iter().map(|(admin, _)| admin).collect())
+}
+
+pub fn list_all_users(state: &State, caller: Principal) -> Result<Vec<AdminUserInfo>, AdminError> {
+    authorize_admin(state, caller)?;
+    Ok(state
+        .users
+        .iter()
+        .map(|(ic_principal, user)| AdminUserInfo {
+            ic_principal,
+            username: user.username,
+            usage: state.storage_usage.get(&ic_principal).unwrap_or_default(),
+            suspended: is_suspended(state, ic_principal),
+        })
+        .collect())
+}
+
+pub fn get_storage_stats(state: &State, caller: Principal) -> Result<StorageStats, AdminError> {
+    authorize_admin(state, caller)?;
+    Ok(StorageStats {
+        user_count: state.users.len(),
+        file_count: state.file_data.len(),
+        stored_bytes: state
+            .storage_usage
+            .iter()
+            .map(|(_, usage)| usage.bytes)
+            .sum(),
+        upload_session_count: state.upload_sessions.len(),
+    })
+}
+
+pub fn suspend_user(
+    state: &mut State,
+    caller: Principal,
+    user: Principal,
+) -> Result<(), AdminError> {
+    authorize_admin(state, caller)?;
+    state.suspended_users.insert(user, ());
+    Ok(())
+}
+
+pub fn unsuspend_user(
+    state: &mut State,
+    caller: Principal,
+    user: Principal,
+) -> Result<(), AdminError> {
+    authorize_admin(state, caller)?;
+    state
+        .suspended_users
+        .remove(&user)
+        .map(|_| ())
+        .ok_or(AdminError::NotFound)
+}
+
+/// Deletes any user's file.
+pub fn force_delete_file(
+    state: &mut State,
+    caller: Principal,
+    file_id: u64,
+) -> Result<(), AdminError> {
+    authorize_admin(state, caller)?;
+    let file = state.file_data.get(&file_id).ok_or(AdminError::NotFound)?;
+    remove_file(state, file.metadata.requester_principal, file_id);
+    Ok(())
+}
+
+pub fn set_config(
+    state: &mut State,
+    caller: Principal,
+    config: CanisterConfig,
+    force_key_change: bool,
+) -> Result<(), AdminError> {
+    authorize_admin(state, caller)?;
+    check_alias_format(&config)?;
+    check_key_change(state, &config, force_key_change)?;
+    state.config = config;
+    Ok(())
+}
+
+/// Applies the arguments the canister was installed or upgraded with.
+pub fn apply_canister_args(state: &mut State, args: CanisterArgs) -> Result<(), AdminError> {
+    let mut config = state.config.clone();
+    if let Some(profile) = args.vetkd_key_profile {
+        config.vetkd_key_name = profile.key_name().to_string();
+    }
+    if let Some(key_name) = args.vetkd_key_name {
+        config.vetkd_key_name = key_name;
+    }
+    if let Some(derivation_path) = args.vetkd_derivation_path {
+        config.vetkd_derivation_path = derivation_path;
+    }
+    if let Some(alias_format) = args.alias_format {
+        config.alias_format = alias_format;
+    }
+    check_alias_format(&config)?;
+    check_key_change(state, &config, args.force_key_change.unwrap_or(false))?;
+    state.config = config;
+    Ok(())
+}
+
+fn check_alias_format(config: &CanisterConfig) -> Result<(), AdminError> {
+    if config.alias_format.entropy_bits() < MIN_ALIAS_ENTROPY_BITS {
+        return Err(AdminError::WeakAliasFormat);
+    }
+    Ok(())
+}
+
+/// Stored files can only be decrypted with keys derived from the vetKD key
+/// they were encrypted with, so it is only switched when asked to explicitly.
+fn check_key_change(state: &State, config: &CanisterConfig, force: bool) -> Result<(), AdminError> {
+    if state.config.changes_vetkd_key(config) && !state.file_data.is_empty() && !force {
+        return Err(AdminError::KeyInUse);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::{reserve_file_id, set_user_info, upload_file_atomic, UploadFileAtomicRequest},
+        AliasFormat, StorageUsage, User, VetkdKeyProfile,
+    };
+
+    fn setup() -> (State, Principal, Principal) {
+        let mut state = State::default();
+        let admin = Principal::from_slice(&[0, 1, 2]);
+        let user = Principal::from_slice(&[0, 1, 1]);
+        state.admins.insert(admin, ());
+        set_user_info(
+            &mut state,
+            user,
+            User {
+                username: "John".to_string(),
+                public_key: vec![1, 2, 3],
+            },
+        );
+        (state, admin, user)
+    }
+
+    #[test]
+    fn non_admins_are_rejected() {
+        let (mut state, _, user) = setup();
+
+        assert_eq!(
+            authorize_admin(&state, user),
+            Err(AdminError::PermissionError)
+        );
+        assert_eq!(
+            add_admin(&mut state, user, user),
+            Err(AdminError::PermissionError)
+        );
+        assert_eq!(
+            list_all_users(&state, user),
+            Err(AdminError::PermissionError)
+        );
+        assert_eq!(
+            suspend_user(&mut state, user, user),
+            Err(AdminError::PermissionError)
+        );
+        assert_eq!(
+            set_config(&mut state, user, CanisterConfig::default(), false),
+            Err(AdminError::PermissionError)
+        );
+        assert!(!state.admins.contains_key(&user));
+        assert!(!is_suspended(&state, user));
+    }
+
+    #[test]
+    fn admins_can_manage_admins() {
+        let (mut state, admin, user) = setup();
+
+        add_admin(&mut state, admin, user).unwrap();
+        assert_eq!(list_admins(&state, user), Ok(vec![user, admin]));
+
+        remove_admin(&mut state, user, admin).unwrap();
+        assert_eq!(
+            authorize_admin(&state, admin),
+            Err(AdminError::PermissionError)
+        );
+        assert_eq!(
+            remove_admin(&mut state, user, admin),
+            Err(AdminError::NotFound)
+        );
+    }
+
+    #[test]
+    fn admins_can_suspend_users() {
+        let (mut state, admin, user) = setup();
+
+        suspend_user(&mut state, admin, user).unwrap();
+        assert!(is_suspended(&state, user));
+        assert_eq!(
+            list_all_users(&state, admin),
+            Ok(vec![AdminUserInfo {
+                ic_principal: user,
+                username: "John".to_string(),
+                usage: StorageUsage::default(),
+                suspended: true,
+            }])
+        );
+
+        unsuspend_user(&mut state, admin, user).unwrap();
+        assert!(!is_suspended(&state, user));
+        assert_eq!(
+            unsuspend_user(&mut state, admin, user),
+            Err(AdminError::NotFound)
+        );
+    }
+
+    fn upload(state: &mut State, user: Principal) -> u64 {
+        upload_file_atomic(
+            user,
+            UploadFileAtomicRequest {
+                name: "file_name".to_string(),
+                content: vec![1, 2, 3],
+                file_type: "image/jpeg".to_string(),
+                num_chunks: 1,
+                total_size: Some(3),
+                chunk_size: Some(3),
+                file_id: reserve_file_id(state, user).unwrap().file_id,
+            },
+            state,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn admins_can_delete_any_file() {
+        let (mut state, admin, user) = setup();
+        let file_id = upload(&mut state, user);
+        assert_eq!(
+            get_storage_stats(&state, admin),
+            Ok(StorageStats {
+                user_count: 1,
+                file_count: 1,
+                stored_bytes: 3,
+                upload_session_count: 0,
+            })
+        );
+
+        force_delete_file(&mut state, admin, file_id).unwrap();
+        assert!(state.file_data.is_empty());
+        assert!(state.owned_files(user).is_empty());
+        assert_eq!(
+            force_delete_file(&mut state, admin, file_id),
+            Err(AdminError::NotFound)
+        );
+        assert_eq!(get_storage_stats(&state, admin).unwrap().stored_bytes, 0);
+    }
+
+    #[test]
+    fn admins_can_change_the_config() {
+        let (mut state, admin, _) = setup();
+        let config = CanisterConfig {
+            vetkd_key_name: "key_1".to_string(),
+            vetkd_derivation_path: vec![b"canister".to_vec()],
+            alias_format: AliasFormat::default(),
+        };
+
+        set_config(&mut state, admin, config.clone(), false).unwrap();
+        assert_eq!(state.config, config);
+    }
+
+    #[test]
+    fn the_key_isnt_switched_on_stored_files() {
+        let (mut state, admin, user) = setup();
+        upload(&mut state, user);
+        let config = CanisterConfig {
+            vetkd_key_name: "key_1".to_string(),
+            vetkd_derivation_path: vec![],
+            alias_format: AliasFormat::default(),
+        };
+
+        assert_eq!(
+            set_config(&mut state, admin, config.clone(), false),
+            Err(AdminError::KeyInUse)
+        );
+        assert_eq!(
+            apply_canister_args(
+                &mut state,
+                CanisterArgs {
+                    vetkd_derivation_path: Some(vec![b"canister".to_vec()]),
+                    ..Default::default()
+                }
+            ),
+            Err(AdminError::KeyInUse)
+        );
+        assert_eq!(state.config, CanisterConfig::default());
+
+        // Re-applying the current key is fine.
+        apply_canister_args(
+            &mut state,
+            CanisterArgs {
+                vetkd_key_profile: Some(VetkdKeyProfile::Local),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        set_config(&mut state, admin, config.clone(), true).unwrap();
+        assert_eq!(state.config, config);
+    }
+
+    #[test]
+    fn canister_args_select_the_key() {
+        let mut state = State::default();
+
+        apply_canister_args(
+            &mut state,
+            CanisterArgs {
+                vetkd_key_profile: Some(VetkdKeyProfile::Mainnet),
+                vetkd_derivation_path: Some(vec![b"canister".to_vec()]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            state.config,
+            CanisterConfig {
+                vetkd_key_name: "key_1".to_string(),
+                vetkd_derivation_path: vec![b"canister".to_vec()],
+                alias_format: AliasFormat::default(),
+            }
+        );
+
+        // An explicit key name takes precedence over the profile.
+        apply_canister_args(
+            &mut state,
+            CanisterArgs {
+                vetkd_key_profile: Some(VetkdKeyProfile::Staging),
+                vetkd_key_name: Some("custom_key".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(state.config.vetkd_key_name, "custom_key");
+        assert_eq!(
+            state.config.vetkd_derivation_path,
+            vec![b"canister".to_vec()]
+        );
+    }
+
+    #[test]
+    fn weak_alias_formats_are_rejected() {
+        let (mut state, admin, _) = setup();
+        let weak = AliasFormat::Words {
+            num_words: 2,
+            suffix_digits: 0,
+        };
+
+        assert_eq!(
+            set_config(
+                &mut state,
+                admin,
+                CanisterConfig {
+                    alias_format: weak.clone(),
+                    ..Default::default()
+                },
+                false
+            ),
+            Err(AdminError::WeakAliasFormat)
+        );
+        assert_eq!(
+            apply_canister_args(
+                &mut state,
+                CanisterArgs {
+                    alias_format: Some(weak),
+                    ..Default::default()
+                }
+            ),
+            Err(AdminError::WeakAliasFormat)
+        );
+
+        let strong = AliasFormat::Base58 { length: 22 };
+        apply_canister_args(
+            &mut state,
+            CanisterArgs {
+                alias_format: Some(strong.clone()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(state.config.alias_format, strong);
+    }
+}
@@ -1,52 +1,78 @@
-use crate::{FileContent, FileSharingResponse, State};
+use super::key_rotation::discard_key_rotation;
+use super::storage_quota::release_storage;
+use crate::{FileContent, FileSharingResponse, SharePermission, State};
 use candid::Principal;
 
 pub fn delete_file(state: &mut State, caller: Principal, file_id: u64) -> FileSharingResponse {
-    // Check if the user owns this file
-    match state.file_owners.get_mut(&caller) {
-        Some(files) => {
-            if !files.contains(&file_id) {
-                return FileSharingResponse::PermissionError;
-            }
-
-            // Remove the file from the user's owned files
-            files.retain(|&id| id != file_id);
-
-            // If the file is pending, need to remove its alias
-            if let Some(file) = state.file_data.get(&file_id) {
-                if let FileContent::Pending { alias } = &file.content {
-                    state.file_alias_index.remove(alias);
-                }
-            }
-
-            // Remove file shares for all users who have access to this file
-            for (_, shared_files) in state.file_shares.iter_mut() {
-                shared_files.retain(|&id| id != file_id);
-            }
-
-            // Remove file chunks from storage
-            let file_data = state.file_data.get(&file_id).unwrap();
-            let num_chunks = match &file_data.content {
-                FileContent::Pending { .. } => 0,
-                FileContent::PartiallyUploaded { num_chunks, .. } => *num_chunks,
-                FileContent::Uploaded { num_chunks, .. } => *num_chunks,
-            };
-
-            for chunk_id in 0..num_chunks {
-                state.file_contents.remove(&(file_id, chunk_id));
-            }
-
-            // Finally remove the file data itself
-            state.file_data.remove(&file_id);
-
-            // Check if this file is part of any request group and remove it
-            for (_, group) in state.request_groups.iter_mut() {
-                group.files.retain(|&id| id != file_id);
-            }
-
-            FileSharingResponse::Ok
+    // Check if the user owns or manages this file
+    if !state.has_permission(caller, file_id, SharePermission::Manage) {
+        return FileSharingResponse::PermissionError;
+    }
+
+    // Managers delete the file for its owner.
+    let owner = state
+        .file_data
+        .get(&file_id)
+        .unwrap()
+        .metadata
+        .requester_principal;
+    remove_file(state, owner, file_id);
+    FileSharingResponse::Ok
+}
+
+/// Removes a file along with its contents, shares and group membership.
+pub(crate) fn remove_file(state: &mut State, owner: Principal, file_id: u64) {
+    // Remove the file from the user's owned files
+    state.file_owners.remove(&(owner, file_id));
+
+    // Remove the file data itself
+    let file_data = state.file_data.remove(&file_id).unwrap();
+    state
+        .indexes
+        .remove_file(owner, file_data.metadata.folder_id, file_id);
+
+    // If the file is pending, need to remove its alias
+    if let FileContent::Pending { alias } = &file_data.content {
+        state.file_alias_index.remove(alias);
+    }
+
+    // Remove file shares for all users who have access to this file
+    for sharee in state.indexes.sharees(file_id) {
+        if let Some(share) = state.file_shares.remove(&(sharee, file_id)) {
+            state.indexes.remove_share(file_id, sharee, &share);
+        }
+    }
+    let invitations: Vec<_> = state
+        .invitations
+        .range((file_id, 0)..=(file_id, u64::MAX))
+        .map(|(key, _)| key)
+        .collect();
+    for (file_id, invitation_id) in invitations {
+        state.remove_invitation(file_id, invitation_id);
+    }
+
+    // Remove file chunks from storage
+    let num_chunks = match &file_data.content {
+        FileContent::Pending { .. } => 0,
+        FileContent::PartiallyUploaded { num_chunks, .. } => *num_chunks,
+        FileContent::Uploaded { num_chunks, .. } => *num_chunks,
+    };
+
+    if num_chunks > 0 {
+        let stored_bytes = state.stored_bytes(file_id);
+        release_storage(state, owner, stored_bytes, 1);
+    }
+    state.remove_file_contents(file_id);
+    state.remove_upload_session(file_id);
+    discard_key_rotation(state, owner, file_id);
+
+    // If this file is part of a request group, remove it from the group
+    if let Some(group_id) = state.indexes.file_group.remove(&file_id) {
+        if let Some(mut group) = state.request_groups.get(&group_id) {
+            group.files.retain(|&id| id != file_id);
+            state.request_groups.insert(group_id, group);
         }
-        None => FileSharingResponse::PermissionError,
+        state.group_files.remove(&(group_id, file_id));
     }
 }
 
@@ -72,15 +98,18 @@ mod test {
         );
 
         // Request a file
-        request_file(Principal::anonymous(), "test_file", &mut state);
+        let alias = request_file(Principal::anonymous(), "test_file", &mut state).unwrap();
 
         // Upload the file
         upload_file(
             0,
+            alias,
             vec![1, 2, 3],
             "txt".to_string(),
             // vec![1, 2, 3],
             1,
+            Some(3),
+            Some(3),
             &mut state,
         )
         .unwrap();
@@ -94,11 +123,8 @@ mod test {
         // Verify result and file deletion
         assert_eq!(result, FileSharingResponse::Ok);
         assert!(!state.file_data.contains_key(&0));
-        assert!(!state
-            .file_owners
-            .get(&Principal::anonymous())
-            .unwrap()
-            .contains(&0));
+        assert!(!state.is_owner(Principal::anonymous(), 0));
+        state.check_indexes();
     }
 
     #[test]
@@ -123,7 +149,7 @@ mod test {
         );
 
         // Request a file as anonymous
-        request_file(Principal::anonymous(), "test_file", &mut state);
+        request_file(Principal::anonymous(), "test_file", &mut state).unwrap();
 
         // Try to delete as another user
         let result = delete_file(&mut state, Principal::from_slice(&[0, 1, 2]), 0);
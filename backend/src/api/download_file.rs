@@ -1,5 +1,6 @@
 // pub use crate::ceil_division;
-use crate::{FileContent, FileData, FileDownloadResponse, State};
+use super::key_rotation::holds_revoked_key;
+use crate::{FileContent, FileData, FileDownloadResponse, SharePermission, State};
 // use ic_cdk::export::candid::Principal;
 use candid::Principal;
 // use ic_cdk::println;
@@ -25,6 +26,12 @@ fn get_file_data(s: &State, file_id: u64, chunk_id: u64) -> FileDownloadResponse
             // No need to store an encrypted key
             // owner_key: owner_key.clone(),
             num_chunks: *num_chunks,
+            chunk_hash: this_file
+                .metadata
+                .chunk_hashes
+                .get(chunk_id as usize)
+                .cloned(),
+            root_hash: this_file.metadata.root_hash.clone(),
         }),
     }
 }
@@ -51,6 +58,12 @@ fn get_shared_file_data(
             file_type: file_type.clone(),
             // owner_key: shared_keys.get(&user).unwrap().clone(),
             num_chunks: *num_chunks,
+            chunk_hash: this_file
+                .metadata
+                .chunk_hashes
+                .get(chunk_id as usize)
+                .cloned(),
+            root_hash: this_file.metadata.root_hash.clone(),
         }),
     }
 }
@@ -60,33 +73,17 @@ pub fn download_file(
     chunk_id: u64,
     caller: Principal,
 ) -> FileDownloadResponse {
-    match s.file_owners.get(&caller) {
+    if s.is_owner(caller, file_id) {
         // This is the case where the files is owned by this user.
-        Some(files) => match files.contains(&file_id) {
-            true => get_file_data(s, file_id, chunk_id),
-            false => {
-                if is_file_shared_with_me(s, file_id, caller) {
-                    get_shared_file_data(s, file_id, chunk_id, caller)
-                } else {
-                    FileDownloadResponse::PermissionError
-                }
-            }
-        },
-        // But it could also be the case that the file is shared with this user.
-        None => {
-            if is_file_shared_with_me(s, file_id, caller) {
-                get_shared_file_data(s, file_id, chunk_id, caller)
-            } else {
-                FileDownloadResponse::PermissionError
-            }
-        }
-    }
-}
-
-fn is_file_shared_with_me(s: &State, file_id: u64, caller: Principal) -> bool {
-    match s.file_shares.get(&caller) {
-        None => false,
-        Some(arr) => arr.contains(&file_id),
+        get_file_data(s, file_id, chunk_id)
+    } else if s.has_permission(caller, file_id, SharePermission::Download)
+        && !holds_revoked_key(s, caller, file_id)
+    {
+        // But it could also be the case that the file is shared with this user,
+        // unless it still has the ciphertext of a share that was revoked from them.
+        get_shared_file_data(s, file_id, chunk_id, caller)
+    } else {
+        FileDownloadResponse::PermissionError
     }
 }
 
@@ -96,7 +93,7 @@ mod test {
     use crate::{
         api::{request_file, share_file},
         api::{set_user_info, upload_file},
-        User,
+        root_hash, sha256, User,
     };
     use candid::Principal;
 
@@ -112,7 +109,7 @@ mod test {
             },
         );
         // Request a file.
-        request_file(Principal::anonymous(), "request", &mut state);
+        request_file(Principal::anonymous(), "request", &mut state).unwrap();
 
         // try to download file as different user
         let result = download_file(&state, 0, 0, Principal::from_slice(&[0, 1, 2]));
@@ -142,13 +139,13 @@ mod test {
         );
 
         // Request a file.
-        request_file(Principal::anonymous(), "request", &mut state);
+        request_file(Principal::anonymous(), "request", &mut state).unwrap();
         // Request a file.
-        request_file(Principal::anonymous(), "request2", &mut state);
+        request_file(Principal::anonymous(), "request2", &mut state).unwrap();
         // Request a file.
-        request_file(Principal::from_slice(&[0, 1, 2]), "request3", &mut state);
+        request_file(Principal::from_slice(&[0, 1, 2]), "request3", &mut state).unwrap();
         // Request a file.
-        request_file(Principal::from_slice(&[0, 1, 2]), "request4", &mut state);
+        request_file(Principal::from_slice(&[0, 1, 2]), "request4", &mut state).unwrap();
 
         // try to download a file that belongs to another user
         let result = download_file(&state, 3, 0, Principal::anonymous());
@@ -168,7 +165,7 @@ mod test {
             },
         );
         // Request a file.
-        request_file(Principal::anonymous(), "request", &mut state);
+        request_file(Principal::anonymous(), "request", &mut state).unwrap();
 
         // try to download a file that was not uploaded yet
         let result = download_file(&state, 0, 0, Principal::anonymous());
@@ -190,17 +187,20 @@ mod test {
         );
 
         // Request a file.
-        request_file(Principal::anonymous(), "request", &mut state);
+        let alias = request_file(Principal::anonymous(), "request", &mut state).unwrap();
 
         // Upload the file, which we assume to have a file ID of zero.
         let file_id = 0;
         let _alias = upload_file(
             file_id,
+            alias,
             vec![1, 2, 3],
             "jpeg".to_string(),
             // Removed owner_key parameter as it's not needed for vetkd
             // vec![1, 2, 3],
             1,
+            Some(3),
+            Some(3),
             &mut state,
         );
 
@@ -211,7 +211,9 @@ mod test {
                 file_type: "jpeg".to_string(),
                 // Each user will use their own principal to derive the key with VetKD
                 // owner_key: vec![1, 2, 3],
-                num_chunks: 1
+                num_chunks: 1,
+                chunk_hash: Some(sha256(&[1, 2, 3])),
+                root_hash: Some(root_hash(&[sha256(&[1, 2, 3])])),
             })
         );
     }
@@ -239,16 +241,19 @@ mod test {
         );
 
         // Request a file.
-        request_file(Principal::anonymous(), "request", &mut state);
+        let alias = request_file(Principal::anonymous(), "request", &mut state).unwrap();
 
         // Upload the file, which we assume to have a file ID of zero.
         let _alias = upload_file(
             0,
+            alias,
             vec![1, 2, 3],
             "jpeg".to_string(),
             // Removed owner_key parameter as it's not needed for vetkd
             // vec![1, 2, 3],
             1,
+            Some(3),
+            Some(3),
             &mut state,
         );
 
@@ -269,7 +274,9 @@ mod test {
                 file_type: "jpeg".to_string(),
                 // Each user will use their own principal to derive the key with VetKD
                 // owner_key: vec![10, 11, 12],
-                num_chunks: 1
+                num_chunks: 1,
+                chunk_hash: Some(sha256(&[1, 2, 3])),
+                root_hash: Some(root_hash(&[sha256(&[1, 2, 3])])),
             })
         )
     }
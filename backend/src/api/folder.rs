@@ -0,0 +1,551 @@
+use crate::{get_time, Folder, FolderContents, FolderCursor, FolderError, State};
+use candid::Principal;
+
+use super::get_requests::get_public_file_metadata;
+use super::list_files::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+
+pub fn create_folder(
+    state: &mut State,
+    caller: Principal,
+    name: String,
+    parent_id: Option<u64>,
+) -> Result<u64, FolderError> {
+    check_parent(state, caller, parent_id)?;
+    if sibling_name_exists(state, caller, parent_id, &name, None) {
+        return Err(FolderError::NameExists);
+    }
+
+    let folder_id = state.generate_folder_id();
+    state.folders.insert(
+        folder_id,
+        Folder {
+            folder_id,
+            name,
+            parent_id,
+            owner: caller,
+            created_at: get_time(),
+        },
+    );
+    state.indexes.add_folder(caller, parent_id, folder_id);
+
+    Ok(folder_id)
+}
+
+pub fn rename_folder(
+    state: &mut State,
+    caller: Principal,
+    folder_id: u64,
+    new_name: String,
+) -> Result<(), FolderError> {
+    let mut folder = get_owned_folder(state, caller, folder_id)?;
+    if sibling_name_exists(state, caller, folder.parent_id, &new_name, Some(folder_id)) {
+        return Err(FolderError::NameExists);
+    }
+
+    folder.name = new_name;
+    state.folders.insert(folder_id, folder);
+    Ok(())
+}
+
+pub fn move_folder(
+    state: &mut State,
+    caller: Principal,
+    folder_id: u64,
+    new_parent_id: Option<u64>,
+) -> Result<(), FolderError> {
+    let mut folder = get_owned_folder(state, caller, folder_id)?;
+    check_parent(state, caller, new_parent_id)?;
+
+    // A folder cannot be moved into itself or into one of its descendants.
+    let mut ancestor = new_parent_id;
+    while let Some(id) = ancestor {
+        if id == folder_id {
+            return Err(FolderError::InvalidParent);
+        }
+        ancestor = state.folders.get(&id).and_then(|folder| folder.parent_id);
+    }
+
+    if sibling_name_exists(state, caller, new_parent_id, &folder.name, Some(folder_id)) {
+        return Err(FolderError::NameExists);
+    }
+
+    state
+        .indexes
+        .remove_folder(caller, folder.parent_id, folder_id);
+    state.indexes.add_folder(caller, new_parent_id, folder_id);
+    folder.parent_id = new_parent_id;
+    state.folders.insert(folder_id, folder);
+    Ok(())
+}
+
+/// Deletes a folder. Only empty folders can be deleted.
+pub fn delete_folder(
+    state: &mut State,
+    caller: Principal,
+    folder_id: u64,
+) -> Result<(), FolderError> {
+    let folder = get_owned_folder(state, caller, folder_id)?;
+
+    let has_subfolders = state
+        .indexes
+        .subfolders(caller, Some(folder_id), None)
+        .next()
+        .is_some();
+    let has_files = state
+        .indexes
+        .folder_files(caller, Some(folder_id), None)
+        .next()
+        .is_some();
+    if has_subfolders || has_files {
+        return Err(FolderError::NotEmpty);
+    }
+
+    state.folders.remove(&folder_id);
+    state
+        .indexes
+        .remove_folder(caller, folder.parent_id, folder_id);
+    Ok(())
+}
+
+/// Moves a file into the given folder, or to the root if no folder is given.
+///
+/// Only the location of the file changes, so a file that is part of a request
+/// group stays linked to its group.
+pub fn move_file(
+    state: &mut State,
+    caller: Principal,
+    file_id: u64,
+    folder_id: Option<u64>,
+) -> Result<(), FolderError> {
+    let mut file = state.file_data.get(&file_id).ok_or(FolderError::NotFound)?;
+    if !state.is_owner(caller, file_id) {
+        return Err(FolderError::PermissionError);
+    }
+    check_parent(state, caller, folder_id)?;
+    if file_name_exists(
+        state,
+        caller,
+        folder_id,
+        &file.metadata.file_name,
+        Some(file_id),
+    ) {
+        return Err(FolderError::NameExists);
+    }
+
+    state
+        .indexes
+        .remove_file(caller, file.metadata.folder_id, file_id);
+    state.indexes.add_file(caller, folder_id, file_id);
+    file.metadata.folder_id = folder_id;
+    state.file_data.insert(file_id, file);
+    Ok(())
+}
+
+/// Returns a page of the folders and files directly inside the given folder,
+/// or inside the root if no folder is given. Folders come first, then files,
+/// each ordered by ID.
+pub fn list_folder(
+    state: &State,
+    caller: Principal,
+    folder_id: Option<u64>,
+    cursor: Option<FolderCursor>,
+    limit: Option<u64>,
+) -> Result<FolderContents, FolderError> {
+    check_parent(state, caller, folder_id)?;
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as usize;
+
+    // Folders are skipped once the cursor has moved on to files.
+    let (folders_after, files_after) = match cursor {
+        None => (Some(None), None),
+        Some(FolderCursor::Folder(id)) => (Some(Some(id)), None),
+        Some(FolderCursor::File(id)) => (None, Some(id)),
+    };
+    let mut entries = folders_after
+        .into_iter()
+        .flat_map(|after| state.indexes.subfolders(caller, folder_id, after))
+        .map(FolderCursor::Folder)
+        .chain(
+            state
+                .indexes
+                .folder_files(caller, folder_id, files_after)
+                .map(FolderCursor::File),
+        );
+    let page: Vec<FolderCursor> = entries.by_ref().take(limit).collect();
+    let next_cursor = entries.next().and(page.last().copied());
+
+    let mut folders = vec![];
+    let mut files = vec![];
+    for entry in page {
+        match entry {
+            FolderCursor::Folder(id) => folders.extend(state.folders.get(&id)),
+            FolderCursor::File(id) => files.push(get_public_file_metadata(state, id)),
+        }
+    }
+
+    Ok(FolderContents {
+        folder_id,
+        folders,
+        files,
+        next_cursor,
+    })
+}
+
+fn get_owned_folder(
+    state: &State,
+    caller: Principal,
+    folder_id: u64,
+) -> Result<Folder, FolderError> {
+    match state.folders.get(&folder_id) {
+        None => Err(FolderError::NotFound),
+        Some(folder) if folder.owner != caller => Err(FolderError::PermissionError),
+        Some(folder) => Ok(folder),
+    }
+}
+
+/// Checks that the caller may place items into the given folder. The root is
+/// always allowed.
+fn check_parent(
+    state: &State,
+    caller: Principal,
+    parent_id: Option<u64>,
+) -> Result<(), FolderError> {
+    match parent_id {
+        None => Ok(()),
+        Some(id) => get_owned_folder(state, caller, id).map(|_| ()),
+    }
+}
+
+fn sibling_name_exists(
+    state: &State,
+    caller: Principal,
+    parent_id: Option<u64>,
+    name: &str,
+    exclude: Option<u64>,
+) -> bool {
+    state
+        .indexes
+        .subfolders(caller, parent_id, None)
+        .filter(|&folder_id| Some(folder_id) != exclude)
+        .any(|folder_id| {
+            state
+                .folders
+                .get(&folder_id)
+                .is_some_and(|folder| folder.name == name)
+        })
+}
+
+/// Whether another of the owner's files in the folder has the name. Files at
+/// the root aren't required to have unique names, as requests often share them.
+pub(super) fn file_name_exists(
+    state: &State,
+    owner: Principal,
+    folder_id: Option<u64>,
+    name: &str,
+    exclude: Option<u64>,
+) -> bool {
+    folder_id.is_some()
+        && state
+            .indexes
+            .folder_files(owner, folder_id, None)
+            .filter(|&file_id| Some(file_id) != exclude)
+            .any(|file_id| {
+                state
+                    .file_data
+                    .get(&file_id)
+                    .is_some_and(|file| file.metadata.file_name == name)
+            })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::{delete_file, multi_request, request_file, set_user_info},
+        FileSharingResponse, MultiRequestInput, User,
+    };
+
+    fn setup() -> State {
+        let mut state = State::default();
+        set_user_info(
+            &mut state,
+            Principal::anonymous(),
+            User {
+                username: "John".to_string(),
+                public_key: vec![1, 2, 3],
+            },
+        );
+        state
+    }
+
+    #[test]
+    fn create_and_list_folders() {
+        let mut state = setup();
+
+        let docs =
+            create_folder(&mut state, Principal::anonymous(), "docs".to_string(), None).unwrap();
+        let taxes = create_folder(
+            &mut state,
+            Principal::anonymous(),
+            "taxes".to_string(),
+            Some(docs),
+        )
+        .unwrap();
+        request_file(Principal::anonymous(), "request", &mut state).unwrap();
+        request_file(Principal::anonymous(), "request2", &mut state).unwrap();
+        move_file(&mut state, Principal::anonymous(), 1, Some(docs)).unwrap();
+
+        let root = list_folder(&state, Principal::anonymous(), None, None, None).unwrap();
+        assert_eq!(
+            root.folders.iter().map(|f| f.folder_id).collect::<Vec<_>>(),
+            vec![docs]
+        );
+        assert_eq!(
+            root.files.iter().map(|f| f.file_id).collect::<Vec<_>>(),
+            vec![0]
+        );
+
+        let contents = list_folder(&state, Principal::anonymous(), Some(docs), None, None).unwrap();
+        assert_eq!(
+            contents
+                .folders
+                .iter()
+                .map(|f| f.folder_id)
+                .collect::<Vec<_>>(),
+            vec![taxes]
+        );
+        assert_eq!(
+            contents.files.iter().map(|f| f.file_id).collect::<Vec<_>>(),
+            vec![1]
+        );
+        assert_eq!(contents.files[0].folder_id, Some(docs));
+    }
+
+    #[test]
+    fn sibling_folder_names_are_unique() {
+        let mut state = setup();
+
+        let docs =
+            create_folder(&mut state, Principal::anonymous(), "docs".to_string(), None).unwrap();
+        assert_eq!(
+            create_folder(&mut state, Principal::anonymous(), "docs".to_string(), None),
+            Err(FolderError::NameExists)
+        );
+
+        // The same name is fine in a different folder.
+        let nested = create_folder(
+            &mut state,
+            Principal::anonymous(),
+            "docs".to_string(),
+            Some(docs),
+        )
+        .unwrap();
+        assert_eq!(
+            move_folder(&mut state, Principal::anonymous(), nested, None),
+            Err(FolderError::NameExists)
+        );
+        assert_eq!(
+            rename_folder(
+                &mut state,
+                Principal::anonymous(),
+                nested,
+                "other".to_string()
+            ),
+            Ok(())
+        );
+        assert_eq!(state.folders.get(&nested).unwrap().name, "other");
+    }
+
+    #[test]
+    fn folder_cannot_move_into_its_descendant() {
+        let mut state = setup();
+
+        let a = create_folder(&mut state, Principal::anonymous(), "a".to_string(), None).unwrap();
+        let b =
+            create_folder(&mut state, Principal::anonymous(), "b".to_string(), Some(a)).unwrap();
+        let c =
+            create_folder(&mut state, Principal::anonymous(), "c".to_string(), Some(b)).unwrap();
+
+        assert_eq!(
+            move_folder(&mut state, Principal::anonymous(), a, Some(c)),
+            Err(FolderError::InvalidParent)
+        );
+        assert_eq!(
+            move_folder(&mut state, Principal::anonymous(), a, Some(a)),
+            Err(FolderError::InvalidParent)
+        );
+        assert_eq!(
+            move_folder(&mut state, Principal::anonymous(), c, None),
+            Ok(())
+        );
+        assert_eq!(state.folders.get(&c).unwrap().parent_id, None);
+    }
+
+    #[test]
+    fn only_empty_folders_can_be_deleted() {
+        let mut state = setup();
+
+        let docs =
+            create_folder(&mut state, Principal::anonymous(), "docs".to_string(), None).unwrap();
+        request_file(Principal::anonymous(), "request", &mut state).unwrap();
+        move_file(&mut state, Principal::anonymous(), 0, Some(docs)).unwrap();
+
+        assert_eq!(
+            delete_folder(&mut state, Principal::anonymous(), docs),
+            Err(FolderError::NotEmpty)
+        );
+
+        // Deleting the file empties the folder.
+        assert_eq!(
+            delete_file(&mut state, Principal::anonymous(), 0),
+            FileSharingResponse::Ok
+        );
+        assert_eq!(
+            delete_folder(&mut state, Principal::anonymous(), docs),
+            Ok(())
+        );
+        assert!(state.folders.is_empty());
+    }
+
+    #[test]
+    fn moved_group_files_stay_in_their_group() {
+        let mut state = setup();
+
+        let response = multi_request(
+            Principal::anonymous(),
+            MultiRequestInput {
+                group_name: "group".to_string(),
+                file_names: vec!["a".to_string(), "b".to_string()],
+                save_as_template: false,
+                expires_at: None,
+                due_at: None,
+            },
+            &mut state,
+        )
+        .unwrap();
+        let docs =
+            create_folder(&mut state, Principal::anonymous(), "docs".to_string(), None).unwrap();
+        move_file(&mut state, Principal::anonymous(), 0, Some(docs)).unwrap();
+        move_file(&mut state, Principal::anonymous(), 1, Some(docs)).unwrap();
+
+        let contents = list_folder(&state, Principal::anonymous(), Some(docs), None, None).unwrap();
+        assert_eq!(contents.files.len(), 2);
+        for file in contents.files {
+            assert_eq!(file.group_name, "group");
+            assert_eq!(file.group_alias, Some(response.group_alias.clone()));
+        }
+        assert_eq!(
+            state.request_groups.get(&response.group_id).unwrap().files,
+            vec![0, 1]
+        );
+    }
+
+    #[test]
+    fn folders_are_private_to_their_owner() {
+        let mut state = setup();
+        let other = Principal::from_slice(&[0, 1, 2]);
+
+        let docs =
+            create_folder(&mut state, Principal::anonymous(), "docs".to_string(), None).unwrap();
+        request_file(Principal::anonymous(), "request", &mut state).unwrap();
+
+        assert_eq!(
+            list_folder(&state, other, Some(docs), None, None),
+            Err(FolderError::PermissionError)
+        );
+        assert_eq!(
+            create_folder(&mut state, other, "mine".to_string(), Some(docs)),
+            Err(FolderError::PermissionError)
+        );
+        assert_eq!(
+            move_file(&mut state, other, 0, None),
+            Err(FolderError::PermissionError)
+        );
+        assert_eq!(
+            move_file(&mut state, Principal::anonymous(), 7, None),
+            Err(FolderError::NotFound)
+        );
+        assert_eq!(
+            delete_folder(&mut state, other, docs),
+            Err(FolderError::PermissionError)
+        );
+    }
+
+    #[test]
+    fn folder_listings_are_paginated() {
+        let mut state = setup();
+        let caller = Principal::anonymous();
+
+        let docs = create_folder(&mut state, caller, "docs".to_string(), None).unwrap();
+        let nested = create_folder(&mut state, caller, "a".to_string(), Some(docs)).unwrap();
+        let other = create_folder(&mut state, caller, "b".to_string(), Some(docs)).unwrap();
+        for (file_id, name) in ["a", "b", "c"].into_iter().enumerate() {
+            request_file(caller, name, &mut state).unwrap();
+            move_file(&mut state, caller, file_id as u64, Some(docs)).unwrap();
+        }
+        state.check_indexes();
+
+        let mut cursor = None;
+        let mut pages = vec![];
+        loop {
+            let page = list_folder(&state, caller, Some(docs), cursor, Some(2)).unwrap();
+            pages.push((
+                page.folders
+                    .iter()
+                    .map(|folder| folder.folder_id)
+                    .collect::<Vec<_>>(),
+                page.files
+                    .iter()
+                    .map(|file| file.file_id)
+                    .collect::<Vec<_>>(),
+            ));
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(
+            pages,
+            vec![
+                (vec![nested, other], vec![]),
+                (vec![], vec![0, 1]),
+                (vec![], vec![2]),
+            ]
+        );
+
+        // The root only lists what is directly inside it.
+        let root = list_folder(&state, caller, None, None, None).unwrap();
+        assert_eq!(root.folders.len(), 1);
+        assert!(root.files.is_empty());
+        assert_eq!(root.next_cursor, None);
+    }
+
+    #[test]
+    fn file_names_are_unique_within_a_folder() {
+        use crate::api::rename_file;
+
+        let mut state = setup();
+        let caller = Principal::anonymous();
+
+        let docs = create_folder(&mut state, caller, "docs".to_string(), None).unwrap();
+        request_file(caller, "report", &mut state).unwrap();
+        request_file(caller, "report", &mut state).unwrap();
+        request_file(caller, "other", &mut state).unwrap();
+
+        move_file(&mut state, caller, 0, Some(docs)).unwrap();
+        assert_eq!(
+            move_file(&mut state, caller, 1, Some(docs)),
+            Err(FolderError::NameExists)
+        );
+        move_file(&mut state, caller, 2, Some(docs)).unwrap();
+        assert_eq!(
+            rename_file(&mut state, caller, 2, "report".to_string()),
+            FileSharingResponse::NameExists
+        );
+        // Names only need to be unique within a folder.
+        assert_eq!(
+            rename_file(&mut state, caller, 1, "other".to_string()),
+            FileSharingResponse::Ok
+        );
+        state.check_indexes();
+    }
+}
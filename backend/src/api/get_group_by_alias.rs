@@ -1,23 +1,24 @@
-use crate::{FileInfo, GetAliasInfoError, GroupInfo, PublicUser, State};
+use super::request_alias::{is_expired, unknown_alias_error};
+use crate::{get_time, FileInfo, GetAliasInfoError, GroupInfo, PublicUser, State};
 // use ic_cdk::println;
 
 pub fn get_group_by_alias(state: &State, alias: String) -> Result<GroupInfo, GetAliasInfoError> {
     let group_id = state
         .group_alias_index
         .get(&alias)
-        .ok_or(GetAliasInfoError::NotFound)?;
+        .ok_or_else(|| unknown_alias_error(state, &alias))?;
     // println!("group_id: {:?}", group_id);
 
     let group = state
         .request_groups
-        .get(group_id)
+        .get(&group_id)
         .ok_or(GetAliasInfoError::NotFound)?;
     // println!("group: {:?}", group);
+    if is_expired(group.expires_at, get_time()) {
+        return Err(GetAliasInfoError::Expired);
+    }
 
-    let files = state
-        .group_files
-        .get(group_id)
-        .ok_or(GetAliasInfoError::NotFound)?;
+    let files = state.group_file_ids(group_id);
     // println!("files: {:?}", files);
 
     let requester = state
@@ -27,22 +28,22 @@ pub fn get_group_by_alias(state: &State, alias: String) -> Result<GroupInfo, Get
     // println!("requester: {:?}", files);
 
     Ok(GroupInfo {
-        group_id: *group_id,
-        group_name: group.name.clone(),
+        group_id,
+        group_name: group.name,
         files: files
-            .iter()
-            .map(|&file_id| {
+            .into_iter()
+            .map(|file_id| {
                 let file = state.file_data.get(&file_id).unwrap();
                 FileInfo {
                     file_id,
-                    file_name: file.metadata.file_name.clone(),
+                    file_name: file.metadata.file_name,
                     alias: alias.clone(), // Files still have individual aliases
                 }
             })
             .collect(),
         requester: PublicUser {
-            username: requester.username.clone(),
-            public_key: requester.public_key.clone(),
+            username: requester.username,
+            public_key: requester.public_key,
             ic_principal: group.requester,
         },
     })
@@ -1,71 +1,72 @@
-use crate::{FileContent, FileStatus, PublicFileMetadata, PublicUser, State};
+use crate::{get_time, FileContent, FileStatus, PublicFileMetadata, PublicSharee, State};
 use candid::Principal;
 
 pub fn get_requests(state: &State, caller: Principal) -> Vec<PublicFileMetadata> {
-    match state.file_owners.get(&caller) {
-        None => vec![],
-        Some(file_ids) => file_ids
-            .iter()
-            .map(|file_id| {
-                let file = state.file_data.get(file_id).expect("file must exist");
+    state
+        .owned_files(caller)
+        .into_iter()
+        .map(|file_id| get_public_file_metadata(state, file_id))
+        .collect()
+}
 
-                // Find group name for this file
-                let group_name = state
-                    .request_groups
-                    .values()
-                    .find(|group| group.files.contains(file_id))
-                    .map(|group| group.name.clone())
-                    .unwrap_or_default();
+/// Builds the metadata of a file as it is returned to the frontend.
+pub(crate) fn get_public_file_metadata(state: &State, file_id: u64) -> PublicFileMetadata {
+    let file = state.file_data.get(&file_id).expect("file must exist");
 
-                // Find group alias for this file
-                let group_alias = state
-                    .request_groups
-                    .values()
-                    .find(|group| group.files.contains(file_id))
-                    .and_then(|group| {
-                        state
-                            .group_alias_index
-                            .iter()
-                            .find(|(_a, id)| **id == group.group_id)
-                            .map(|(alias, _)| alias.clone())
-                    });
+    let group_id = state.indexes.file_group.get(&file_id);
+    let group_name = group_id
+        .and_then(|group_id| state.request_groups.get(&group_id))
+        .map(|group| group.name)
+        .unwrap_or_default();
+    let group_alias = group_id.and_then(|group_id| state.indexes.group_alias.get(&group_id));
 
-                PublicFileMetadata {
-                    file_id: *file_id,
-                    file_name: file.metadata.file_name.clone(),
-                    group_name,
-                    group_alias,
-                    shared_with: get_allowed_users(state, *file_id),
-                    file_status: get_file_status(state, *file_id),
-                }
-            })
-            .collect(),
+    PublicFileMetadata {
+        file_id,
+        file_name: file.metadata.file_name,
+        group_name,
+        group_alias,
+        folder_id: file.metadata.folder_id,
+        root_hash: file.metadata.root_hash,
+        due_at: file.metadata.due_at,
+        shared_with: get_allowed_users(state, file_id),
+        file_status: get_file_status(state, file_id),
     }
 }
 
-pub fn get_allowed_users(state: &State, file_id: u64) -> Vec<PublicUser> {
+pub fn get_allowed_users(state: &State, file_id: u64) -> Vec<PublicSharee> {
     state
-        .file_shares
-        .iter()
-        .filter(|element| element.1.contains(&file_id))
-        .map(|(user_principal, _file_vector)| {
-            let user = state.users.get(user_principal).unwrap().clone();
-            PublicUser {
+        .indexes
+        .sharees(file_id)
+        .into_iter()
+        // Files could be shared with unregistered principals before sharing checked for it.
+        .filter_map(|user_principal| {
+            let user = state.users.get(&user_principal)?;
+            let share = state.file_shares.get(&(user_principal, file_id)).unwrap();
+            Some(PublicSharee {
                 username: user.username,
                 public_key: user.public_key,
-                ic_principal: *user_principal,
-            }
+                ic_principal: user_principal,
+                permission: share.permission,
+                expires_at: share.expires_at,
+            })
         })
         .collect()
 }
 
 pub fn get_file_status(state: &State, file_id: u64) -> FileStatus {
     // unwrap is safe, we know the file exists
-    let file = &state.file_data.get(&file_id).unwrap();
+    let file = state.file_data.get(&file_id).unwrap();
     match &file.content {
-        FileContent::Pending { alias } => FileStatus::Pending {
-            alias: alias.clone(),
-            requested_at: file.metadata.requested_at,
+        FileContent::Pending { alias } => match file.metadata.due_at {
+            Some(due_at) if due_at <= get_time() => FileStatus::Overdue {
+                alias: alias.clone(),
+                requested_at: file.metadata.requested_at,
+                due_at,
+            },
+            _ => FileStatus::Pending {
+                alias: alias.clone(),
+                requested_at: file.metadata.requested_at,
+            },
         },
         FileContent::PartiallyUploaded { .. } => FileStatus::PartiallyUploaded,
         FileContent::Uploaded { .. } => FileStatus::Uploaded {
@@ -98,13 +99,13 @@ mod test {
         );
 
         // Request a file.
-        let alias1 = request_file(Principal::anonymous(), "request", &mut state);
+        let alias1 = request_file(Principal::anonymous(), "request", &mut state).unwrap();
         // Request a file.
-        let alias2 = request_file(Principal::anonymous(), "request2", &mut state);
+        let alias2 = request_file(Principal::anonymous(), "request2", &mut state).unwrap();
         // Request a file.
-        let alias3 = request_file(Principal::anonymous(), "request3", &mut state);
+        let alias3 = request_file(Principal::anonymous(), "request3", &mut state).unwrap();
         // Request a file.
-        let alias4 = request_file(Principal::anonymous(), "request4", &mut state);
+        let alias4 = request_file(Principal::anonymous(), "request4", &mut state).unwrap();
 
         // We assume here that the file id generation starts at 0 and continues
         // incrementing the id while files are requested.
@@ -116,6 +117,9 @@ mod test {
                     file_name: "request".to_string(),
                     group_name: "group1".to_string(),
                     group_alias: Some("group_alias1".to_string()),
+                    folder_id: None,
+                    root_hash: None,
+                    due_at: None,
                     file_status: FileStatus::Pending {
                         alias: alias1,
                         requested_at: get_time()
@@ -127,6 +131,9 @@ mod test {
                     file_name: "request2".to_string(),
                     group_name: "group2".to_string(),
                     group_alias: Some("group_alias2".to_string()),
+                    folder_id: None,
+                    root_hash: None,
+                    due_at: None,
                     file_status: FileStatus::Pending {
                         alias: alias2,
                         requested_at: get_time()
@@ -138,6 +145,9 @@ mod test {
                     file_name: "request3".to_string(),
                     group_name: "group3".to_string(),
                     group_alias: Some("group_alias3".to_string()),
+                    folder_id: None,
+                    root_hash: None,
+                    due_at: None,
                     file_status: FileStatus::Pending {
                         alias: alias3,
                         requested_at: get_time()
@@ -149,6 +159,9 @@ mod test {
                     file_name: "request4".to_string(),
                     group_name: "group4".to_string(),
                     group_alias: Some("group_alias4".to_string()),
+                    folder_id: None,
+                    root_hash: None,
+                    due_at: None,
                     file_status: FileStatus::Pending {
                         alias: alias4,
                         requested_at: get_time()
@@ -9,10 +9,10 @@ pub fn get_users(state: &State, caller: Principal) -> GetUsersResponse {
             state
                 .users
                 .iter()
-                .map(|val| PublicUser {
-                    username: val.1.username.clone(),
-                    public_key: val.1.public_key.clone(),
-                    ic_principal: *val.0,
+                .map(|(ic_principal, user)| PublicUser {
+                    username: user.username,
+                    public_key: user.public_key,
+                    ic_principal,
                 })
                 .collect(),
         ),
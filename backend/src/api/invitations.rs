@@ -0,0 +1,423 @@
+use super::request_alias::is_expired;
+use super::share_file::{can_share, share_file_with_permission};
+use super::user_info::{principal_by_username, set_user_info};
+use crate::{
+    get_time, AliasError, FileContent, FileSharingResponse, Invitation, InviteResponse, Invitee,
+    SharePermission, State, User,
+};
+use candid::Principal;
+
+/// Shares a file with the user registered with the username, or invites
+/// whoever registers with it later.
+pub fn share_file_by_username(
+    state: &mut State,
+    caller: Principal,
+    username: String,
+    file_id: u64,
+    permission: SharePermission,
+    expires_at: Option<u64>,
+) -> InviteResponse {
+    match principal_by_username(state, &username) {
+        Some(user) => {
+            match share_file_with_permission(state, caller, user, file_id, permission, expires_at) {
+                FileSharingResponse::Ok => InviteResponse::Shared,
+                FileSharingResponse::PendingError => InviteResponse::PendingError,
+                FileSharingResponse::PermissionError
+                | FileSharingResponse::UnknownUser
+                | FileSharingResponse::NameExists => InviteResponse::PermissionError,
+            }
+        }
+        None => invite(
+            state,
+            caller,
+            |_| Ok(Invitee::Username(username)),
+            file_id,
+            permission,
+            expires_at,
+        ),
+    }
+}
+
+/// Invites whoever redeems the code of the returned invitation.
+pub fn create_invitation(
+    state: &mut State,
+    caller: Principal,
+    file_id: u64,
+    permission: SharePermission,
+    expires_at: Option<u64>,
+) -> InviteResponse {
+    invite(
+        state,
+        caller,
+        |state| state.generate_alias().map(Invitee::Code),
+        file_id,
+        permission,
+        expires_at,
+    )
+}
+
+/// Returns the pending invitations to the caller's files.
+pub fn get_invitations(state: &State, caller: Principal) -> Vec<Invitation> {
+    state
+        .owned_files(caller)
+        .into_iter()
+        .flat_map(|file_id| {
+            state
+                .invitations
+                .range((file_id, 0)..=(file_id, u64::MAX))
+                .map(|(_, invitation)| invitation)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+pub fn cancel_invitation(
+    state: &mut State,
+    caller: Principal,
+    file_id: u64,
+    invitation_id: u64,
+) -> FileSharingResponse {
+    if !state.has_permission(caller, file_id, SharePermission::Manage) {
+        return FileSharingResponse::PermissionError;
+    }
+    match state.remove_invitation(file_id, invitation_id) {
+        Some(_) => FileSharingResponse::Ok,
+        None => FileSharingResponse::PermissionError,
+    }
+}
+
+/// Turns the invitation with the given code into a share with the caller.
+pub fn accept_invitation(
+    state: &mut State,
+    caller: Principal,
+    code: String,
+) -> FileSharingResponse {
+    if !state.users.contains_key(&caller) {
+        return FileSharingResponse::UnknownUser;
+    }
+    match find_invitations(state, &Invitee::Code(code)).pop() {
+        Some(invitation) => redeem(state, caller, invitation),
+        None => FileSharingResponse::PermissionError,
+    }
+}
+
+/// Registers the caller, or changes their user info. On their first
+/// registration, the invitations to their username become shares. When they
+/// rename themselves, the invitations to their new username are dropped
+/// instead, as that would let anyone take over the invitations to a username,
+/// and no one else can register with it anymore. The invitation with the
+/// given code, if any, is accepted either way.
+pub fn register_user(
+    state: &mut State,
+    caller: Principal,
+    user: User,
+    invite_code: Option<String>,
+) {
+    let is_new_user = !state.users.contains_key(&caller);
+    let invitee = Invitee::Username(user.username.clone());
+    set_user_info(state, caller, user);
+    for invitation in find_invitations(state, &invitee) {
+        if is_new_user {
+            redeem(state, caller, invitation);
+        } else {
+            state.remove_invitation(invitation.file_id, invitation.invitation_id);
+        }
+    }
+    if let Some(code) = invite_code {
+        accept_invitation(state, caller, code);
+    }
+}
+
+fn invite(
+    state: &mut State,
+    caller: Principal,
+    invitee: impl FnOnce(&mut State) -> Result<Invitee, AliasError>,
+    file_id: u64,
+    permission: SharePermission,
+    expires_at: Option<u64>,
+) -> InviteResponse {
+    if !can_share(state, caller, file_id, permission) {
+        return InviteResponse::PermissionError;
+    }
+    match state.file_data.get(&file_id).unwrap().content {
+        FileContent::Pending { .. } | FileContent::PartiallyUploaded { .. } => {
+            InviteResponse::PendingError
+        }
+        FileContent::Uploaded { .. } => {
+            let Ok(invitee) = invitee(state) else {
+                return InviteResponse::AliasUnavailable;
+            };
+            InviteResponse::Invited(state.invite(caller, invitee, file_id, permission, expires_at))
+        }
+    }
+}
+
+/// Returns the invitations to the invitee. Those whose share would already
+/// have ended are dropped instead.
+fn find_invitations(state: &mut State, invitee: &Invitee) -> Vec<Invitation> {
+    let now = get_time();
+    let mut invitations = vec![];
+    for (file_id, invitation_id) in state.indexes.invitations_to(invitee) {
+        let Some(invitation) = state.invitations.get(&(file_id, invitation_id)) else {
+            continue;
+        };
+        if is_expired(invitation.expires_at, now) {
+            state.remove_invitation(file_id, invitation_id);
+        } else {
+            invitations.push(invitation);
+        }
+    }
+    invitations
+}
+
+/// Shares the file as the inviter did. The invitation is used up even if the
+/// inviter may no longer share the file.
+fn redeem(state: &mut State, user: Principal, invitation: Invitation) -> FileSharingResponse {
+    state.remove_invitation(invitation.file_id, invitation.invitation_id);
+    share_file_with_permission(
+        state,
+        invitation.inviter,
+        user,
+        invitation.file_id,
+        invitation.permission,
+        invitation.expires_at,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{
+        delete_file, get_requests, reserve_file_id, set_user_info, upload_file_atomic,
+        UploadFileAtomicRequest,
+    };
+    use crate::{get_time, Share};
+
+    const OWNER: Principal = Principal::from_slice(&[0, 1, 2]);
+    const JANE: Principal = Principal::from_slice(&[0, 1, 3]);
+    const BOB: Principal = Principal::from_slice(&[0, 1, 4]);
+
+    fn user(username: &str) -> User {
+        User {
+            username: username.to_string(),
+            public_key: vec![1, 2, 3],
+        }
+    }
+
+    fn register(state: &mut State, principal: Principal, username: &str) {
+        set_user_info(state, principal, user(username));
+    }
+
+    fn upload(state: &mut State) -> u64 {
+        upload_file_atomic(
+            OWNER,
+            UploadFileAtomicRequest {
+                name: "file".to_string(),
+                content: vec![1, 2, 3],
+                file_type: "jpeg".to_string(),
+                num_chunks: 1,
+                total_size: Some(3),
+                chunk_size: Some(3),
+                file_id: reserve_file_id(state, OWNER).unwrap().file_id,
+            },
+            state,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn invitations_become_shares_on_registration() {
+        let mut state = State::default();
+        register(&mut state, OWNER, "owner");
+        let file_id = upload(&mut state);
+
+        let InviteResponse::Invited(jane_invitation) = share_file_by_username(
+            &mut state,
+            OWNER,
+            "jane".to_string(),
+            file_id,
+            SharePermission::View,
+            None,
+        ) else {
+            panic!("expected an invitation");
+        };
+        assert_eq!(
+            jane_invitation.invitee,
+            Invitee::Username("jane".to_string())
+        );
+        let InviteResponse::Invited(code_invitation) = create_invitation(
+            &mut state,
+            OWNER,
+            file_id,
+            SharePermission::Download,
+            Some(get_time() + 1),
+        ) else {
+            panic!("expected an invitation");
+        };
+        let Invitee::Code(code) = code_invitation.invitee.clone() else {
+            panic!("expected a code");
+        };
+        assert_eq!(
+            create_invitation(&mut state, JANE, file_id, SharePermission::View, None),
+            InviteResponse::PermissionError
+        );
+        assert_eq!(
+            get_invitations(&state, OWNER),
+            vec![jane_invitation, code_invitation]
+        );
+
+        register_user(&mut state, JANE, user("jane"), None);
+        register_user(&mut state, BOB, user("bob"), Some(code.clone()));
+        assert_eq!(
+            state.file_shares.get(&(JANE, file_id)),
+            Some(Share {
+                permission: SharePermission::View,
+                expires_at: None,
+            })
+        );
+        assert_eq!(
+            state.file_shares.get(&(BOB, file_id)),
+            Some(Share {
+                permission: SharePermission::Download,
+                expires_at: Some(get_time() + 1),
+            })
+        );
+        assert_eq!(get_invitations(&state, OWNER), vec![]);
+        assert_eq!(
+            accept_invitation(&mut state, BOB, code),
+            FileSharingResponse::PermissionError
+        );
+
+        // Registered users are shared with directly.
+        assert_eq!(
+            share_file_by_username(
+                &mut state,
+                OWNER,
+                "jane".to_string(),
+                file_id,
+                SharePermission::Manage,
+                None,
+            ),
+            InviteResponse::Shared
+        );
+    }
+
+    #[test]
+    fn renaming_drops_invitations() {
+        let mut state = State::default();
+        register(&mut state, OWNER, "owner");
+        register(&mut state, BOB, "bob");
+        let file_id = upload(&mut state);
+        share_file_by_username(
+            &mut state,
+            OWNER,
+            "jane".to_string(),
+            file_id,
+            SharePermission::View,
+            None,
+        );
+
+        register_user(&mut state, BOB, user("jane"), None);
+        assert_eq!(state.file_shares.get(&(BOB, file_id)), None);
+        // No one else can register with the username anymore.
+        assert_eq!(get_invitations(&state, OWNER), vec![]);
+        assert_eq!(principal_by_username(&state, "jane"), Some(BOB));
+        assert_eq!(principal_by_username(&state, "bob"), None);
+        state.check_indexes();
+    }
+
+    #[test]
+    fn expired_invitations_are_rejected() {
+        let mut state = State::default();
+        register(&mut state, OWNER, "owner");
+        let file_id = upload(&mut state);
+        let InviteResponse::Invited(invitation) = create_invitation(
+            &mut state,
+            OWNER,
+            file_id,
+            SharePermission::View,
+            Some(get_time()),
+        ) else {
+            panic!("expected an invitation");
+        };
+        let Invitee::Code(code) = invitation.invitee else {
+            panic!("expected a code");
+        };
+        share_file_by_username(
+            &mut state,
+            OWNER,
+            "jane".to_string(),
+            file_id,
+            SharePermission::View,
+            Some(get_time()),
+        );
+
+        register_user(&mut state, JANE, user("jane"), Some(code));
+        assert_eq!(state.file_shares.get(&(JANE, file_id)), None);
+        // Expired invitations are dropped once found.
+        assert_eq!(get_invitations(&state, OWNER), vec![]);
+        state.check_indexes();
+    }
+
+    #[test]
+    fn invitations_can_be_cancelled() {
+        let mut state = State::default();
+        register(&mut state, OWNER, "owner");
+        register(&mut state, JANE, "jane");
+        let file_id = upload(&mut state);
+        let invite = |state: &mut State| match create_invitation(
+            state,
+            OWNER,
+            file_id,
+            SharePermission::View,
+            None,
+        ) {
+            InviteResponse::Invited(invitation) => invitation.invitation_id,
+            response => panic!("unexpected response {response:?}"),
+        };
+
+        let invitation_id = invite(&mut state);
+        assert_eq!(
+            cancel_invitation(&mut state, JANE, file_id, invitation_id),
+            FileSharingResponse::PermissionError
+        );
+        assert_eq!(
+            cancel_invitation(&mut state, OWNER, file_id, invitation_id),
+            FileSharingResponse::Ok
+        );
+        assert_eq!(get_invitations(&state, OWNER), vec![]);
+
+        // Deleting the file drops its invitations.
+        invite(&mut state);
+        delete_file(&mut state, OWNER, file_id);
+        assert!(state.invitations.is_empty());
+        state.check_indexes();
+    }
+
+    #[test]
+    fn shares_with_unregistered_principals_are_listed_without_them() {
+        let mut state = State::default();
+        register(&mut state, OWNER, "owner");
+        let file_id = upload(&mut state);
+
+        assert_eq!(
+            share_file_with_permission(
+                &mut state,
+                OWNER,
+                JANE,
+                file_id,
+                SharePermission::View,
+                None
+            ),
+            FileSharingResponse::UnknownUser
+        );
+        // Such shares could be made before sharing checked for registration.
+        let share = Share {
+            permission: SharePermission::View,
+            expires_at: None,
+        };
+        state.file_shares.insert((JANE, file_id), share);
+        state.indexes.add_share(file_id, JANE, &share);
+        assert_eq!(get_requests(&state, OWNER)[0].shared_with, vec![]);
+    }
+}
@@ -0,0 +1,400 @@
+use super::storage_quota::{charge_storage, check_quota, release_storage};
+use super::upload_session::{MAX_CHUNK_SIZE, MAX_NUM_CHUNKS};
+use crate::{FileContent, KeyDerivation, ReencryptedChunk, State, UploadFileError};
+use candid::Principal;
+
+/// Moves a file to a new key after a share was revoked. The file keeps its
+/// ciphertext until the owner re-encrypts it for the new key.
+pub(crate) fn rotate_file_key(state: &mut State, file_id: u64, former_sharee: Principal) {
+    let Some(mut file) = state.file_data.get(&file_id) else {
+        return;
+    };
+
+    // The former sharee holds the key of the current ciphertext.
+    state.revoked_shares.insert(
+        (file_id, former_sharee),
+        file.metadata.derivation_id(file_id),
+    );
+
+    // Nobody has the key of a pending version yet, so it doesn't need to be bumped again.
+    if file.metadata.pending_key_version.is_none() {
+        file.metadata.pending_key_version = Some(match file.metadata.key_derivation {
+            KeyDerivation::Owner => 0,
+            KeyDerivation::PerFile { key_version } => key_version + 1,
+        });
+        state.file_data.insert(file_id, file);
+    }
+}
+
+/// Whether the user lost access to the file while its current ciphertext was
+/// shared with them.
+pub(crate) fn holds_revoked_key(state: &State, user: Principal, file_id: u64) -> bool {
+    let Some(file) = state.file_data.get(&file_id) else {
+        return false;
+    };
+    state.revoked_shares.get(&(file_id, user)) == Some(file.metadata.derivation_id(file_id))
+}
+
+/// Returns the IDs of the caller's files that wait to be re-encrypted for a new key.
+pub fn get_files_to_reencrypt(state: &State, caller: Principal) -> Vec<u64> {
+    state
+        .owned_files(caller)
+        .into_iter()
+        .filter(|file_id| {
+            state
+                .file_data
+                .get(file_id)
+                .is_some_and(|file| file.metadata.pending_key_version.is_some())
+        })
+        .collect()
+}
+
+/// Queues the caller's files that are still encrypted with their owner key to
+/// be re-encrypted for their per-file keys, and returns their IDs. Sharees
+/// can't decrypt such files until they are re-encrypted.
+pub fn migrate_to_per_file_keys(state: &mut State, caller: Principal) -> Vec<u64> {
+    let mut file_ids = vec![];
+    for file_id in state.owned_files(caller) {
+        let Some(mut file) = state.file_data.get(&file_id) else {
+            continue;
+        };
+        if file.metadata.key_derivation != KeyDerivation::Owner
+            || !matches!(file.content, FileContent::Uploaded { .. })
+        {
+            continue;
+        }
+        if file.metadata.pending_key_version.is_none() {
+            file.metadata.pending_key_version = Some(0);
+            state.file_data.insert(file_id, file);
+        }
+        file_ids.push(file_id);
+    }
+    file_ids
+}
+
+/// Stages a chunk of a file re-encrypted for its pending key. The file keeps
+/// its current contents until `commit_reencryption` is called.
+pub fn upload_reencrypted_chunk(
+    state: &mut State,
+    caller: Principal,
+    request: ReencryptedChunk,
+) -> Result<(), UploadFileError> {
+    let file_id = request.file_id;
+    let file = state
+        .file_data
+        .get(&file_id)
+        .ok_or(UploadFileError::NotRequested)?;
+    if !state.is_owner(caller, file_id) {
+        return Err(UploadFileError::PermissionError);
+    }
+    if file.metadata.pending_key_version.is_none() {
+        return Err(UploadFileError::NoKeyRotation);
+    }
+    let chunk_len = request.contents.len() as u64;
+    if chunk_len > MAX_CHUNK_SIZE {
+        return Err(UploadFileError::ChunkTooLarge);
+    }
+    if request.chunk_id >= MAX_NUM_CHUNKS {
+        return Err(UploadFileError::ChunkOutOfRange);
+    }
+
+    // A chunk can be uploaded again, replacing the staged one.
+    let replaced_len = state
+        .reencrypted_contents
+        .get(&(file_id, request.chunk_id))
+        .map_or(0, |chunk| chunk.len() as u64);
+    check_quota(state, caller, chunk_len.saturating_sub(replaced_len), 0)?;
+    release_storage(state, caller, replaced_len, 0);
+    charge_storage(state, caller, chunk_len, 0);
+    state
+        .reencrypted_contents
+        .insert((file_id, request.chunk_id), request.contents);
+    Ok(())
+}
+
+/// Replaces the contents of a file with its staged re-encrypted chunks and
+/// moves the file to its pending key.
+pub fn commit_reencryption(
+    state: &mut State,
+    caller: Principal,
+    file_id: u64,
+    num_chunks: u64,
+) -> Result<(), UploadFileError> {
+    let mut file = state
+        .file_data
+        .get(&file_id)
+        .ok_or(UploadFileError::NotRequested)?;
+    if !state.is_owner(caller, file_id) {
+        return Err(UploadFileError::PermissionError);
+    }
+    let Some(key_version) = file.metadata.pending_key_version else {
+        return Err(UploadFileError::NoKeyRotation);
+    };
+    let FileContent::Uploaded { file_type, .. } = file.content else {
+        return Err(UploadFileError::UploadNotStarted);
+    };
+    let staged: Vec<_> = state
+        .reencrypted_contents
+        .range((file_id, 0)..=(file_id, u64::MAX))
+        .collect();
+    if num_chunks == 0
+        || staged.len() as u64 != num_chunks
+        || staged.last().map(|((_, chunk_id), _)| *chunk_id) != Some(num_chunks - 1)
+    {
+        return Err(UploadFileError::MissingChunks);
+    }
+
+    // Swap the contents. The staged chunks were already counted against the quota.
+    let old_bytes = state.stored_bytes(file_id);
+    state.remove_file_contents(file_id);
+    release_storage(state, caller, old_bytes, 0);
+    for (key, chunk) in staged {
+        state.reencrypted_contents.remove(&key);
+        state.file_contents.insert(key, chunk);
+    }
+
+    file.content = FileContent::Uploaded {
+        num_chunks,
+        file_type,
+    };
+    file.metadata.key_derivation = KeyDerivation::PerFile { key_version };
+    file.metadata.pending_key_version = None;
+    state.hash_file_contents(file_id, &mut file.metadata);
+    state.file_data.insert(file_id, file);
+    Ok(())
+}
+
+/// Drops the staged chunks and revoked shares of a file that is removed.
+pub(crate) fn discard_key_rotation(state: &mut State, owner: Principal, file_id: u64) {
+    let staged: Vec<_> = state
+        .reencrypted_contents
+        .range((file_id, 0)..=(file_id, u64::MAX))
+        .collect();
+    for (key, chunk) in staged {
+        state.reencrypted_contents.remove(&key);
+        release_storage(state, owner, chunk.len() as u64, 0);
+    }
+
+    // The empty principal sorts before all others.
+    let former_sharees: Vec<_> = state
+        .revoked_shares
+        .range((file_id, Principal::management_canister())..)
+        .take_while(|((id, _), _)| *id == file_id)
+        .map(|(key, _)| key)
+        .collect();
+    for key in former_sharees {
+        state.revoked_shares.remove(&key);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{
+        download_file, reserve_file_id, revoke_share, set_user_info, share_file,
+        upload_file_atomic, UploadFileAtomicRequest,
+    };
+    use crate::vetkd::controller::vetkd_encrypted_key::file_key_info;
+    use crate::{file_derivation_id, FileDownloadResponse, User, VetkdError};
+
+    const OWNER: Principal = Principal::from_slice(&[0, 1, 2]);
+    const SHAREE: Principal = Principal::from_slice(&[3, 4, 5]);
+
+    fn setup() -> (State, u64) {
+        let mut state = State::default();
+        for (principal, username) in [(OWNER, "John"), (SHAREE, "Jane")] {
+            set_user_info(
+                &mut state,
+                principal,
+                User {
+                    username: username.to_string(),
+                    public_key: vec![1, 2, 3],
+                },
+            );
+        }
+        let file_id = upload_file_atomic(
+            OWNER,
+            UploadFileAtomicRequest {
+                name: "file_name".to_string(),
+                content: vec![1, 2, 3],
+                file_type: "image/jpeg".to_string(),
+                num_chunks: 1,
+                total_size: Some(3),
+                chunk_size: Some(3),
+                file_id: reserve_file_id(&mut state, OWNER).unwrap().file_id,
+            },
+            &mut state,
+        )
+        .unwrap();
+        share_file(&mut state, OWNER, SHAREE, file_id);
+        revoke_share(&mut state, OWNER, SHAREE, file_id);
+        (state, file_id)
+    }
+
+    fn stage(state: &mut State, file_id: u64, chunk_id: u64, contents: Vec<u8>) {
+        upload_reencrypted_chunk(
+            state,
+            OWNER,
+            ReencryptedChunk {
+                file_id,
+                chunk_id,
+                contents,
+            },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn revoking_a_share_rotates_the_key() {
+        let (mut state, file_id) = setup();
+
+        let metadata = state.file_data.get(&file_id).unwrap().metadata;
+        assert_eq!(
+            metadata.key_derivation,
+            KeyDerivation::PerFile { key_version: 0 }
+        );
+        assert_eq!(metadata.pending_key_version, Some(1));
+        assert_eq!(get_files_to_reencrypt(&state, OWNER), vec![file_id]);
+        assert_eq!(
+            file_key_info(&state, OWNER, file_id)
+                .unwrap()
+                .reencryption_derivation_id,
+            Some(file_derivation_id(OWNER, file_id, 1))
+        );
+
+        // Sharing the file again doesn't give back access to the old ciphertext.
+        share_file(&mut state, OWNER, SHAREE, file_id);
+        assert_eq!(
+            download_file(&state, file_id, 0, SHAREE),
+            FileDownloadResponse::PermissionError
+        );
+        assert_eq!(
+            file_key_info(&state, SHAREE, file_id),
+            Err(VetkdError::PermissionError)
+        );
+
+        stage(&mut state, file_id, 0, vec![4, 5]);
+        stage(&mut state, file_id, 1, vec![6]);
+        commit_reencryption(&mut state, OWNER, file_id, 2).unwrap();
+
+        let file = state.file_data.get(&file_id).unwrap();
+        assert_eq!(
+            file.metadata.key_derivation,
+            KeyDerivation::PerFile { key_version: 1 }
+        );
+        assert_eq!(
+            file.metadata.derivation_id(file_id),
+            file_derivation_id(OWNER, file_id, 1)
+        );
+        assert_eq!(file.metadata.pending_key_version, None);
+        assert_eq!(state.file_contents.get(&(file_id, 0)), Some(vec![4, 5]));
+        assert_eq!(state.file_contents.get(&(file_id, 1)), Some(vec![6]));
+        assert!(state.reencrypted_contents.is_empty());
+        assert_eq!(state.storage_usage.get(&OWNER).unwrap().bytes, 3);
+        assert!(get_files_to_reencrypt(&state, OWNER).is_empty());
+
+        // The re-encrypted file can be downloaded by its sharees again.
+        assert!(matches!(
+            download_file(&state, file_id, 0, SHAREE),
+            FileDownloadResponse::FoundFile(_)
+        ));
+    }
+
+    #[test]
+    fn incomplete_reencryptions_are_rejected() {
+        let (mut state, file_id) = setup();
+
+        assert_eq!(
+            upload_reencrypted_chunk(
+                &mut state,
+                SHAREE,
+                ReencryptedChunk {
+                    file_id,
+                    chunk_id: 0,
+                    contents: vec![4],
+                },
+            ),
+            Err(UploadFileError::PermissionError)
+        );
+        stage(&mut state, file_id, 1, vec![6]);
+        assert_eq!(
+            commit_reencryption(&mut state, OWNER, file_id, 2),
+            Err(UploadFileError::MissingChunks)
+        );
+        assert_eq!(
+            commit_reencryption(&mut state, OWNER, file_id, 1),
+            Err(UploadFileError::MissingChunks)
+        );
+
+        // The file still has its old contents and key.
+        assert_eq!(state.file_contents.get(&(file_id, 0)), Some(vec![1, 2, 3]));
+        assert_eq!(
+            state
+                .file_data
+                .get(&file_id)
+                .unwrap()
+                .metadata
+                .pending_key_version,
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn files_without_a_rotation_cannot_be_reencrypted() {
+        let (mut state, file_id) = setup();
+        stage(&mut state, file_id, 0, vec![4]);
+        commit_reencryption(&mut state, OWNER, file_id, 1).unwrap();
+
+        assert_eq!(
+            commit_reencryption(&mut state, OWNER, file_id, 1),
+            Err(UploadFileError::NoKeyRotation)
+        );
+
+        // Revoking again bumps the key version.
+        share_file(&mut state, OWNER, SHAREE, file_id);
+        revoke_share(&mut state, OWNER, SHAREE, file_id);
+        assert_eq!(
+            state
+                .file_data
+                .get(&file_id)
+                .unwrap()
+                .metadata
+                .pending_key_version,
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn owner_keyed_files_migrate_to_per_file_keys() {
+        let (mut state, file_id) = setup();
+        // Files stored before per-file keys are encrypted with the owner's key.
+        let mut file = state.file_data.get(&file_id).unwrap();
+        file.metadata.key_derivation = KeyDerivation::Owner;
+        file.metadata.pending_key_version = None;
+        state.file_data.insert(file_id, file);
+        state.revoked_shares.remove(&(file_id, SHAREE));
+
+        assert_eq!(migrate_to_per_file_keys(&mut state, SHAREE), vec![]);
+        assert_eq!(migrate_to_per_file_keys(&mut state, OWNER), vec![file_id]);
+        assert_eq!(get_files_to_reencrypt(&state, OWNER), vec![file_id]);
+        assert_eq!(
+            file_key_info(&state, OWNER, file_id)
+                .unwrap()
+                .reencryption_derivation_id,
+            Some(file_derivation_id(OWNER, file_id, 0))
+        );
+
+        stage(&mut state, file_id, 0, vec![4]);
+        commit_reencryption(&mut state, OWNER, file_id, 1).unwrap();
+        assert_eq!(
+            state
+                .file_data
+                .get(&file_id)
+                .unwrap()
+                .metadata
+                .key_derivation,
+            KeyDerivation::PerFile { key_version: 0 }
+        );
+        assert_eq!(migrate_to_per_file_keys(&mut state, OWNER), vec![]);
+    }
+}
@@ -0,0 +1,416 @@
+use crate::memory::Memory;
+use crate::{
+    get_time, File, FileContent, FileStatusFilter, ListFilesQuery, ListFilesResponse, SortOrder,
+    State,
+};
+use candid::Principal;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::ops::Bound::{Excluded, Included};
+
+use super::get_requests::get_public_file_metadata;
+
+/// The number of files returned per page if the query doesn't specify a limit.
+pub(super) const DEFAULT_PAGE_SIZE: u64 = 50;
+/// The maximum number of files returned per page.
+pub(super) const MAX_PAGE_SIZE: u64 = 200;
+
+/// Returns a page of the files owned by the caller.
+pub fn list_requests(state: &State, caller: Principal, query: ListFilesQuery) -> ListFilesResponse {
+    list_files(state, &state.file_owners, caller, query, |_| true)
+}
+
+/// Returns a page of the files shared with the caller.
+pub fn list_shared_files(
+    state: &State,
+    caller: Principal,
+    query: ListFilesQuery,
+) -> ListFilesResponse {
+    let now = get_time();
+    list_files(state, &state.file_shares, caller, query, |share| {
+        !share.is_expired(now)
+    })
+}
+
+/// Walks the caller's entries of a map keyed by (principal, file ID) from the
+/// cursor on, and stops as soon as the page is full.
+fn list_files<V: Storable>(
+    state: &State,
+    files: &StableBTreeMap<(Principal, u64), V, Memory>,
+    caller: Principal,
+    query: ListFilesQuery,
+    include: impl Fn(&V) -> bool,
+) -> ListFilesResponse {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE) as usize;
+    let descending = query.sort_order == Some(SortOrder::Descending);
+
+    // Only consider the files after the cursor, in the requested order.
+    let file_ids: Box<dyn Iterator<Item = (u64, V)> + '_> = if descending {
+        Box::new(entries_before(
+            files,
+            caller,
+            query.cursor.unwrap_or(u64::MAX),
+        ))
+    } else {
+        let start = match query.cursor {
+            Some(cursor) => Excluded((caller, cursor)),
+            None => Included((caller, 0)),
+        };
+        Box::new(
+            files
+                .range((start, Included((caller, u64::MAX))))
+                .map(|((_, file_id), value)| (file_id, value)),
+        )
+    };
+
+    let mut matching = file_ids
+        .filter(|(_, value)| include(value))
+        .map(|(file_id, _)| file_id)
+        .filter(|file_id| {
+            query
+                .group_id
+                .is_none_or(|group_id| state.indexes.file_group.get(file_id) == Some(group_id))
+                && state
+                    .file_data
+                    .get(file_id)
+                    .is_some_and(|file| matches_filters(&file, &query))
+        });
+
+    let page: Vec<u64> = matching.by_ref().take(limit).collect();
+    let next_cursor = match matching.next() {
+        Some(_) => page.last().copied(),
+        None => None,
+    };
+
+    ListFilesResponse {
+        files: page
+            .into_iter()
+            .map(|file_id| get_public_file_metadata(state, file_id))
+            .collect(),
+        next_cursor,
+    }
+}
+
+/// Iterates over the caller's entries with a file ID below `before`, from the
+/// highest ID down.
+fn entries_before<V: Storable>(
+    files: &StableBTreeMap<(Principal, u64), V, Memory>,
+    caller: Principal,
+    before: u64,
+) -> impl Iterator<Item = (u64, V)> + '_ {
+    let mut bound = (caller, before);
+    std::iter::from_fn(move || {
+        let ((principal, file_id), value) = files.iter_upper_bound(&bound).next()?;
+        if principal != caller {
+            return None;
+        }
+        bound = (principal, file_id);
+        Some((file_id, value))
+    })
+}
+
+fn matches_filters(file: &File, query: &ListFilesQuery) -> bool {
+    let status_matches = match query.status {
+        None => true,
+        Some(FileStatusFilter::Pending) => matches!(file.content, FileContent::Pending { .. }),
+        Some(FileStatusFilter::Overdue) => {
+            matches!(file.content, FileContent::Pending { .. })
+                && file
+                    .metadata
+                    .due_at
+                    .is_some_and(|due_at| due_at <= get_time())
+        }
+        Some(FileStatusFilter::PartiallyUploaded) => {
+            matches!(file.content, FileContent::PartiallyUploaded { .. })
+        }
+        Some(FileStatusFilter::Uploaded) => matches!(file.content, FileContent::Uploaded { .. }),
+    };
+
+    let name_matches = query
+        .name_prefix
+        .as_ref()
+        .is_none_or(|prefix| file.metadata.file_name.starts_with(prefix.as_str()));
+
+    // Files that haven't been uploaded yet never match an upload time range.
+    let uploaded_at_matches = if query.uploaded_from.is_none() && query.uploaded_to.is_none() {
+        true
+    } else {
+        file.metadata.uploaded_at.is_some_and(|uploaded_at| {
+            query.uploaded_from.is_none_or(|from| uploaded_at >= from)
+                && query.uploaded_to.is_none_or(|to| uploaded_at < to)
+        })
+    };
+
+    status_matches && name_matches && uploaded_at_matches
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::{multi_request, request_file, set_user_info, share_file, upload_file},
+        get_time, MultiRequestInput, User,
+    };
+
+    fn setup() -> State {
+        let mut state = State::default();
+        set_user_info(
+            &mut state,
+            Principal::anonymous(),
+            User {
+                username: "John".to_string(),
+                public_key: vec![1, 2, 3],
+            },
+        );
+        set_user_info(
+            &mut state,
+            Principal::from_slice(&[0, 1, 2]),
+            User {
+                username: "Jane".to_string(),
+                public_key: vec![3, 4, 5],
+            },
+        );
+        state
+    }
+
+    fn file_ids(response: &ListFilesResponse) -> Vec<u64> {
+        response.files.iter().map(|file| file.file_id).collect()
+    }
+
+    #[test]
+    fn pages_through_requests() {
+        let mut state = setup();
+        for i in 0..5 {
+            request_file(Principal::anonymous(), format!("request{i}"), &mut state).unwrap();
+        }
+
+        let query = ListFilesQuery {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page1 = list_requests(&state, Principal::anonymous(), query.clone());
+        assert_eq!(file_ids(&page1), vec![0, 1]);
+        assert_eq!(page1.next_cursor, Some(1));
+
+        let page2 = list_requests(
+            &state,
+            Principal::anonymous(),
+            ListFilesQuery {
+                cursor: page1.next_cursor,
+                ..query.clone()
+            },
+        );
+        assert_eq!(file_ids(&page2), vec![2, 3]);
+        assert_eq!(page2.next_cursor, Some(3));
+
+        let page3 = list_requests(
+            &state,
+            Principal::anonymous(),
+            ListFilesQuery {
+                cursor: page2.next_cursor,
+                ..query
+            },
+        );
+        assert_eq!(file_ids(&page3), vec![4]);
+        assert_eq!(page3.next_cursor, None);
+    }
+
+    #[test]
+    fn pages_in_descending_order() {
+        let mut state = setup();
+        for i in 0..4 {
+            request_file(Principal::anonymous(), format!("request{i}"), &mut state).unwrap();
+        }
+
+        let query = ListFilesQuery {
+            limit: Some(3),
+            sort_order: Some(SortOrder::Descending),
+            ..Default::default()
+        };
+        let page1 = list_requests(&state, Principal::anonymous(), query.clone());
+        assert_eq!(file_ids(&page1), vec![3, 2, 1]);
+
+        let page2 = list_requests(
+            &state,
+            Principal::anonymous(),
+            ListFilesQuery {
+                cursor: page1.next_cursor,
+                ..query
+            },
+        );
+        assert_eq!(file_ids(&page2), vec![0]);
+        assert_eq!(page2.next_cursor, None);
+    }
+
+    #[test]
+    fn pages_only_list_the_callers_files() {
+        let mut state = setup();
+        let jane = Principal::from_slice(&[0, 1, 2]);
+        for i in 0..3 {
+            request_file(Principal::anonymous(), format!("request{i}"), &mut state).unwrap();
+            request_file(jane, format!("request{i}"), &mut state).unwrap();
+        }
+
+        for sort_order in [SortOrder::Ascending, SortOrder::Descending] {
+            let query = ListFilesQuery {
+                sort_order: Some(sort_order),
+                ..Default::default()
+            };
+            let mut johns = file_ids(&list_requests(
+                &state,
+                Principal::anonymous(),
+                query.clone(),
+            ));
+            let mut janes = file_ids(&list_requests(&state, jane, query));
+            if sort_order == SortOrder::Descending {
+                johns.reverse();
+                janes.reverse();
+            }
+            assert_eq!(johns, vec![0, 2, 4]);
+            assert_eq!(janes, vec![1, 3, 5]);
+        }
+    }
+
+    #[test]
+    fn filters_requests() {
+        let mut state = setup();
+        request_file(Principal::anonymous(), "invoice-1", &mut state).unwrap();
+        request_file(Principal::anonymous(), "receipt", &mut state).unwrap();
+        let alias = request_file(Principal::anonymous(), "invoice-2", &mut state).unwrap();
+        multi_request(
+            Principal::anonymous(),
+            MultiRequestInput {
+                group_name: "group".to_string(),
+                file_names: vec!["invoice-3".to_string(), "contract".to_string()],
+                save_as_template: false,
+                expires_at: None,
+                due_at: None,
+            },
+            &mut state,
+        )
+        .unwrap();
+        upload_file(
+            2,
+            alias,
+            vec![1, 2, 3],
+            "jpeg".to_string(),
+            1,
+            Some(3),
+            Some(3),
+            &mut state,
+        )
+        .unwrap();
+
+        let by_status = list_requests(
+            &state,
+            Principal::anonymous(),
+            ListFilesQuery {
+                status: Some(FileStatusFilter::Uploaded),
+                ..Default::default()
+            },
+        );
+        assert_eq!(file_ids(&by_status), vec![2]);
+
+        let by_prefix = list_requests(
+            &state,
+            Principal::anonymous(),
+            ListFilesQuery {
+                name_prefix: Some("invoice".to_string()),
+                status: Some(FileStatusFilter::Pending),
+                ..Default::default()
+            },
+        );
+        assert_eq!(file_ids(&by_prefix), vec![0, 3]);
+
+        let by_group = list_requests(
+            &state,
+            Principal::anonymous(),
+            ListFilesQuery {
+                group_id: Some(0),
+                ..Default::default()
+            },
+        );
+        assert_eq!(file_ids(&by_group), vec![3, 4]);
+
+        let by_upload_time = list_requests(
+            &state,
+            Principal::anonymous(),
+            ListFilesQuery {
+                uploaded_from: Some(get_time()),
+                uploaded_to: Some(get_time() + 1),
+                ..Default::default()
+            },
+        );
+        assert_eq!(file_ids(&by_upload_time), vec![2]);
+
+        let empty_range = list_requests(
+            &state,
+            Principal::anonymous(),
+            ListFilesQuery {
+                uploaded_to: Some(get_time()),
+                ..Default::default()
+            },
+        );
+        assert!(empty_range.files.is_empty());
+    }
+
+    #[test]
+    fn pages_through_shared_files() {
+        let mut state = setup();
+        for i in 0..3 {
+            let alias =
+                request_file(Principal::anonymous(), format!("request{i}"), &mut state).unwrap();
+            upload_file(
+                i,
+                alias,
+                vec![1, 2, 3],
+                "jpeg".to_string(),
+                1,
+                Some(3),
+                Some(3),
+                &mut state,
+            )
+            .unwrap();
+        }
+        // Share in an order that differs from the file IDs.
+        for file_id in [2, 0, 1] {
+            share_file(
+                &mut state,
+                Principal::anonymous(),
+                Principal::from_slice(&[0, 1, 2]),
+                file_id,
+            );
+        }
+
+        let page1 = list_shared_files(
+            &state,
+            Principal::from_slice(&[0, 1, 2]),
+            ListFilesQuery {
+                limit: Some(2),
+                ..Default::default()
+            },
+        );
+        assert_eq!(file_ids(&page1), vec![0, 1]);
+
+        let page2 = list_shared_files(
+            &state,
+            Principal::from_slice(&[0, 1, 2]),
+            ListFilesQuery {
+                limit: Some(2),
+                cursor: page1.next_cursor,
+                ..Default::default()
+            },
+        );
+        assert_eq!(file_ids(&page2), vec![2]);
+        assert_eq!(page2.next_cursor, None);
+
+        // The owner has nothing shared with them.
+        assert!(
+            list_shared_files(&state, Principal::anonymous(), ListFilesQuery::default())
+                .files
+                .is_empty()
+        );
+    }
+}
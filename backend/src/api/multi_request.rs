@@ -1,7 +1,7 @@
 // use crate::aliases::{AliasGenerator, Randomness};
 use crate::{
-    get_time, File, FileContent, FileMetadata, MultiRequestInput, MultiRequestResponse,
-    RequestGroup, State,
+    get_time, AliasError, File, FileContent, FileMetadata, KeyDerivation, MultiRequestInput,
+    MultiRequestResponse, RequestGroup, State,
 };
 use candid::Principal;
 
@@ -9,26 +9,39 @@ pub fn multi_request(
     caller: Principal,
     input: MultiRequestInput,
     state: &mut State,
-) -> MultiRequestResponse {
+) -> Result<MultiRequestResponse, AliasError> {
+    let group_alias = state.generate_alias()?;
+    // Every alias is generated before anything is stored, so that a failure
+    // leaves no half-created group behind.
+    let mut file_aliases = Vec::new();
+    for _ in &input.file_names {
+        let file_alias = state.generate_alias()?;
+        // Aliases are only marked as used once stored, so keep them apart.
+        if file_alias == group_alias || file_aliases.contains(&file_alias) {
+            return Err(AliasError::Unavailable);
+        }
+        file_aliases.push(file_alias);
+    }
     let group_id = state.generate_group_id();
-    let group_alias = state.alias_generator.next();
 
     // Add group alias to index
     state
         .group_alias_index
         .insert(group_alias.clone(), group_id);
+    state
+        .indexes
+        .group_alias
+        .insert(group_id, group_alias.clone());
 
     // Clone values before moving
     let group_name = input.group_name.clone();
     let file_names = input.file_names.clone();
 
     let mut file_ids = Vec::new();
-    let mut file_aliases = Vec::new();
 
     // Process original file_names (consumes input.file_names)
-    for file_name in input.file_names {
+    for (file_name, file_alias) in input.file_names.into_iter().zip(file_aliases) {
         let file_id = state.generate_file_id();
-        let file_alias = state.alias_generator.next(); // Unique per file
 
         state.file_data.insert(
             file_id,
@@ -39,6 +52,13 @@ pub fn multi_request(
                     requester_principal: caller,
                     requested_at: get_time(),
                     uploaded_at: None,
+                    folder_id: None,
+                    chunk_hashes: vec![],
+                    root_hash: None,
+                    key_derivation: KeyDerivation::PerFile { key_version: 0 },
+                    pending_key_version: None,
+                    alias_expires_at: input.expires_at,
+                    due_at: input.due_at,
                 },
                 content: FileContent::Pending {
                     alias: file_alias.clone(),
@@ -46,9 +66,12 @@ pub fn multi_request(
             },
         );
         state.file_alias_index.insert(file_alias.clone(), file_id);
-        state.file_owners.entry(caller).or_default().push(file_id);
+        state.schedule_alias_expiry(&file_alias, input.expires_at);
+        state.schedule_reminder(file_id, input.due_at);
+        state.file_owners.insert((caller, file_id), ());
+        state.indexes.add_file(caller, None, file_id);
+        state.indexes.file_group.insert(file_id, group_id);
         file_ids.push(file_id);
-        file_aliases.push(file_alias);
     }
 
     // Create request group with original group_name
@@ -58,20 +81,24 @@ pub fn multi_request(
         files: file_ids.clone(),
         requester: caller,
         created_at: get_time(),
+        expires_at: input.expires_at,
     };
 
     state.request_groups.insert(group_id, request_group);
+    state.schedule_alias_expiry(&group_alias, input.expires_at);
 
     // Add this line to store file IDs in group_files
-    state.group_files.insert(group_id, file_ids.clone());
+    for &file_id in &file_ids {
+        state.group_files.insert((group_id, file_id), ());
+    }
 
     // Save template using cloned values
     if input.save_as_template {
         let _ = crate::api::template::save_template(state, caller, group_name, file_names);
     }
 
-    MultiRequestResponse {
+    Ok(MultiRequestResponse {
         group_id,
         group_alias,
-    }
+    })
 }
@@ -0,0 +1,126 @@
+use crate::{FileContent, Notification, NotificationKind, State};
+use candid::Principal;
+
+/// Returns the caller's notifications, oldest first.
+pub fn get_notifications(state: &State, caller: Principal) -> Vec<Notification> {
+    state
+        .notifications
+        .range((caller, 0)..=(caller, u64::MAX))
+        .map(|(_, notification)| notification)
+        .collect()
+}
+
+/// Removes a notification from the caller's feed. Returns whether it existed.
+pub fn dismiss_notification(state: &mut State, caller: Principal, notification_id: u64) -> bool {
+    state
+        .notifications
+        .remove(&(caller, notification_id))
+        .is_some()
+}
+
+/// Reminds requesters of the requests that became due before `now` and are
+/// still waiting for their upload. Returns how many reminders were sent.
+pub fn send_reminders(state: &mut State, now: u64) -> usize {
+    let due: Vec<_> = state
+        .due_requests
+        .range(..=(now, u64::MAX))
+        .map(|(key, _)| key)
+        .collect();
+    let mut count = 0;
+    for (due_at, file_id) in due {
+        state.due_requests.remove(&(due_at, file_id));
+        // The file may have been uploaded or deleted in the meantime.
+        let Some(file) = state.file_data.get(&file_id) else {
+            continue;
+        };
+        if !matches!(file.content, FileContent::Pending { .. }) {
+            continue;
+        }
+        state.notify(
+            file.metadata.requester_principal,
+            NotificationKind::OverdueRequest {
+                file_id,
+                file_name: file.metadata.file_name,
+                due_at,
+            },
+        );
+        count += 1;
+    }
+    count
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{get_requests, request_file_with_options, set_user_info, upload_file};
+    use crate::{get_time, FileStatus, RequestOptions, User};
+
+    const REQUESTER: Principal = Principal::from_slice(&[0, 1, 2]);
+
+    #[test]
+    fn overdue_requests_are_reminded_once() {
+        let mut state = State::default();
+        set_user_info(
+            &mut state,
+            REQUESTER,
+            User {
+                username: "John".to_string(),
+                public_key: vec![1, 2, 3],
+            },
+        );
+        let now = get_time();
+        let due = |due_at| RequestOptions {
+            due_at: Some(due_at),
+            ..Default::default()
+        };
+        let alias = request_file_with_options(REQUESTER, "overdue", due(now), &mut state).unwrap();
+        request_file_with_options(REQUESTER, "later", due(now + 1), &mut state).unwrap();
+        let uploaded_alias =
+            request_file_with_options(REQUESTER, "uploaded", due(now), &mut state).unwrap();
+        upload_file(
+            2,
+            uploaded_alias,
+            vec![1, 2, 3],
+            "jpeg".to_string(),
+            1,
+            Some(3),
+            Some(3),
+            &mut state,
+        )
+        .unwrap();
+
+        let requests = get_requests(&state, REQUESTER);
+        assert_eq!(
+            requests[0].file_status,
+            FileStatus::Overdue {
+                alias,
+                requested_at: now,
+                due_at: now,
+            }
+        );
+        assert!(matches!(
+            requests[1].file_status,
+            FileStatus::Pending { .. }
+        ));
+
+        assert_eq!(send_reminders(&mut state, now), 1);
+        assert_eq!(send_reminders(&mut state, now), 0);
+        assert_eq!(
+            get_notifications(&state, REQUESTER),
+            vec![Notification {
+                notification_id: 0,
+                created_at: now,
+                kind: NotificationKind::OverdueRequest {
+                    file_id: 0,
+                    file_name: "overdue".to_string(),
+                    due_at: now,
+                },
+            }]
+        );
+        assert_eq!(get_notifications(&state, Principal::anonymous()), vec![]);
+
+        assert!(dismiss_notification(&mut state, REQUESTER, 0));
+        assert!(!dismiss_notification(&mut state, REQUESTER, 0));
+        assert_eq!(get_notifications(&state, REQUESTER), vec![]);
+    }
+}
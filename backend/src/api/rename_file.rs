@@ -1,4 +1,5 @@
-use crate::{FileSharingResponse, State};
+use super::folder::file_name_exists;
+use crate::{FileSharingResponse, SharePermission, State};
 use candid::Principal;
 
 pub fn rename_file(
@@ -7,22 +8,29 @@ pub fn rename_file(
     file_id: u64,
     new_name: String,
 ) -> FileSharingResponse {
-    // Check if the file exists and the user owns it
-    match state.file_owners.get(&caller) {
-        Some(files) => {
-            if !files.contains(&file_id) {
-                return FileSharingResponse::PermissionError;
-            }
+    // Check if the file exists and the user owns or manages it
+    if !state.has_permission(caller, file_id, SharePermission::Manage) {
+        return FileSharingResponse::PermissionError;
+    }
 
-            // File exists and user has permission, update the name
-            if let Some(file) = state.file_data.get_mut(&file_id) {
-                file.metadata.file_name = new_name;
-                FileSharingResponse::Ok
-            } else {
-                // This shouldn't happen if file_owners is consistent
-                FileSharingResponse::PermissionError
+    // File exists and user has permission, update the name
+    match state.file_data.get(&file_id) {
+        Some(mut file) => {
+            // Managers rename the file within its owner's folder.
+            if file_name_exists(
+                state,
+                file.metadata.requester_principal,
+                file.metadata.folder_id,
+                &new_name,
+                Some(file_id),
+            ) {
+                return FileSharingResponse::NameExists;
             }
+            file.metadata.file_name = new_name;
+            state.file_data.insert(file_id, file);
+            FileSharingResponse::Ok
         }
+        // This shouldn't happen if file_owners is consistent
         None => FileSharingResponse::PermissionError,
     }
 }
@@ -49,7 +57,7 @@ mod test {
         );
 
         // Request a file
-        request_file(Principal::anonymous(), "original_name", &mut state);
+        request_file(Principal::anonymous(), "original_name", &mut state).unwrap();
 
         // Rename the file
         let result = rename_file(
@@ -89,7 +97,7 @@ mod test {
         );
 
         // Request a file as anonymous
-        request_file(Principal::anonymous(), "original_name", &mut state);
+        request_file(Principal::anonymous(), "original_name", &mut state).unwrap();
 
         // Try to rename as another user
         let result = rename_file(
@@ -0,0 +1,440 @@
+use crate::memory::Memory;
+use crate::{
+    get_time, AliasError, AliasRetirement, AliasTarget, FileContent, GetAliasInfoError,
+    RetiredAlias, State, TimedAlias,
+};
+use candid::Principal;
+use ic_stable_structures::StableBTreeMap;
+
+/// The most aliases expired or forgotten at once, which bounds the work done per call.
+const MAX_EXPIRED_ALIASES: usize = 100;
+
+/// How long a retired alias is kept to tell its users why it stopped working
+/// (90 days, in nanoseconds).
+const RETIRED_ALIAS_RETENTION: u64 = 90 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Returns whether an alias with the given expiry has expired by `now`.
+pub(crate) fn is_expired(expires_at: Option<u64>, now: u64) -> bool {
+    expires_at.is_some_and(|expires_at| expires_at <= now)
+}
+
+/// Tells apart aliases that were retired from ones that never existed.
+pub(crate) fn unknown_alias_error(state: &State, alias: &str) -> GetAliasInfoError {
+    match state.retired_aliases.get(&alias.to_string()) {
+        Some(RetiredAlias {
+            reason: AliasRetirement::Expired,
+            ..
+        }) => GetAliasInfoError::Expired,
+        Some(RetiredAlias {
+            reason: AliasRetirement::Closed,
+            ..
+        }) => GetAliasInfoError::Closed,
+        None => GetAliasInfoError::NotFound,
+    }
+}
+
+/// Replaces the alias of a request, e.g. because it was leaked. The old
+/// alias stops working and the new one expires at `expires_at`, if ever.
+pub fn regenerate_alias(
+    state: &mut State,
+    caller: Principal,
+    target: AliasTarget,
+    expires_at: Option<u64>,
+) -> Result<String, AliasError> {
+    authorize_requester(state, caller, target)?;
+    // The old alias keeps working if no new one can be generated.
+    let alias = state.generate_alias()?;
+    retire_alias(state, target, AliasRetirement::Closed);
+
+    match target {
+        AliasTarget::File(file_id) => {
+            let mut file = state.file_data.get(&file_id).ok_or(AliasError::NotFound)?;
+            file.content = FileContent::Pending {
+                alias: alias.clone(),
+            };
+            file.metadata.alias_expires_at = expires_at;
+            state.file_data.insert(file_id, file);
+            state.file_alias_index.insert(alias.clone(), file_id);
+            state.schedule_alias_expiry(&alias, expires_at);
+        }
+        AliasTarget::Group(group_id) => {
+            let mut group = state
+                .request_groups
+                .get(&group_id)
+                .ok_or(AliasError::NotFound)?;
+            group.expires_at = expires_at;
+            state.request_groups.insert(group_id, group);
+            state.group_alias_index.insert(alias.clone(), group_id);
+            state.indexes.group_alias.insert(group_id, alias.clone());
+            state.schedule_alias_expiry(&alias, expires_at);
+            // The group's files can be uploaded for as long as the group can.
+            for file_id in state.group_file_ids(group_id) {
+                if let Some(mut file) = state.file_data.get(&file_id) {
+                    if let FileContent::Pending { alias } = &file.content {
+                        state.schedule_alias_expiry(alias, expires_at);
+                        file.metadata.alias_expires_at = expires_at;
+                        state.file_data.insert(file_id, file);
+                    }
+                }
+            }
+        }
+    }
+    Ok(alias)
+}
+
+/// Stops the alias of a request from accepting uploads until it is regenerated.
+pub fn disable_alias(
+    state: &mut State,
+    caller: Principal,
+    target: AliasTarget,
+) -> Result<(), AliasError> {
+    authorize_requester(state, caller, target)?;
+    retire_alias(state, target, AliasRetirement::Closed);
+    Ok(())
+}
+
+/// Retires the aliases that expired before `now`, up to `MAX_EXPIRED_ALIASES`
+/// of them and earliest first, and returns how many were retired. The rest are
+/// retired by later calls.
+pub fn expire_aliases(state: &mut State, now: u64) -> usize {
+    let due = due_aliases(&state.alias_expiries, now);
+    let mut count = 0;
+    for entry in due {
+        state.alias_expiries.remove(&entry);
+        // The alias may have been replaced or used up in the meantime.
+        let target = if let Some(file_id) = state.file_alias_index.get(&entry.alias) {
+            state
+                .file_data
+                .get(&file_id)
+                .filter(|file| is_expired(file.metadata.alias_expires_at, now))
+                .map(|_| AliasTarget::File(file_id))
+        } else if let Some(group_id) = state.group_alias_index.get(&entry.alias) {
+            state
+                .request_groups
+                .get(&group_id)
+                .filter(|group| is_expired(group.expires_at, now))
+                .map(|_| AliasTarget::Group(group_id))
+        } else {
+            None
+        };
+        if let Some(target) = target {
+            retire_alias(state, target, AliasRetirement::Expired);
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Forgets the aliases retired more than `RETIRED_ALIAS_RETENTION` before
+/// `now`, up to `MAX_EXPIRED_ALIASES` of them, and returns how many were
+/// forgotten. The rest are forgotten by later calls.
+pub fn prune_retired_aliases(state: &mut State, now: u64) -> usize {
+    let due = due_aliases(
+        &state.alias_retirements,
+        now.saturating_sub(RETIRED_ALIAS_RETENTION),
+    );
+    for entry in &due {
+        state.alias_retirements.remove(entry);
+        state.retired_aliases.remove(&entry.alias);
+    }
+    due.len()
+}
+
+/// Returns the first `MAX_EXPIRED_ALIASES` entries that are due by `now`.
+fn due_aliases(map: &StableBTreeMap<TimedAlias, (), Memory>, now: u64) -> Vec<TimedAlias> {
+    map.iter()
+        .map(|(entry, _)| entry)
+        .take_while(|entry| entry.at <= now)
+        .take(MAX_EXPIRED_ALIASES)
+        .collect()
+}
+
+/// Only the requester manages the alias of a request, and only while it waits for its upload.
+fn authorize_requester(
+    state: &State,
+    caller: Principal,
+    target: AliasTarget,
+) -> Result<(), AliasError> {
+    let requester = match target {
+        AliasTarget::File(file_id) => {
+            let file = state.file_data.get(&file_id).ok_or(AliasError::NotFound)?;
+            if !matches!(file.content, FileContent::Pending { .. }) {
+                return Err(AliasError::AlreadyUploaded);
+            }
+            file.metadata.requester_principal
+        }
+        AliasTarget::Group(group_id) => {
+            state
+                .request_groups
+                .get(&group_id)
+                .ok_or(AliasError::NotFound)?
+                .requester
+        }
+    };
+    if requester == caller {
+        Ok(())
+    } else {
+        Err(AliasError::PermissionError)
+    }
+}
+
+/// Removes the current alias of the target, if it has one, from the indexes.
+fn retire_alias(state: &mut State, target: AliasTarget, reason: AliasRetirement) {
+    let alias = match target {
+        AliasTarget::File(file_id) => {
+            let Some(mut file) = state.file_data.get(&file_id) else {
+                return;
+            };
+            let FileContent::Pending { alias } = file.content else {
+                return;
+            };
+            // An empty alias marks a request that can only be uploaded to
+            // through its group.
+            file.content = FileContent::Pending {
+                alias: String::new(),
+            };
+            state.file_data.insert(file_id, file);
+            state.file_alias_index.remove(&alias);
+            alias
+        }
+        AliasTarget::Group(group_id) => {
+            let Some(alias) = state.indexes.group_alias.remove(&group_id) else {
+                return;
+            };
+            state.group_alias_index.remove(&alias);
+            alias
+        }
+    };
+    if !alias.is_empty() {
+        let retired_at = get_time();
+        state.alias_retirements.insert(
+            TimedAlias {
+                at: retired_at,
+                alias: alias.clone(),
+            },
+            (),
+        );
+        state.retired_aliases.insert(
+            alias,
+            RetiredAlias {
+                target,
+                reason,
+                retired_at,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{
+        get_alias_info, get_group_by_alias, multi_request, request_file_with_options,
+        set_user_info, upload_file,
+    };
+    use crate::{MultiRequestInput, RequestOptions, UploadFileError, User};
+
+    const REQUESTER: Principal = Principal::from_slice(&[0, 1, 2]);
+
+    fn setup() -> State {
+        let mut state = State::default();
+        set_user_info(
+            &mut state,
+            REQUESTER,
+            User {
+                username: "John".to_string(),
+                public_key: vec![1, 2, 3],
+            },
+        );
+        state
+    }
+
+    fn upload(state: &mut State, file_id: u64, alias: &str) -> Result<(), UploadFileError> {
+        upload_file(
+            file_id,
+            alias.to_string(),
+            vec![1, 2, 3],
+            "jpeg".to_string(),
+            1,
+            Some(3),
+            Some(3),
+            state,
+        )
+    }
+
+    #[test]
+    fn leaked_aliases_can_be_replaced() {
+        let mut state = setup();
+        let alias =
+            request_file_with_options(REQUESTER, "request", RequestOptions::default(), &mut state)
+                .unwrap();
+
+        assert_eq!(
+            regenerate_alias(
+                &mut state,
+                Principal::anonymous(),
+                AliasTarget::File(0),
+                None
+            ),
+            Err(AliasError::PermissionError)
+        );
+        let new_alias =
+            regenerate_alias(&mut state, REQUESTER, AliasTarget::File(0), None).unwrap();
+
+        assert_eq!(
+            get_alias_info(&state, alias.clone()),
+            Err(GetAliasInfoError::Closed)
+        );
+        assert_eq!(
+            upload(&mut state, 0, &alias),
+            Err(UploadFileError::AliasMismatch)
+        );
+        upload(&mut state, 0, &new_alias).unwrap();
+        assert_eq!(
+            disable_alias(&mut state, REQUESTER, AliasTarget::File(0)),
+            Err(AliasError::AlreadyUploaded)
+        );
+    }
+
+    #[test]
+    fn disabled_group_aliases_are_closed() {
+        let mut state = setup();
+        let group = multi_request(
+            REQUESTER,
+            MultiRequestInput {
+                group_name: "group".to_string(),
+                file_names: vec!["a".to_string()],
+                save_as_template: false,
+                expires_at: None,
+                due_at: None,
+            },
+            &mut state,
+        )
+        .unwrap();
+        let group_target = AliasTarget::Group(group.group_id);
+
+        disable_alias(&mut state, REQUESTER, group_target).unwrap();
+        assert_eq!(
+            get_group_by_alias(&state, group.group_alias.clone()),
+            Err(GetAliasInfoError::Closed)
+        );
+        assert_eq!(
+            upload(&mut state, 0, &group.group_alias),
+            Err(UploadFileError::AliasMismatch)
+        );
+
+        let alias = regenerate_alias(&mut state, REQUESTER, group_target, None).unwrap();
+        assert!(get_group_by_alias(&state, alias.clone()).is_ok());
+        upload(&mut state, 0, &alias).unwrap();
+    }
+
+    #[test]
+    fn expired_aliases_are_retired() {
+        let mut state = setup();
+        let now = crate::get_time();
+        let expiring = |expires_at| RequestOptions {
+            expires_at: Some(expires_at),
+            ..Default::default()
+        };
+        let alias =
+            request_file_with_options(REQUESTER, "request", expiring(now), &mut state).unwrap();
+        let open_alias =
+            request_file_with_options(REQUESTER, "open", expiring(now + 1), &mut state).unwrap();
+
+        // Expired aliases are rejected before they are cleaned up.
+        assert_eq!(
+            get_alias_info(&state, alias.clone()),
+            Err(GetAliasInfoError::Expired)
+        );
+        assert_eq!(
+            upload(&mut state, 0, &alias),
+            Err(UploadFileError::AliasExpired)
+        );
+
+        assert_eq!(expire_aliases(&mut state, now), 1);
+        assert_eq!(state.file_alias_index.get(&alias), None);
+        assert_eq!(
+            get_alias_info(&state, alias),
+            Err(GetAliasInfoError::Expired)
+        );
+        assert!(get_alias_info(&state, open_alias).is_ok());
+    }
+
+    #[test]
+    fn replaced_aliases_expire_on_their_new_schedule() {
+        let mut state = setup();
+        let now = crate::get_time();
+        let group = multi_request(
+            REQUESTER,
+            MultiRequestInput {
+                group_name: "group".to_string(),
+                file_names: vec!["a".to_string()],
+                save_as_template: false,
+                expires_at: Some(now),
+                due_at: None,
+            },
+            &mut state,
+        )
+        .unwrap();
+        let group_target = AliasTarget::Group(group.group_id);
+        let alias = regenerate_alias(&mut state, REQUESTER, group_target, Some(now + 10)).unwrap();
+
+        // What was scheduled before the alias was regenerated is skipped.
+        assert_eq!(expire_aliases(&mut state, now), 0);
+        assert!(get_group_by_alias(&state, alias.clone()).is_ok());
+
+        assert_eq!(expire_aliases(&mut state, now + 10), 2);
+        assert_eq!(
+            get_group_by_alias(&state, alias),
+            Err(GetAliasInfoError::Expired)
+        );
+        assert!(state.alias_expiries.is_empty());
+    }
+
+    #[test]
+    fn aliases_are_expired_in_batches() {
+        let mut state = setup();
+        let now = crate::get_time();
+        for _ in 0..=MAX_EXPIRED_ALIASES {
+            let options = RequestOptions {
+                expires_at: Some(now),
+                ..Default::default()
+            };
+            request_file_with_options(REQUESTER, "request", options, &mut state).unwrap();
+        }
+
+        assert_eq!(expire_aliases(&mut state, now), MAX_EXPIRED_ALIASES);
+        assert_eq!(expire_aliases(&mut state, now), 1);
+        assert_eq!(expire_aliases(&mut state, now), 0);
+        assert!(state.file_alias_index.is_empty());
+    }
+
+    #[test]
+    fn retired_aliases_are_forgotten_after_a_while() {
+        let mut state = setup();
+        let alias =
+            request_file_with_options(REQUESTER, "request", RequestOptions::default(), &mut state)
+                .unwrap();
+        disable_alias(&mut state, REQUESTER, AliasTarget::File(0)).unwrap();
+        let retired_at = crate::get_time();
+
+        assert_eq!(
+            prune_retired_aliases(&mut state, retired_at + RETIRED_ALIAS_RETENTION - 1),
+            0
+        );
+        assert_eq!(
+            get_alias_info(&state, alias.clone()),
+            Err(GetAliasInfoError::Closed)
+        );
+
+        assert_eq!(
+            prune_retired_aliases(&mut state, retired_at + RETIRED_ALIAS_RETENTION),
+            1
+        );
+        assert_eq!(
+            get_alias_info(&state, alias),
+            Err(GetAliasInfoError::NotFound)
+        );
+        assert!(state.alias_retirements.is_empty());
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{get_time, File, FileContent, FileMetadata, State};
+use crate::{
+    get_time, AliasError, File, FileContent, FileMetadata, KeyDerivation, RequestOptions, State,
+};
 use candid::Principal;
 
 use super::user_info::get_user_key;
@@ -8,9 +10,18 @@ pub fn request_file<S: Into<String>>(
     caller: Principal,
     request_name: S,
     state: &mut State,
-) -> String {
-    // TODO: verify that file alias has not been used before.
-    let alias = state.alias_generator.next();
+) -> Result<String, AliasError> {
+    request_file_with_options(caller, request_name, RequestOptions::default(), state)
+}
+
+/// Requests a file with an alias expiry and a due date.
+pub fn request_file_with_options<S: Into<String>>(
+    caller: Principal,
+    request_name: S,
+    options: RequestOptions,
+    state: &mut State,
+) -> Result<String, AliasError> {
+    let alias = state.generate_alias()?;
 
     let file_id = state.generate_file_id();
 
@@ -21,6 +32,13 @@ pub fn request_file<S: Into<String>>(
             requester_principal: caller,
             requested_at: get_time(),
             uploaded_at: None,
+            folder_id: None,
+            chunk_hashes: vec![],
+            root_hash: None,
+            key_derivation: KeyDerivation::PerFile { key_version: 0 },
+            pending_key_version: None,
+            alias_expires_at: options.expires_at,
+            due_at: options.due_at,
         },
         content: FileContent::Pending {
             alias: alias.clone(),
@@ -30,15 +48,14 @@ pub fn request_file<S: Into<String>>(
     state.file_data.insert(file_id, file);
 
     state.file_alias_index.insert(alias.clone(), file_id);
+    state.schedule_alias_expiry(&alias, options.expires_at);
+    state.schedule_reminder(file_id, options.due_at);
 
     // The caller is the owner of this file.
-    state
-        .file_owners
-        .entry(caller)
-        .or_insert_with(Vec::new)
-        .push(file_id);
+    state.file_owners.insert((caller, file_id), ());
+    state.indexes.add_file(caller, None, file_id);
 
-    alias
+    Ok(alias)
 }
 
 #[cfg(test)]
@@ -47,6 +64,7 @@ mod test {
 
     use super::*;
     use maplit::btreemap;
+    use std::collections::BTreeMap;
 
     #[test]
     fn requesting_a_file_updates_file_data_and_owners() {
@@ -59,10 +77,10 @@ mod test {
                 public_key: vec![1, 2, 3],
             },
         );
-        request_file(Principal::anonymous(), "request".to_string(), &mut state);
+        request_file(Principal::anonymous(), "request".to_string(), &mut state).unwrap();
 
         assert_eq!(
-            state.file_data,
+            state.file_data.iter().collect::<BTreeMap<_, _>>(),
             btreemap! {
                 0 => File {
                     metadata: FileMetadata {
@@ -71,18 +89,20 @@ mod test {
                         requester_principal: Principal::anonymous(),
                         requested_at: get_time(),
                         uploaded_at: None,
+                        folder_id: None,
+                        chunk_hashes: vec![],
+                        root_hash: None,
+                        key_derivation: KeyDerivation::PerFile { key_version: 0 },
+                        pending_key_version: None,
+                        alias_expires_at: None,
+                        due_at: None,
                     },
                     content: FileContent::Pending { alias: "puzzling-mountain".to_string() }
                 }
             }
         );
 
-        assert_eq!(
-            state.file_owners,
-            btreemap! {
-                Principal::anonymous() => vec![0],
-            }
-        );
+        assert_eq!(state.owned_files(Principal::anonymous()), vec![0]);
     }
 
     #[test]
@@ -96,16 +116,11 @@ mod test {
                 public_key: vec![1, 2, 3],
             },
         );
-        request_file(Principal::anonymous(), "request".to_string(), &mut state);
+        request_file(Principal::anonymous(), "request".to_string(), &mut state).unwrap();
         assert_eq!(state.file_count, 1);
-        request_file(Principal::anonymous(), "request".to_string(), &mut state);
+        request_file(Principal::anonymous(), "request".to_string(), &mut state).unwrap();
         assert_eq!(state.file_count, 2);
 
-        assert_eq!(
-            state.file_owners,
-            btreemap! {
-                Principal::anonymous() => vec![0, 1],
-            }
-        );
+        assert_eq!(state.owned_files(Principal::anonymous()), vec![0, 1]);
     }
 }
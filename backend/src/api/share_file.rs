@@ -1,7 +1,14 @@
-use crate::{FileContent, FileSharingResponse, PublicFileMetadata, State};
+use crate::{
+    get_time, FileContent, FileSharingResponse, PublicFileMetadata, Share, ShareFilesRequest,
+    SharePermission, ShareResult, State,
+};
 use candid::Principal;
 
-use super::get_requests::{get_allowed_users, get_file_status};
+use super::get_requests::get_public_file_metadata;
+use super::key_rotation::rotate_file_key;
+
+/// The most expired shares removed at once, which bounds the work done per call.
+const MAX_EXPIRED_SHARES: usize = 100;
 
 pub fn share_file(
     state: &mut State,
@@ -11,26 +18,75 @@ pub fn share_file(
     // Remove the file_key_encrypted_for_user parameter as it's not needed
     // file_key_encrypted_for_user: Vec<u8>,
 ) -> FileSharingResponse {
-    if !can_share(state, caller, file_id) {
+    share_file_with_permission(
+        state,
+        caller,
+        sharing_with,
+        file_id,
+        SharePermission::Download,
+        None,
+    )
+}
+
+/// Shares a file until `expires_at`, or changes an existing share. Sharees
+/// with the reshare permission can grant up to their own permission, and
+/// for no longer than their own share lasts.
+pub fn share_file_with_permission(
+    state: &mut State,
+    caller: Principal,
+    sharing_with: Principal,
+    file_id: u64,
+    permission: SharePermission,
+    expires_at: Option<u64>,
+) -> FileSharingResponse {
+    if !can_share(state, caller, file_id, permission)
+        || state.is_owner(sharing_with, file_id)
+        || !can_share(
+            state,
+            caller,
+            file_id,
+            current_permission(state, sharing_with, file_id),
+        )
+    {
         FileSharingResponse::PermissionError
+    } else if !state.users.contains_key(&sharing_with) {
+        FileSharingResponse::UnknownUser
     } else {
-        let file = state.file_data.get_mut(&file_id).unwrap();
-        match &mut file.content {
+        let file = state.file_data.get(&file_id).unwrap();
+        match file.content {
             FileContent::Pending { .. } | FileContent::PartiallyUploaded { .. } => {
                 FileSharingResponse::PendingError
             }
             FileContent::Uploaded { .. } => {
-                // Simply add the file to the shared files list
-                let file_shares = state
+                let caller_expiry = state
                     .file_shares
-                    .entry(sharing_with)
-                    .or_insert_with(Vec::new);
-
-                if !file_shares.contains(&file_id) {
-                    file_shares.push(file_id);
-                    // No need to store an encrypted key
-                    // shared_keys.insert(sharing_with, file_key_encrypted_for_user);
+                    .get(&(caller, file_id))
+                    .and_then(|share| share.expires_at);
+                let share = Share {
+                    permission,
+                    expires_at: match (expires_at, caller_expiry) {
+                        (Some(a), Some(b)) => Some(a.min(b)),
+                        (a, b) => a.or(b),
+                    },
+                };
+                // Simply add the file to the shared files list
+                let previous = state.file_shares.insert((sharing_with, file_id), share);
+                if let Some(previous) = &previous {
+                    state.indexes.remove_share(file_id, sharing_with, previous);
                 }
+                state.indexes.add_share(file_id, sharing_with, &share);
+                match previous {
+                    // A sharee who can no longer download may still hold the file's key.
+                    Some(previous)
+                        if previous.permission >= SharePermission::Download
+                            && permission < SharePermission::Download =>
+                    {
+                        rotate_file_key(state, file_id, sharing_with)
+                    }
+                    _ => {}
+                }
+                // No need to store an encrypted key
+                // shared_keys.insert(sharing_with, file_key_encrypted_for_user);
 
                 FileSharingResponse::Ok
             }
@@ -38,11 +94,26 @@ pub fn share_file(
     }
 }
 
-fn can_share(state: &State, user: Principal, file_id: u64) -> bool {
-    match state.file_owners.get(&user) {
-        None => false,
-        Some(arr) => arr.contains(&file_id),
-    }
+/// Whether the user may grant the permission. Owners and managers may grant
+/// any permission, resharers only up to their own.
+pub(super) fn can_share(
+    state: &State,
+    user: Principal,
+    file_id: u64,
+    permission: SharePermission,
+) -> bool {
+    state.has_permission(user, file_id, SharePermission::Manage)
+        || (state.has_permission(user, file_id, SharePermission::Reshare)
+            && state.has_permission(user, file_id, permission))
+}
+
+/// The permission the user currently has on the file, or view if they have none.
+fn current_permission(state: &State, user: Principal, file_id: u64) -> SharePermission {
+    state
+        .file_shares
+        .get(&(user, file_id))
+        .filter(|share| !share.is_expired(get_time()))
+        .map_or(SharePermission::View, |share| share.permission)
 }
 
 pub fn revoke_share(
@@ -51,15 +122,15 @@ pub fn revoke_share(
     sharing_with: Principal,
     file_id: u64,
 ) -> FileSharingResponse {
-    if !can_share(state, caller, file_id) {
+    if !state.has_permission(caller, file_id, SharePermission::Manage) {
         FileSharingResponse::PermissionError
     } else {
-        match state.file_shares.get_mut(&sharing_with) {
+        match state.file_shares.remove(&(sharing_with, file_id)) {
             None => FileSharingResponse::PermissionError,
-            Some(arr) => {
-                arr.retain(|&val| val != file_id);
-                let file = state.file_data.get_mut(&file_id).unwrap();
-                match &mut file.content {
+            Some(share) => {
+                state.indexes.remove_share(file_id, sharing_with, &share);
+                let file = state.file_data.get(&file_id).unwrap();
+                match file.content {
                     FileContent::Pending { .. } | FileContent::PartiallyUploaded { .. } => {
                         FileSharingResponse::PendingError
                     }
@@ -67,6 +138,10 @@ pub fn revoke_share(
                         // No need to remove an encrypted key since we weren't storing it in the first place
                         // shared_keys.remove(&sharing_with);
 
+                        // The former sharee may still hold the file's key.
+                        if share.permission >= SharePermission::Download {
+                            rotate_file_key(state, file_id, sharing_with);
+                        }
                         FileSharingResponse::Ok
                     }
                 }
@@ -75,52 +150,92 @@ pub fn revoke_share(
     }
 }
 
-pub fn get_shared_files(state: &State, caller: Principal) -> Vec<PublicFileMetadata> {
-    match state.file_shares.get(&caller) {
-        None => vec![],
-        Some(file_ids) => file_ids
-            .iter()
-            .map(|file_id| {
-                let _file = state.file_data.get(file_id).expect("file must exist");
-
-                // Find group name for this file
-                let group_name = state
-                    .request_groups
-                    .values()
-                    .find(|group| group.files.contains(file_id))
-                    .map(|group| group.name.clone())
-                    .unwrap_or_default();
-
-                // Find group alias for this file
-                let group_alias = state
-                    .request_groups
-                    .values()
-                    .find(|group| group.files.contains(file_id))
-                    .and_then(|group| {
-                        state
-                            .group_alias_index
-                            .iter()
-                            .find(|(_a, id)| **id == group.group_id)
-                            .map(|(alias, _)| alias.clone())
-                    });
+/// Shares each of the files with each of the users, and reports the outcome
+/// of every pair so that partial failures can be shown.
+pub fn share_files(
+    state: &mut State,
+    caller: Principal,
+    request: ShareFilesRequest,
+) -> Vec<ShareResult> {
+    let permission = request.permission.unwrap_or(SharePermission::Download);
+    for_each_pair(
+        state,
+        request.file_ids,
+        request.user_ids,
+        |state, user_id, file_id| {
+            share_file_with_permission(
+                state,
+                caller,
+                user_id,
+                file_id,
+                permission,
+                request.expires_at,
+            )
+        },
+    )
+}
 
-                PublicFileMetadata {
-                    file_id: *file_id,
-                    file_name: state
-                        .file_data
-                        .get(file_id)
-                        .expect("file must exist")
-                        .metadata
-                        .file_name
-                        .clone(),
-                    group_name, // Add group name here
-                    group_alias,
-                    shared_with: get_allowed_users(state, *file_id),
-                    file_status: get_file_status(state, *file_id),
-                }
-            })
-            .collect(),
+/// Revokes the shares of each of the files with each of the users.
+pub fn revoke_shares(
+    state: &mut State,
+    caller: Principal,
+    file_ids: Vec<u64>,
+    user_ids: Vec<Principal>,
+) -> Vec<ShareResult> {
+    for_each_pair(state, file_ids, user_ids, |state, user_id, file_id| {
+        revoke_share(state, caller, user_id, file_id)
+    })
+}
+
+fn for_each_pair(
+    state: &mut State,
+    file_ids: Vec<u64>,
+    user_ids: Vec<Principal>,
+    mut f: impl FnMut(&mut State, Principal, u64) -> FileSharingResponse,
+) -> Vec<ShareResult> {
+    let mut results = Vec::with_capacity(file_ids.len() * user_ids.len());
+    for &file_id in &file_ids {
+        for &user_id in &user_ids {
+            let result = if state.users.contains_key(&user_id) {
+                f(state, user_id, file_id)
+            } else {
+                FileSharingResponse::UnknownUser
+            };
+            results.push(ShareResult {
+                file_id,
+                user_id,
+                result,
+            });
+        }
+    }
+    results
+}
+
+/// Removes up to `MAX_EXPIRED_SHARES` shares that expired before `now`,
+/// earliest first, and returns how many were removed. The rest are removed by
+/// later calls.
+pub fn expire_shares(state: &mut State, now: u64) -> usize {
+    let expired = state.indexes.expired_shares(now, MAX_EXPIRED_SHARES);
+    let count = expired.len();
+    for (sharee, file_id) in expired {
+        let Some(share) = state.file_shares.remove(&(sharee, file_id)) else {
+            continue;
+        };
+        state.indexes.remove_share(file_id, sharee, &share);
+        // The former sharee may still hold the file's key.
+        if share.permission >= SharePermission::Download {
+            rotate_file_key(state, file_id, sharee);
+        }
     }
+    count
+}
+
+pub fn get_shared_files(state: &State, caller: Principal) -> Vec<PublicFileMetadata> {
+    state
+        .shared_files(caller)
+        .into_iter()
+        .map(|file_id| get_public_file_metadata(state, file_id))
+        .collect()
 }
 
 #[cfg(test)]
@@ -128,7 +243,7 @@ mod test {
     use super::*;
     use crate::{
         api::{request_file, set_user_info, upload_file},
-        get_time, FileStatus, PublicFileMetadata, PublicUser, User,
+        get_time, root_hash, sha256, FileStatus, PublicFileMetadata, PublicSharee, User,
     };
     use candid::Principal;
 
@@ -154,22 +269,25 @@ mod test {
         );
 
         // Request a file.
-        request_file(Principal::anonymous(), "request", &mut state);
+        let alias0 = request_file(Principal::anonymous(), "request", &mut state).unwrap();
         // Request a file.
-        request_file(Principal::anonymous(), "request2", &mut state);
+        request_file(Principal::anonymous(), "request2", &mut state).unwrap();
         // Request a file.
-        request_file(Principal::anonymous(), "request3", &mut state);
+        let alias2 = request_file(Principal::anonymous(), "request3", &mut state).unwrap();
         // Request a file.
-        request_file(Principal::anonymous(), "request4", &mut state);
+        request_file(Principal::anonymous(), "request4", &mut state).unwrap();
 
         // Upload a file with file ID of zero.
         let _alias0 = upload_file(
             0,
+            alias0,
             vec![1, 2, 3],
             "jpeg".to_string(),
             // Removed owner_key parameter as it's not needed for vetkd
             // vec![1, 2, 3],
             1,
+            Some(3),
+            Some(3),
             &mut state,
         );
         // share file with ID 0
@@ -184,11 +302,14 @@ mod test {
         // Upload a file with file ID 2
         let _alias2 = upload_file(
             2,
+            alias2,
             vec![1, 2, 3],
             "jpeg".to_string(),
             // Removed owner_key parameter as it's not needed for vetkd
             // vec![1, 2, 3],
             1,
+            Some(3),
+            Some(3),
             &mut state,
         );
         // share file index 2
@@ -210,15 +331,20 @@ mod test {
                     file_name: "request".to_string(),
                     group_name: "group1".to_string(),
                     group_alias: Some("group_alias1".to_string()),
+                    folder_id: None,
+                    root_hash: Some(root_hash(&[sha256(&[1, 2, 3])])),
+                    due_at: None,
                     file_status: FileStatus::Uploaded {
                         uploaded_at: get_time(),
                         // Not needed as the user can derive their vetkey so we don't need to store it
                         // document_key: vec![1, 2, 3],
                     },
-                    shared_with: vec![PublicUser {
+                    shared_with: vec![PublicSharee {
                         username: "John".to_string(),
                         public_key: vec![1, 2, 3],
                         ic_principal: Principal::from_slice(&[0, 1, 2]),
+                        permission: SharePermission::Download,
+                        expires_at: None,
                     }]
                 },
                 PublicFileMetadata {
@@ -226,15 +352,20 @@ mod test {
                     file_name: "request3".to_string(),
                     group_name: "group3".to_string(),
                     group_alias: Some("group_alias3".to_string()),
+                    folder_id: None,
+                    root_hash: Some(root_hash(&[sha256(&[1, 2, 3])])),
+                    due_at: None,
                     file_status: FileStatus::Uploaded {
                         uploaded_at: get_time(),
                         // Not needed as the user can derive their vetkey so we don't need to store it
                         // document_key: vec![1, 2, 3],
                     },
-                    shared_with: vec![PublicUser {
+                    shared_with: vec![PublicSharee {
                         username: "John".to_string(),
                         public_key: vec![1, 2, 3],
                         ic_principal: Principal::from_slice(&[0, 1, 2]),
+                        permission: SharePermission::Download,
+                        expires_at: None,
                     }]
                 },
             ]
@@ -253,9 +384,9 @@ mod test {
         );
 
         // Request a file.
-        request_file(Principal::anonymous(), "request", &mut state);
+        request_file(Principal::anonymous(), "request", &mut state).unwrap();
         // Request a file.
-        request_file(Principal::anonymous(), "request2", &mut state);
+        request_file(Principal::anonymous(), "request2", &mut state).unwrap();
 
         // share file index 2, should not be allowed
         assert_eq!(
@@ -293,22 +424,25 @@ mod test {
         );
 
         // Request a file.
-        let _alias1 = request_file(Principal::anonymous(), "request", &mut state);
+        let alias0 = request_file(Principal::anonymous(), "request", &mut state).unwrap();
         // Request a file.
-        let _alias2 = request_file(Principal::anonymous(), "request2", &mut state);
+        let _alias1 = request_file(Principal::anonymous(), "request2", &mut state).unwrap();
         // Request a file.
-        let _alias3 = request_file(Principal::anonymous(), "request3", &mut state);
+        let alias2 = request_file(Principal::anonymous(), "request3", &mut state).unwrap();
         // Request a file.
-        let _alias4 = request_file(Principal::anonymous(), "request4", &mut state);
+        let _alias3 = request_file(Principal::anonymous(), "request4", &mut state).unwrap();
 
         // Upload a file with file ID of 0.
         let _alias0 = upload_file(
             0,
+            alias0,
             vec![1, 2, 3],
             "jpeg".to_string(),
             // Removed owner_key parameter as it's not needed for vetkd
             // vec![1, 2, 3],
             1,
+            Some(3),
+            Some(3),
             &mut state,
         );
         // share file index 0
@@ -323,11 +457,14 @@ mod test {
         // Upload a file with file ID of 2.
         let _alias2 = upload_file(
             2,
+            alias2,
             vec![1, 2, 3],
             "jpeg".to_string(),
             // Removed owner_key parameter as it's not needed for vetkd
             // vec![1, 2, 3],
             1,
+            Some(3),
+            Some(3),
             &mut state,
         );
         // share file index 2
@@ -356,15 +493,20 @@ mod test {
                 file_name: "request3".to_string(),
                 group_name: "group3".to_string(),
                 group_alias: Some("group_alias3".to_string()),
+                folder_id: None,
+                root_hash: Some(root_hash(&[sha256(&[1, 2, 3])])),
+                due_at: None,
                 file_status: FileStatus::Uploaded {
                     uploaded_at: get_time(),
                     // Not needed as the user can derive their vetkey so we don't need to store it
                     // document_key: vec![1, 2, 3],
                 },
-                shared_with: vec![PublicUser {
+                shared_with: vec![PublicSharee {
                     username: "John".to_string(),
                     public_key: vec![1, 2, 3],
                     ic_principal: Principal::from_slice(&[0, 1, 2]),
+                    permission: SharePermission::Download,
+                    expires_at: None,
                 }]
             },]
         );
@@ -382,9 +524,9 @@ mod test {
         );
 
         // Request a file.
-        request_file(Principal::anonymous(), "request", &mut state);
+        request_file(Principal::anonymous(), "request", &mut state).unwrap();
         // Request a file.
-        request_file(Principal::anonymous(), "request2", &mut state);
+        request_file(Principal::anonymous(), "request2", &mut state).unwrap();
 
         // revoke share of file index 2 should not be allowed
         assert_eq!(
@@ -410,9 +552,9 @@ mod test {
         );
 
         // Request a file.
-        request_file(Principal::anonymous(), "request", &mut state);
+        request_file(Principal::anonymous(), "request", &mut state).unwrap();
         // Request a file.
-        request_file(Principal::anonymous(), "request2", &mut state);
+        request_file(Principal::anonymous(), "request2", &mut state).unwrap();
 
         // share file index 0
         share_file(
@@ -435,4 +577,377 @@ mod test {
             FileSharingResponse::PermissionError
         );
     }
+
+    #[test]
+    fn permissions_limit_what_sharees_can_do() {
+        use crate::api::{
+            delete_file, download_file, rename_file, reserve_file_id, upload_file_atomic,
+        };
+        use crate::{api::UploadFileAtomicRequest, FileDownloadResponse};
+
+        let mut state = State::default();
+        let owner = Principal::from_slice(&[0, 1, 1]);
+        let viewer = Principal::from_slice(&[0, 1, 2]);
+        let resharer = Principal::from_slice(&[0, 1, 3]);
+        let manager = Principal::from_slice(&[0, 1, 4]);
+        for (user, name) in [
+            (owner, "owner"),
+            (viewer, "viewer"),
+            (resharer, "resharer"),
+            (manager, "manager"),
+        ] {
+            set_user_info(
+                &mut state,
+                user,
+                User {
+                    username: name.to_string(),
+                    public_key: vec![1, 2, 3],
+                },
+            );
+        }
+        let file_id = upload_file_atomic(
+            owner,
+            UploadFileAtomicRequest {
+                name: "file".to_string(),
+                content: vec![1, 2, 3],
+                file_type: "jpeg".to_string(),
+                num_chunks: 1,
+                total_size: Some(3),
+                chunk_size: Some(3),
+                file_id: reserve_file_id(&mut state, owner).unwrap().file_id,
+            },
+            &mut state,
+        )
+        .unwrap();
+        let share = |state: &mut State, caller, user, permission| {
+            share_file_with_permission(state, caller, user, file_id, permission, None)
+        };
+
+        assert_eq!(
+            share(&mut state, owner, viewer, SharePermission::View),
+            FileSharingResponse::Ok
+        );
+        assert_eq!(
+            download_file(&state, file_id, 0, viewer),
+            FileDownloadResponse::PermissionError
+        );
+        assert_eq!(
+            share(&mut state, viewer, resharer, SharePermission::View),
+            FileSharingResponse::PermissionError
+        );
+
+        // Resharers can grant up to their own permission.
+        assert_eq!(
+            share(&mut state, owner, resharer, SharePermission::Reshare),
+            FileSharingResponse::Ok
+        );
+        assert_eq!(
+            share(&mut state, resharer, manager, SharePermission::Manage),
+            FileSharingResponse::PermissionError
+        );
+        assert_eq!(
+            share(&mut state, resharer, viewer, SharePermission::Download),
+            FileSharingResponse::Ok
+        );
+        assert!(matches!(
+            download_file(&state, file_id, 0, viewer),
+            FileDownloadResponse::FoundFile(_)
+        ));
+        assert_eq!(
+            rename_file(&mut state, resharer, file_id, "renamed".to_string()),
+            FileSharingResponse::PermissionError
+        );
+
+        // Managers can't be changed by resharers, but can rename, revoke and delete.
+        assert_eq!(
+            share(&mut state, owner, manager, SharePermission::Manage),
+            FileSharingResponse::Ok
+        );
+        assert_eq!(
+            share(&mut state, resharer, manager, SharePermission::View),
+            FileSharingResponse::PermissionError
+        );
+        assert_eq!(
+            get_public_file_metadata(&state, file_id)
+                .shared_with
+                .into_iter()
+                .map(|sharee| (sharee.username, sharee.permission))
+                .collect::<Vec<_>>(),
+            vec![
+                ("viewer".to_string(), SharePermission::Download),
+                ("resharer".to_string(), SharePermission::Reshare),
+                ("manager".to_string(), SharePermission::Manage),
+            ]
+        );
+        assert_eq!(
+            rename_file(&mut state, manager, file_id, "renamed".to_string()),
+            FileSharingResponse::Ok
+        );
+        assert_eq!(
+            revoke_share(&mut state, manager, viewer, file_id),
+            FileSharingResponse::Ok
+        );
+        assert_eq!(
+            delete_file(&mut state, manager, file_id),
+            FileSharingResponse::Ok
+        );
+        assert!(state.owned_files(owner).is_empty());
+    }
+
+    #[test]
+    fn shares_without_a_permission_can_download() {
+        use ic_stable_structures::Storable;
+        use std::borrow::Cow;
+
+        assert_eq!(
+            Share::from_bytes(Cow::Borrowed(&[])),
+            Share {
+                permission: SharePermission::Download,
+                expires_at: None,
+            }
+        );
+        // Shares that were stored before they could expire.
+        assert_eq!(
+            Share::from_bytes(Cow::Borrowed(&[3])),
+            Share {
+                permission: SharePermission::Manage,
+                expires_at: None,
+            }
+        );
+        for permission in [
+            SharePermission::View,
+            SharePermission::Download,
+            SharePermission::Reshare,
+            SharePermission::Manage,
+        ] {
+            for expires_at in [None, Some(u64::MAX)] {
+                let share = Share {
+                    permission,
+                    expires_at,
+                };
+                assert_eq!(Share::from_bytes(share.to_bytes()), share);
+            }
+        }
+    }
+
+    #[test]
+    fn shares_expire() {
+        use crate::api::{
+            download_file, reserve_file_id, upload_file_atomic, UploadFileAtomicRequest,
+        };
+        use crate::FileDownloadResponse;
+
+        let mut state = State::default();
+        let owner = Principal::from_slice(&[0, 1, 1]);
+        let auditor = Principal::from_slice(&[0, 1, 2]);
+        let colleague = Principal::from_slice(&[0, 1, 3]);
+        for user in [owner, auditor, colleague] {
+            set_user_info(
+                &mut state,
+                user,
+                User {
+                    username: "John".to_string(),
+                    public_key: vec![1, 2, 3],
+                },
+            );
+        }
+        let file_id = upload_file_atomic(
+            owner,
+            UploadFileAtomicRequest {
+                name: "file".to_string(),
+                content: vec![1, 2, 3],
+                file_type: "jpeg".to_string(),
+                num_chunks: 1,
+                total_size: Some(3),
+                chunk_size: Some(3),
+                file_id: reserve_file_id(&mut state, owner).unwrap().file_id,
+            },
+            &mut state,
+        )
+        .unwrap();
+        let now = get_time();
+
+        share_file_with_permission(
+            &mut state,
+            owner,
+            auditor,
+            file_id,
+            SharePermission::Reshare,
+            Some(now + 1),
+        );
+        // Reshares don't outlast the share they were made from.
+        share_file_with_permission(
+            &mut state,
+            auditor,
+            colleague,
+            file_id,
+            SharePermission::Download,
+            None,
+        );
+        assert_eq!(
+            get_shared_files(&state, colleague)[0]
+                .shared_with
+                .iter()
+                .map(|sharee| sharee.expires_at)
+                .collect::<Vec<_>>(),
+            vec![Some(now + 1), Some(now + 1)]
+        );
+        assert!(matches!(
+            download_file(&state, file_id, 0, colleague),
+            FileDownloadResponse::FoundFile(_)
+        ));
+
+        // Expired shares can't be used before they are cleaned up.
+        share_file_with_permission(
+            &mut state,
+            owner,
+            colleague,
+            file_id,
+            SharePermission::Download,
+            Some(now),
+        );
+        assert_eq!(
+            download_file(&state, file_id, 0, colleague),
+            FileDownloadResponse::PermissionError
+        );
+        assert_eq!(get_shared_files(&state, colleague), vec![]);
+
+        assert_eq!(expire_shares(&mut state, now), 1);
+        assert_eq!(state.indexes.sharees(file_id), vec![auditor]);
+        state.check_indexes();
+        // The former sharee held the file's key.
+        assert!(state
+            .file_data
+            .get(&file_id)
+            .unwrap()
+            .metadata
+            .pending_key_version
+            .is_some());
+    }
+
+    #[test]
+    fn batch_shares_report_each_recipient() {
+        use crate::api::{reserve_file_id, upload_file_atomic, UploadFileAtomicRequest};
+
+        let mut state = State::default();
+        let owner = Principal::from_slice(&[0, 1, 1]);
+        let sharee = Principal::from_slice(&[0, 1, 2]);
+        let stranger = Principal::from_slice(&[0, 1, 3]);
+        for user in [owner, sharee] {
+            set_user_info(
+                &mut state,
+                user,
+                User {
+                    username: "John".to_string(),
+                    public_key: vec![1, 2, 3],
+                },
+            );
+        }
+        let file_id = upload_file_atomic(
+            owner,
+            UploadFileAtomicRequest {
+                name: "file".to_string(),
+                content: vec![1, 2, 3],
+                file_type: "jpeg".to_string(),
+                num_chunks: 1,
+                total_size: Some(3),
+                chunk_size: Some(3),
+                file_id: reserve_file_id(&mut state, owner).unwrap().file_id,
+            },
+            &mut state,
+        )
+        .unwrap();
+        let missing_file_id = file_id + 1;
+        let result = |file_id, user_id, result| ShareResult {
+            file_id,
+            user_id,
+            result,
+        };
+
+        assert_eq!(
+            share_files(
+                &mut state,
+                owner,
+                ShareFilesRequest {
+                    file_ids: vec![file_id, missing_file_id],
+                    user_ids: vec![sharee, stranger],
+                    permission: None,
+                    expires_at: None,
+                },
+            ),
+            vec![
+                result(file_id, sharee, FileSharingResponse::Ok),
+                result(file_id, stranger, FileSharingResponse::UnknownUser),
+                result(
+                    missing_file_id,
+                    sharee,
+                    FileSharingResponse::PermissionError
+                ),
+                result(missing_file_id, stranger, FileSharingResponse::UnknownUser),
+            ]
+        );
+        assert_eq!(state.indexes.sharees(file_id), vec![sharee]);
+        assert!(state.has_permission(sharee, file_id, SharePermission::Download));
+
+        assert_eq!(
+            revoke_shares(&mut state, owner, vec![file_id], vec![sharee, stranger]),
+            vec![
+                result(file_id, sharee, FileSharingResponse::Ok),
+                result(file_id, stranger, FileSharingResponse::UnknownUser),
+            ]
+        );
+        assert!(state.indexes.sharees(file_id).is_empty());
+    }
+
+    #[test]
+    fn shares_are_expired_in_batches() {
+        use crate::api::{reserve_file_id, upload_file_atomic, UploadFileAtomicRequest};
+
+        let mut state = State::default();
+        let owner = Principal::from_slice(&[0, 1, 1]);
+        let sharees: Vec<_> = (0..=MAX_EXPIRED_SHARES as u8)
+            .map(|i| Principal::from_slice(&[1, i]))
+            .collect();
+        for (i, user) in std::iter::once(owner).chain(sharees.clone()).enumerate() {
+            set_user_info(
+                &mut state,
+                user,
+                User {
+                    username: format!("user{i}"),
+                    public_key: vec![1, 2, 3],
+                },
+            );
+        }
+        let file_id = upload_file_atomic(
+            owner,
+            UploadFileAtomicRequest {
+                name: "file".to_string(),
+                content: vec![1, 2, 3],
+                file_type: "jpeg".to_string(),
+                num_chunks: 1,
+                total_size: Some(3),
+                chunk_size: Some(3),
+                file_id: reserve_file_id(&mut state, owner).unwrap().file_id,
+            },
+            &mut state,
+        )
+        .unwrap();
+        let now = get_time();
+        for sharee in sharees {
+            share_file_with_permission(
+                &mut state,
+                owner,
+                sharee,
+                file_id,
+                SharePermission::View,
+                Some(now),
+            );
+        }
+
+        assert_eq!(expire_shares(&mut state, now), MAX_EXPIRED_SHARES);
+        assert_eq!(expire_shares(&mut state, now), 1);
+        assert_eq!(expire_shares(&mut state, now), 0);
+        assert!(state.file_shares.is_empty());
+        state.check_indexes();
+    }
 }
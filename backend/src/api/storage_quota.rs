@@ -0,0 +1,261 @@
+use super::admin::authorize_admin;
+use crate::{AdminError, State, StorageQuota, StorageUsageResponse, UploadFileError};
+use candid::Principal;
+
+pub fn get_storage_usage(state: &State, caller: Principal) -> StorageUsageResponse {
+    StorageUsageResponse {
+        usage: state.storage_usage.get(&caller).unwrap_or_default(),
+        quota: quota_of(state, caller),
+    }
+}
+
+/// Gives a user their own quota, or the default quota again if `quota` is `None`.
+pub fn set_user_quota(
+    state: &mut State,
+    caller: Principal,
+    user: Principal,
+    quota: Option<StorageQuota>,
+) -> Result<(), AdminError> {
+    authorize_admin(state, caller)?;
+    match quota {
+        Some(quota) => state.storage_quotas.insert(user, quota),
+        None => state.storage_quotas.remove(&user),
+    };
+    Ok(())
+}
+
+pub fn set_default_quota(
+    state: &mut State,
+    caller: Principal,
+    quota: StorageQuota,
+) -> Result<(), AdminError> {
+    authorize_admin(state, caller)?;
+    state.default_quota = quota;
+    Ok(())
+}
+
+fn quota_of(state: &State, user: Principal) -> StorageQuota {
+    state
+        .storage_quotas
+        .get(&user)
+        .unwrap_or_else(|| state.default_quota.clone())
+}
+
+/// Checks that the user can store `bytes` more bytes in `files` more files.
+pub(crate) fn check_quota(
+    state: &State,
+    user: Principal,
+    bytes: u64,
+    files: u64,
+) -> Result<(), UploadFileError> {
+    let usage = state.storage_usage.get(&user).unwrap_or_default();
+    let quota = quota_of(state, user);
+    if usage.bytes.saturating_add(bytes) > quota.max_bytes
+        || usage.file_count.saturating_add(files) > quota.max_files
+    {
+        return Err(UploadFileError::QuotaExceeded);
+    }
+    Ok(())
+}
+
+/// Counts stored bytes and files against the user's quota.
+pub(crate) fn charge_storage(state: &mut State, user: Principal, bytes: u64, files: u64) {
+    let mut usage = state.storage_usage.get(&user).unwrap_or_default();
+    usage.bytes += bytes;
+    usage.file_count += files;
+    state.storage_usage.insert(user, usage);
+}
+
+/// Gives back storage that was counted against the user's quota.
+pub(crate) fn release_storage(state: &mut State, user: Principal, bytes: u64, files: u64) {
+    let mut usage = state.storage_usage.get(&user).unwrap_or_default();
+    usage.bytes = usage.bytes.saturating_sub(bytes);
+    usage.file_count = usage.file_count.saturating_sub(files);
+    state.storage_usage.insert(user, usage);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::{
+            delete_file, request_file, reserve_file_id, set_user_info, upload_file,
+            upload_file_atomic, upload_file_continue, UploadFileAtomicRequest, MIN_CHUNK_SIZE,
+        },
+        StorageUsage, UploadFileContinueRequest, User,
+    };
+
+    const ADMIN: Principal = Principal::from_slice(&[0, 1, 2]);
+    const USER: Principal = Principal::from_slice(&[0, 1, 3]);
+
+    fn setup() -> State {
+        let mut state = State::default();
+        state.admins.insert(ADMIN, ());
+        set_user_info(
+            &mut state,
+            USER,
+            User {
+                username: "John".to_string(),
+                public_key: vec![1, 2, 3],
+            },
+        );
+        state
+    }
+
+    fn upload(state: &mut State, num_chunks: u64, total_size: u64) -> Result<u64, UploadFileError> {
+        upload_file_atomic(
+            USER,
+            UploadFileAtomicRequest {
+                name: "file_name".to_string(),
+                content: vec![0; total_size.min(MIN_CHUNK_SIZE) as usize],
+                file_type: "image/jpeg".to_string(),
+                num_chunks,
+                total_size: Some(total_size),
+                chunk_size: Some(MIN_CHUNK_SIZE),
+                file_id: reserve_file_id(state, USER).unwrap().file_id,
+            },
+            state,
+        )
+    }
+
+    #[test]
+    fn only_admins_can_set_quotas() {
+        let mut state = setup();
+
+        assert_eq!(
+            set_default_quota(&mut state, USER, StorageQuota::default()),
+            Err(AdminError::PermissionError)
+        );
+        assert_eq!(
+            set_user_quota(&mut state, USER, USER, None),
+            Err(AdminError::PermissionError)
+        );
+    }
+
+    #[test]
+    fn uploads_are_counted_and_released() {
+        let mut state = setup();
+        let owner = USER;
+
+        let file_id = upload(&mut state, 2, MIN_CHUNK_SIZE + 2).unwrap();
+        upload_file_continue(
+            UploadFileContinueRequest {
+                file_id,
+                chunk_id: 1,
+                contents: vec![0, 0],
+                sha256: None,
+                alias: None,
+            },
+            owner,
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            get_storage_usage(&state, owner).usage,
+            StorageUsage {
+                bytes: MIN_CHUNK_SIZE + 2,
+                file_count: 1,
+            }
+        );
+
+        delete_file(&mut state, owner, file_id);
+        assert_eq!(
+            get_storage_usage(&state, owner).usage,
+            StorageUsage::default()
+        );
+    }
+
+    #[test]
+    fn uploads_over_the_quota_are_rejected() {
+        let mut state = setup();
+        set_default_quota(
+            &mut state,
+            ADMIN,
+            StorageQuota {
+                max_bytes: MIN_CHUNK_SIZE,
+                max_files: 2,
+            },
+        )
+        .unwrap();
+
+        // The declared size of the file counts, not just its first chunk.
+        assert_eq!(
+            upload(&mut state, 2, MIN_CHUNK_SIZE + 1),
+            Err(UploadFileError::QuotaExceeded)
+        );
+        upload(&mut state, 1, 3).unwrap();
+        upload(&mut state, 1, 3).unwrap();
+        assert_eq!(
+            upload(&mut state, 1, 3),
+            Err(UploadFileError::QuotaExceeded)
+        );
+
+        // A user's own quota takes precedence over the default quota.
+        set_user_quota(
+            &mut state,
+            ADMIN,
+            USER,
+            Some(StorageQuota {
+                max_bytes: 100,
+                max_files: 100,
+            }),
+        )
+        .unwrap();
+        upload(&mut state, 1, 3).unwrap();
+        set_user_quota(&mut state, ADMIN, USER, None).unwrap();
+        assert_eq!(
+            upload(&mut state, 1, 3),
+            Err(UploadFileError::QuotaExceeded)
+        );
+    }
+
+    #[test]
+    fn request_uploads_count_against_the_requester() {
+        let mut state = setup();
+        let requester = USER;
+        let alias = request_file(requester, "request", &mut state).unwrap();
+        set_user_quota(
+            &mut state,
+            ADMIN,
+            requester,
+            Some(StorageQuota {
+                max_bytes: 2,
+                max_files: 1,
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(
+            upload_file(
+                0,
+                alias.clone(),
+                vec![1, 2, 3],
+                "jpeg".to_string(),
+                1,
+                Some(3),
+                Some(3),
+                &mut state
+            ),
+            Err(UploadFileError::QuotaExceeded)
+        );
+        set_user_quota(&mut state, ADMIN, requester, None).unwrap();
+        upload_file(
+            0,
+            alias,
+            vec![1, 2, 3],
+            "jpeg".to_string(),
+            1,
+            Some(3),
+            Some(3),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            get_storage_usage(&state, requester).usage,
+            StorageUsage {
+                bytes: 3,
+                file_count: 1,
+            }
+        );
+    }
+}
@@ -1,6 +1,5 @@
 use crate::{GetAliasInfoError, State, Template};
 use candid::Principal;
-use std::collections::BTreeMap;
 
 pub fn save_template(
     state: &mut State,
@@ -8,13 +7,13 @@ pub fn save_template(
     name: String,
     file_names: Vec<String>,
 ) -> Result<(), GetAliasInfoError> {
-    let user_templates = state
-        .user_templates
-        .entry(caller)
-        .or_insert_with(BTreeMap::new);
+    let mut user_templates = state.user_templates.get(&caller).unwrap_or_default();
 
     // Clone the name before moving it into the map key
-    user_templates.insert(name.clone(), Template { name, file_names });
+    user_templates
+        .0
+        .insert(name.clone(), Template { name, file_names });
+    state.user_templates.insert(caller, user_templates);
     Ok(())
 }
 
@@ -22,7 +21,7 @@ pub fn get_user_templates(state: &State, caller: Principal) -> Vec<Template> {
     state
         .user_templates
         .get(&caller)
-        .map(|templates| templates.values().cloned().collect())
+        .map(|templates| templates.0.into_values().collect())
         .unwrap_or_default()
 }
 
@@ -34,8 +33,7 @@ pub fn get_template(
     state
         .user_templates
         .get(&caller)
-        .and_then(|templates| templates.get(&name))
-        .cloned()
+        .and_then(|mut templates| templates.0.remove(&name))
         .ok_or(GetAliasInfoError::NotFound)
 }
 
@@ -44,10 +42,14 @@ pub fn delete_template(
     caller: Principal,
     name: String,
 ) -> Result<(), GetAliasInfoError> {
-    state
+    let mut user_templates = state
         .user_templates
-        .get_mut(&caller)
-        .and_then(|templates| templates.remove(&name))
-        .map(|_| ())
-        .ok_or(GetAliasInfoError::NotFound)
+        .get(&caller)
+        .ok_or(GetAliasInfoError::NotFound)?;
+    user_templates
+        .0
+        .remove(&name)
+        .ok_or(GetAliasInfoError::NotFound)?;
+    state.user_templates.insert(caller, user_templates);
+    Ok(())
 }
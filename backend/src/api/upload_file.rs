@@ -1,17 +1,47 @@
+use super::request_alias::is_expired;
+use super::storage_quota::{charge_storage, check_quota};
+use super::upload_session::start_session;
 use crate::{get_time, FileContent, State, UploadFileError};
 // Not used as we aren't storing encrypted_keys while sharing anymore
 // use std::collections::BTreeMap;
 
+/// Checks that the uploader knows the secret alias of the requested file,
+/// either its own alias or the alias of its request group.
+pub(crate) fn authorize_alias(
+    state: &State,
+    file_id: u64,
+    file_alias: &str,
+    alias: &str,
+) -> Result<(), UploadFileError> {
+    let is_file_alias = !file_alias.is_empty() && alias == file_alias;
+    let is_group_alias = state
+        .indexes
+        .file_group
+        .get(&file_id)
+        .is_some_and(|group_id| state.group_alias_index.get(&alias.to_string()) == Some(group_id));
+    if is_file_alias || is_group_alias {
+        Ok(())
+    } else {
+        Err(UploadFileError::AliasMismatch)
+    }
+}
+
+/// Uploads the first chunk of a requested file. Only whoever knows the
+/// request's alias can upload it.
+#[allow(clippy::too_many_arguments)]
 pub fn upload_file(
     file_id: u64,
+    alias: String,
     contents: Vec<u8>,
     file_type: String,
     // _owner_key: Vec<u8>,
     num_chunks: u64,
+    total_size: Option<u64>,
+    chunk_size: Option<u64>,
     state: &mut State,
 ) -> Result<(), UploadFileError> {
     // Fetch the file.
-    let file = match state.file_data.get_mut(&file_id) {
+    let mut file = match state.file_data.get(&file_id) {
         Some(file) => file,
         None => return Err(UploadFileError::NotRequested),
     };
@@ -20,8 +50,24 @@ pub fn upload_file(
     // let shared_keys = BTreeMap::new();
     // Retrieve the alias associated with the file.
     let alias = match file.content {
-        FileContent::Pending { ref alias } => {
-            let alias = alias.clone();
+        FileContent::Pending {
+            alias: ref file_alias,
+        } => {
+            authorize_alias(state, file_id, file_alias, &alias)?;
+            if is_expired(file.metadata.alias_expires_at, get_time()) {
+                return Err(UploadFileError::AliasExpired);
+            }
+            let alias = file_alias.clone();
+            let session = start_session(
+                num_chunks,
+                total_size,
+                chunk_size,
+                &contents,
+                Some(alias.clone()),
+            )?;
+            // The upload counts against the quota of whoever requested the file.
+            let requester = file.metadata.requester_principal;
+            check_quota(state, requester, session.max_size(), 1)?;
             if num_chunks == 1 {
                 file.content = FileContent::Uploaded {
                     file_type,
@@ -46,7 +92,14 @@ pub fn upload_file(
 
             // Add file contents to stable store.
             let chunk_id = 0;
+            charge_storage(state, requester, contents.len() as u64, 1);
             state.file_contents.insert((file_id, chunk_id), contents);
+            if num_chunks == 1 {
+                state.hash_file_contents(file_id, &mut file.metadata);
+            } else {
+                state.insert_upload_session(file_id, session);
+            }
+            state.file_data.insert(file_id, file);
 
             alias
         }
@@ -70,11 +123,18 @@ pub fn upload_file(
 mod test {
     use super::*;
     use crate::{
-        api::{request_file, set_user_info, user_info::get_user_key},
-        File, FileMetadata, User,
+        api::{
+            multi_request, request_file, reserve_file_id, set_user_info, upload_file_atomic,
+            upload_file_continue, user_info::get_user_key, UploadFileAtomicRequest, MIN_CHUNK_SIZE,
+        },
+        root_hash, sha256, File, FileMetadata, KeyDerivation, MultiRequestInput,
+        UploadFileContinueRequest, User,
     };
     use candid::Principal;
     use maplit::btreemap;
+    use std::collections::BTreeMap;
+
+    const CHUNK: usize = MIN_CHUNK_SIZE as usize;
 
     #[test]
     fn stored_file_in_state() {
@@ -90,7 +150,7 @@ mod test {
         );
 
         // Request a file.
-        request_file(Principal::anonymous(), "request", &mut state);
+        let alias = request_file(Principal::anonymous(), "request", &mut state).unwrap();
 
         // The alias index is not empty.
         assert!(!state.file_alias_index.is_empty());
@@ -99,17 +159,20 @@ mod test {
         let file_id = 0;
         let _alias = upload_file(
             file_id,
+            alias,
             vec![1, 2, 3],
             "jpeg".to_string(),
             // Removed owner_key parameter as it's not needed for vetkd
             // vec![1, 2, 3],
             1,
+            Some(3),
+            Some(3),
             &mut state,
         );
 
         // The file is stored in the state.
         assert_eq!(
-            state.file_data,
+            state.file_data.iter().collect::<BTreeMap<_, _>>(),
             btreemap! {
                 file_id => File {
                     metadata: FileMetadata {
@@ -118,6 +181,13 @@ mod test {
                         requester_principal: Principal::anonymous(),
                         requested_at: get_time(),
                         uploaded_at: Some(get_time()),
+                        folder_id: None,
+                        chunk_hashes: vec![sha256(&[1, 2, 3])],
+                        root_hash: Some(root_hash(&[sha256(&[1, 2, 3])])),
+                        key_derivation: KeyDerivation::PerFile { key_version: 0 },
+                        pending_key_version: None,
+                        alias_expires_at: None,
+                        due_at: None,
                     },
                     content: FileContent::Uploaded {
                         file_type: "jpeg".to_string(),
@@ -135,4 +205,128 @@ mod test {
         // The alias index is empty.
         assert!(state.file_alias_index.is_empty());
     }
+
+    #[test]
+    fn uploads_need_the_alias_of_the_request() {
+        let mut state = State::default();
+        let requester = Principal::anonymous();
+        let uploader = Principal::from_slice(&[0, 1, 2]);
+        set_user_info(
+            &mut state,
+            requester,
+            User {
+                username: "John".to_string(),
+                public_key: vec![1, 2, 3],
+            },
+        );
+        let alias = request_file(requester, "request", &mut state).unwrap();
+        let group = multi_request(
+            requester,
+            MultiRequestInput {
+                group_name: "group".to_string(),
+                file_names: vec!["a".to_string()],
+                save_as_template: false,
+                expires_at: None,
+                due_at: None,
+            },
+            &mut state,
+        )
+        .unwrap();
+
+        // Another request's alias doesn't grant access.
+        assert_eq!(
+            upload_file(
+                1,
+                alias.clone(),
+                vec![1, 2, 3],
+                "jpeg".to_string(),
+                1,
+                Some(3),
+                Some(3),
+                &mut state
+            ),
+            Err(UploadFileError::AliasMismatch)
+        );
+        // Files of a request group can be uploaded with the group's alias.
+        upload_file(
+            1,
+            group.group_alias,
+            vec![1, 2, 3],
+            "jpeg".to_string(),
+            1,
+            Some(3),
+            Some(3),
+            &mut state,
+        )
+        .unwrap();
+
+        upload_file(
+            0,
+            alias.clone(),
+            vec![1; CHUNK],
+            "jpeg".to_string(),
+            2,
+            Some(2 * MIN_CHUNK_SIZE),
+            Some(MIN_CHUNK_SIZE),
+            &mut state,
+        )
+        .unwrap();
+        let continue_request = |alias: &str| UploadFileContinueRequest {
+            file_id: 0,
+            chunk_id: 1,
+            contents: vec![4; CHUNK],
+            sha256: None,
+            alias: Some(alias.to_string()),
+        };
+        assert_eq!(
+            upload_file_continue(continue_request("wrong"), uploader, &mut state),
+            Err(UploadFileError::AliasMismatch)
+        );
+        upload_file_continue(continue_request(&alias), uploader, &mut state).unwrap();
+    }
+
+    #[test]
+    fn only_owners_continue_their_uploads() {
+        let mut state = State::default();
+        let owner = Principal::from_slice(&[0, 1, 1]);
+        set_user_info(
+            &mut state,
+            owner,
+            User {
+                username: "John".to_string(),
+                public_key: vec![1, 2, 3],
+            },
+        );
+        let file_id = upload_file_atomic(
+            owner,
+            UploadFileAtomicRequest {
+                name: "file_name".to_string(),
+                content: vec![1; CHUNK],
+                file_type: "image/jpeg".to_string(),
+                num_chunks: 2,
+                total_size: Some(2 * MIN_CHUNK_SIZE),
+                chunk_size: Some(MIN_CHUNK_SIZE),
+                file_id: reserve_file_id(&mut state, owner).unwrap().file_id,
+            },
+            &mut state,
+        )
+        .unwrap();
+        let continue_request = UploadFileContinueRequest {
+            file_id,
+            chunk_id: 1,
+            contents: vec![4; CHUNK],
+            sha256: None,
+            alias: None,
+        };
+
+        assert_eq!(
+            upload_file_continue(
+                continue_request.clone(),
+                Principal::from_slice(&[0, 1, 2]),
+                &mut state
+            ),
+            Err(UploadFileError::PermissionError)
+        );
+        upload_file_continue(continue_request, owner, &mut state).unwrap();
+    }
 }
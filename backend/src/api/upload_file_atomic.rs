@@ -1,11 +1,21 @@
-use crate::{get_time, File, FileContent, FileMetadata, State};
+use crate::{
+    file_derivation_id, get_time, File, FileContent, FileKeyInfo, FileMetadata, KeyDerivation,
+    State, UploadFileError,
+};
 use candid::CandidType;
 use candid::Principal;
 use serde::{Deserialize, Serialize};
 // Not used as we aren't storing encrypted_keys while sharing anymore
 // use std::collections::BTreeMap;
 
-use super::user_info::get_user_key;
+use super::{
+    storage_quota::{charge_storage, check_quota},
+    upload_session::{start_session, UPLOAD_SESSION_TTL},
+    user_info::get_user_key,
+};
+
+/// How many file ids a user can have reserved at once.
+pub const MAX_RESERVED_FILE_IDS: usize = 100;
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct UploadFileAtomicRequest {
@@ -14,14 +24,76 @@ pub struct UploadFileAtomicRequest {
     // pub owner_key: Vec<u8>,
     pub file_type: String,
     pub num_chunks: u64,
+    pub total_size: Option<u64>,
+    pub chunk_size: Option<u64>,
+    /// The file id reserved with `reserve_file_id`, whose per-file key the file
+    /// is encrypted for.
+    pub file_id: u64,
+}
+
+/// Reserves a file id for the caller, so that they can encrypt a file for its
+/// per-file key before uploading it with `upload_file_atomic`. Reservations
+/// that aren't used expire like abandoned uploads.
+pub fn reserve_file_id(
+    state: &mut State,
+    caller: Principal,
+) -> Result<FileKeyInfo, UploadFileError> {
+    if caller == Principal::anonymous() {
+        return Err(UploadFileError::PermissionError);
+    }
+    let reserved = state
+        .reserved_file_ids
+        .range((caller, 0)..=(caller, u64::MAX))
+        .take(MAX_RESERVED_FILE_IDS)
+        .count();
+    if reserved >= MAX_RESERVED_FILE_IDS {
+        return Err(UploadFileError::QuotaExceeded);
+    }
+
+    let file_id = state.generate_file_id();
+    state
+        .reserved_file_ids
+        .insert((caller, file_id), get_time());
+    Ok(FileKeyInfo {
+        file_id,
+        derivation_id: file_derivation_id(caller, file_id, 0),
+        key_derivation: KeyDerivation::PerFile { key_version: 0 },
+        reencryption_derivation_id: None,
+    })
+}
+
+/// Drops the reservations made before `now - UPLOAD_SESSION_TTL` and returns
+/// how many were dropped.
+pub fn expire_reserved_file_ids(state: &mut State, now: u64) -> usize {
+    let expired: Vec<_> = state
+        .reserved_file_ids
+        .iter()
+        .filter(|(_, reserved_at)| reserved_at.saturating_add(UPLOAD_SESSION_TTL) <= now)
+        .map(|(key, _)| key)
+        .collect();
+    for key in &expired {
+        state.reserved_file_ids.remove(key);
+    }
+    expired.len()
 }
 
 pub fn upload_file_atomic(
     caller: Principal,
     request: UploadFileAtomicRequest,
     state: &mut State,
-) -> u64 {
-    let file_id = state.generate_file_id();
+) -> Result<u64, UploadFileError> {
+    let session = start_session(
+        request.num_chunks,
+        request.total_size,
+        request.chunk_size,
+        &request.content,
+        None,
+    )?;
+    check_quota(state, caller, session.max_size(), 1)?;
+    let file_id = request.file_id;
+    if state.reserved_file_ids.remove(&(caller, file_id)).is_none() {
+        return Err(UploadFileError::NotRequested);
+    }
 
     let content = if request.num_chunks == 1 {
         // File is uploaded in one chunk.
@@ -43,45 +115,56 @@ pub fn upload_file_atomic(
         }
     };
 
+    if request.num_chunks > 1 {
+        state.insert_upload_session(file_id, session);
+    }
+
     // Add file contents to stable store.
     let chunk_id = 0;
+    charge_storage(state, caller, request.content.len() as u64, 1);
     state
         .file_contents
         .insert((file_id, chunk_id), request.content);
 
-    let old_value = state.file_data.insert(
-        file_id,
-        File {
-            metadata: FileMetadata {
-                file_name: request.name,
-                user_public_key: get_user_key(state, caller),
-                requester_principal: caller,
-                requested_at: get_time(),
-                uploaded_at: Some(get_time()),
-            },
-            content,
-        },
-    );
+    let mut metadata = FileMetadata {
+        file_name: request.name,
+        user_public_key: get_user_key(state, caller),
+        requester_principal: caller,
+        requested_at: get_time(),
+        uploaded_at: Some(get_time()),
+        folder_id: None,
+        chunk_hashes: vec![],
+        root_hash: None,
+        key_derivation: KeyDerivation::PerFile { key_version: 0 },
+        pending_key_version: None,
+        alias_expires_at: None,
+        due_at: None,
+    };
+    if request.num_chunks == 1 {
+        state.hash_file_contents(file_id, &mut metadata);
+    }
+
+    let old_value = state.file_data.insert(file_id, File { metadata, content });
 
     if old_value.is_some() {
         panic!("Overwriting an existing file should be impossible.");
     }
 
     // Add the caller as the owner of this file.
-    state
-        .file_owners
-        .entry(caller)
-        .or_insert_with(Vec::new)
-        .push(file_id);
+    state.file_owners.insert((caller, file_id), ());
+    state.indexes.add_file(caller, None, file_id);
 
-    file_id
+    Ok(file_id)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{api::set_user_info, File, FileMetadata, User};
+    use crate::{api::set_user_info, root_hash, sha256, File, FileMetadata, User};
     use maplit::btreemap;
+    use std::collections::BTreeMap;
+
+    const OWNER: Principal = Principal::from_slice(&[0, 1, 2]);
 
     #[test]
     fn stores_file_in_state() {
@@ -89,7 +172,7 @@ mod test {
 
         set_user_info(
             &mut state,
-            Principal::anonymous(),
+            OWNER,
             User {
                 username: "John".to_string(),
                 public_key: vec![1, 2, 3],
@@ -98,28 +181,39 @@ mod test {
 
         // Request a file.
         upload_file_atomic(
-            Principal::anonymous(),
+            OWNER,
             UploadFileAtomicRequest {
                 num_chunks: 1,
                 name: "file_name".to_string(),
                 content: vec![1, 2, 3],
                 // owner_key: vec![1, 2, 3],
                 file_type: "image/jpeg".to_string(),
+                total_size: Some(3),
+                chunk_size: Some(3),
+                file_id: reserve_file_id(&mut state, OWNER).unwrap().file_id,
             },
             &mut state,
-        );
+        )
+        .unwrap();
 
         // The file is stored in the state.
         assert_eq!(
-            state.file_data,
+            state.file_data.iter().collect::<BTreeMap<_, _>>(),
             btreemap! {
                 0 => File {
                     metadata: FileMetadata {
                         file_name: "file_name".to_string(),
-                        user_public_key: get_user_key(&state, Principal::anonymous()),
-                        requester_principal: Principal::anonymous(),
+                        user_public_key: get_user_key(&state, OWNER),
+                        requester_principal: OWNER,
                         requested_at: get_time(),
                         uploaded_at: Some(get_time()),
+                        folder_id: None,
+                        chunk_hashes: vec![sha256(&[1, 2, 3])],
+                        root_hash: Some(root_hash(&[sha256(&[1, 2, 3])])),
+                        key_derivation: KeyDerivation::PerFile { key_version: 0 },
+                        pending_key_version: None,
+                        alias_expires_at: None,
+                        due_at: None,
                     },
                     content: FileContent::Uploaded {
                         file_type: "image/jpeg".to_string(),
@@ -138,11 +232,79 @@ mod test {
 
         // Owners are updated.
         // TODO: test this logic with the get_files endpoint.
+        assert_eq!(state.owned_files(OWNER), vec![0]);
+    }
+
+    #[test]
+    fn reserved_file_ids_get_per_file_keys() {
+        let mut state = State::default();
+        let owner = Principal::from_slice(&[0, 1, 2]);
+        set_user_info(
+            &mut state,
+            owner,
+            User {
+                username: "John".to_string(),
+                public_key: vec![1, 2, 3],
+            },
+        );
         assert_eq!(
-            state.file_owners,
-            btreemap! {
-                Principal::anonymous() => vec![0],
-            }
+            reserve_file_id(&mut state, Principal::anonymous()),
+            Err(UploadFileError::PermissionError)
+        );
+        let key_info = reserve_file_id(&mut state, owner).unwrap();
+        let request = UploadFileAtomicRequest {
+            num_chunks: 1,
+            name: "file_name".to_string(),
+            content: vec![1, 2, 3],
+            file_type: "image/jpeg".to_string(),
+            total_size: Some(3),
+            chunk_size: Some(3),
+            file_id: key_info.file_id,
+        };
+
+        // Only the principal that reserved the file id can use it.
+        assert_eq!(
+            upload_file_atomic(Principal::anonymous(), request.clone(), &mut state),
+            Err(UploadFileError::NotRequested)
+        );
+        assert_eq!(
+            upload_file_atomic(owner, request.clone(), &mut state),
+            Ok(key_info.file_id)
+        );
+        assert_eq!(
+            upload_file_atomic(owner, request, &mut state),
+            Err(UploadFileError::NotRequested)
+        );
+
+        let metadata = state.file_data.get(&key_info.file_id).unwrap().metadata;
+        assert_eq!(metadata.key_derivation, key_info.key_derivation);
+        assert_eq!(
+            metadata.derivation_id(key_info.file_id),
+            key_info.derivation_id
+        );
+    }
+
+    #[test]
+    fn reservations_are_capped_and_expire() {
+        let mut state = State::default();
+        let owner = Principal::from_slice(&[0, 1, 2]);
+        for _ in 0..MAX_RESERVED_FILE_IDS {
+            reserve_file_id(&mut state, owner).unwrap();
+        }
+        assert_eq!(
+            reserve_file_id(&mut state, owner),
+            Err(UploadFileError::QuotaExceeded)
+        );
+        // Others can still reserve file ids.
+        reserve_file_id(&mut state, Principal::from_slice(&[0, 1, 3])).unwrap();
+
+        let expires_at = get_time() + UPLOAD_SESSION_TTL;
+        assert_eq!(expire_reserved_file_ids(&mut state, expires_at - 1), 0);
+        assert_eq!(
+            expire_reserved_file_ids(&mut state, expires_at),
+            MAX_RESERVED_FILE_IDS + 1
         );
+        assert!(state.reserved_file_ids.is_empty());
+        reserve_file_id(&mut state, owner).unwrap();
     }
 }
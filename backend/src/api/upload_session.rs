@@ -0,0 +1,533 @@
+use super::{
+    delete_file::remove_file, storage_quota::release_storage, upload_file::authorize_alias,
+};
+use crate::{get_time, FileContent, State, UploadFileError, UploadProgress, UploadSession};
+use candid::Principal;
+use std::collections::BTreeSet;
+
+/// The largest chunk that can be uploaded, which leaves room for the rest of
+/// the ingress message.
+pub const MAX_CHUNK_SIZE: u64 = 2_000_000;
+
+/// The smallest chunk size of a file uploaded in multiple chunks, so that
+/// small files can't be split into many chunks.
+pub const MIN_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// The most chunks a file can have, which bounds the work done per file.
+pub const MAX_NUM_CHUNKS: u64 = 1024;
+
+/// The most expired uploads reclaimed at once, which bounds the work done per call.
+const MAX_RECLAIMED_UPLOADS: usize = 100;
+
+/// How long an upload session is kept after its last chunk (24 hours, in
+/// nanoseconds).
+pub const UPLOAD_SESSION_TTL: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Validates the layout declared when an upload starts, along with the first
+/// chunk, and returns the session that tracks the upload. Uploaders that don't
+/// declare the layout get the size of the first chunk as the chunk size.
+pub(crate) fn start_session(
+    num_chunks: u64,
+    total_size: Option<u64>,
+    chunk_size: Option<u64>,
+    first_chunk: &[u8],
+    alias: Option<String>,
+) -> Result<UploadSession, UploadFileError> {
+    let first_len = first_chunk.len() as u64;
+    let chunk_size = chunk_size.unwrap_or(first_len.max(1));
+    let total_size = total_size.or((num_chunks == 1).then_some(first_len));
+    if chunk_size > MAX_CHUNK_SIZE {
+        return Err(UploadFileError::ChunkTooLarge);
+    }
+    if num_chunks > MAX_NUM_CHUNKS || (num_chunks > 1 && chunk_size < MIN_CHUNK_SIZE) {
+        return Err(UploadFileError::InvalidChunkSize);
+    }
+    // An empty file is still uploaded as a single, empty chunk.
+    if chunk_size == 0
+        || num_chunks == 0
+        || total_size.is_some_and(|total_size| total_size.div_ceil(chunk_size).max(1) != num_chunks)
+    {
+        return Err(UploadFileError::InvalidFileSize);
+    }
+
+    let now = get_time();
+    let session = UploadSession {
+        total_size,
+        chunk_size,
+        num_chunks,
+        alias,
+        created_at: now,
+        expires_at: now + UPLOAD_SESSION_TTL,
+    };
+    if !session.fits_chunk(0, first_len) {
+        return Err(UploadFileError::InvalidChunkSize);
+    }
+    Ok(session)
+}
+
+/// Returns which chunks of a partially uploaded file are still missing, so
+/// that an interrupted upload can be resumed by its owner or its uploader.
+pub fn get_upload_progress(
+    state: &State,
+    caller: Principal,
+    file_id: u64,
+    alias: Option<String>,
+) -> Result<UploadProgress, UploadFileError> {
+    let file = state
+        .file_data
+        .get(&file_id)
+        .ok_or(UploadFileError::NotRequested)?;
+    let session = state.upload_sessions.get(&file_id);
+    if !can_manage_upload(state, caller, file_id, session.as_ref(), alias.as_deref()) {
+        return Err(UploadFileError::PermissionError);
+    }
+    match file.content {
+        FileContent::Pending { .. } => Err(UploadFileError::UploadNotStarted),
+        FileContent::Uploaded { .. } => Err(UploadFileError::AlreadyUploaded),
+        FileContent::PartiallyUploaded { num_chunks, .. } => {
+            let uploaded: BTreeSet<u64> = state.chunk_ids(file_id).collect();
+            Ok(UploadProgress {
+                num_chunks,
+                missing_chunks: (0..num_chunks)
+                    .filter(|chunk_id| !uploaded.contains(chunk_id))
+                    .collect(),
+            })
+        }
+    }
+}
+
+/// Aborts the upload of a partially uploaded file, either by its owner or by
+/// whoever uploads it with the alias the upload was started with. Files
+/// uploaded for a request go back to pending, while files uploaded by their
+/// owner are removed.
+pub fn abort_upload(
+    state: &mut State,
+    caller: Principal,
+    file_id: u64,
+    alias: Option<String>,
+) -> Result<(), UploadFileError> {
+    let file = state
+        .file_data
+        .get(&file_id)
+        .ok_or(UploadFileError::NotRequested)?;
+    let session = state.upload_sessions.get(&file_id);
+    if !can_manage_upload(state, caller, file_id, session.as_ref(), alias.as_deref()) {
+        return Err(UploadFileError::PermissionError);
+    }
+    if let FileContent::Uploaded { .. } = file.content {
+        return Err(UploadFileError::AlreadyUploaded);
+    }
+
+    let session = session.ok_or(UploadFileError::UploadNotStarted)?;
+    cancel_upload(state, file_id, session);
+    Ok(())
+}
+
+/// Whether the caller owns the file or uploads it with the alias the upload
+/// was started with.
+fn can_manage_upload(
+    state: &State,
+    caller: Principal,
+    file_id: u64,
+    session: Option<&UploadSession>,
+    alias: Option<&str>,
+) -> bool {
+    let is_uploader = match (session.and_then(|session| session.alias.as_deref()), alias) {
+        (Some(file_alias), Some(alias)) => {
+            authorize_alias(state, file_id, file_alias, alias).is_ok()
+        }
+        _ => false,
+    };
+    is_uploader || state.is_owner(caller, file_id)
+}
+
+/// Cancels up to `MAX_RECLAIMED_UPLOADS` uploads whose sessions expired
+/// before `now`, earliest first, and returns how many were reclaimed. The rest
+/// are reclaimed by later calls.
+pub fn reclaim_expired_uploads(state: &mut State, now: u64) -> usize {
+    let expired = state.indexes.expired_uploads(now, MAX_RECLAIMED_UPLOADS);
+    let count = expired.len();
+    for file_id in expired {
+        if let Some(session) = state.upload_sessions.get(&file_id) {
+            cancel_upload(state, file_id, session);
+        }
+    }
+    count
+}
+
+fn cancel_upload(state: &mut State, file_id: u64, session: UploadSession) {
+    state.remove_upload_session(file_id);
+    let Some(mut file) = state.file_data.get(&file_id) else {
+        return;
+    };
+
+    match session.alias {
+        // The request stays open, so it can be uploaded to again with the same alias.
+        Some(alias) => {
+            let requester = file.metadata.requester_principal;
+            let stored_bytes = state.stored_bytes(file_id);
+            release_storage(state, requester, stored_bytes, 1);
+            state.remove_file_contents(file_id);
+            file.content = FileContent::Pending {
+                alias: alias.clone(),
+            };
+            file.metadata.uploaded_at = None;
+            state.file_data.insert(file_id, file);
+            // Requests of a group may have no alias of their own.
+            if !alias.is_empty() {
+                state.file_alias_index.insert(alias, file_id);
+            }
+        }
+        None => remove_file(state, file.metadata.requester_principal, file_id),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::{
+            request_file, reserve_file_id, set_user_info, upload_file, upload_file_atomic,
+            upload_file_continue, UploadFileAtomicRequest,
+        },
+        UploadFileContinueRequest, User,
+    };
+    use candid::Principal;
+
+    const OWNER: Principal = Principal::from_slice(&[0, 1, 3]);
+    const CHUNK: usize = MIN_CHUNK_SIZE as usize;
+
+    fn setup() -> (State, u64) {
+        let mut state = State::default();
+        set_user_info(
+            &mut state,
+            OWNER,
+            User {
+                username: "John".to_string(),
+                public_key: vec![1, 2, 3],
+            },
+        );
+        let file_id = upload_file_atomic(
+            OWNER,
+            UploadFileAtomicRequest {
+                name: "file_name".to_string(),
+                content: vec![1; CHUNK],
+                file_type: "image/jpeg".to_string(),
+                num_chunks: 4,
+                total_size: Some(3 * MIN_CHUNK_SIZE + 1),
+                chunk_size: Some(MIN_CHUNK_SIZE),
+                file_id: reserve_file_id(&mut state, OWNER).unwrap().file_id,
+            },
+            &mut state,
+        )
+        .unwrap();
+        (state, file_id)
+    }
+
+    fn upload_chunk(
+        state: &mut State,
+        file_id: u64,
+        chunk_id: u64,
+        contents: Vec<u8>,
+    ) -> Result<(), UploadFileError> {
+        upload_file_continue(
+            UploadFileContinueRequest {
+                file_id,
+                chunk_id,
+                contents,
+                sha256: None,
+                alias: None,
+            },
+            OWNER,
+            state,
+        )
+    }
+
+    #[test]
+    fn declared_layout_is_validated() {
+        assert_eq!(
+            start_session(
+                1,
+                Some(MAX_CHUNK_SIZE + 1),
+                Some(MAX_CHUNK_SIZE + 1),
+                &[],
+                None
+            ),
+            Err(UploadFileError::ChunkTooLarge)
+        );
+        assert_eq!(
+            start_session(
+                MAX_NUM_CHUNKS + 1,
+                None,
+                Some(MIN_CHUNK_SIZE),
+                &[1; CHUNK],
+                None
+            ),
+            Err(UploadFileError::InvalidChunkSize)
+        );
+        assert_eq!(
+            start_session(
+                2,
+                Some(2 * MIN_CHUNK_SIZE - 2),
+                Some(MIN_CHUNK_SIZE - 1),
+                &[1; CHUNK - 1],
+                None
+            ),
+            Err(UploadFileError::InvalidChunkSize)
+        );
+        assert_eq!(
+            start_session(
+                3,
+                Some(3 * MIN_CHUNK_SIZE + 1),
+                Some(MIN_CHUNK_SIZE),
+                &[1; CHUNK],
+                None
+            ),
+            Err(UploadFileError::InvalidFileSize)
+        );
+        assert_eq!(
+            start_session(1, Some(3), Some(0), &[1, 2, 3], None),
+            Err(UploadFileError::InvalidFileSize)
+        );
+        assert_eq!(
+            start_session(
+                4,
+                Some(3 * MIN_CHUNK_SIZE + 1),
+                Some(MIN_CHUNK_SIZE),
+                &[1; CHUNK - 1],
+                None
+            ),
+            Err(UploadFileError::InvalidChunkSize)
+        );
+        assert_eq!(
+            start_session(1, Some(0), Some(3), &[], None).map(|session| session.fits_chunk(0, 0)),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn undeclared_layout_defaults_to_the_first_chunk() {
+        let session = start_session(1, None, None, &[], None).unwrap();
+        assert_eq!((session.total_size, session.chunk_size), (Some(0), 1));
+
+        let session = start_session(3, None, None, &[1; CHUNK], None).unwrap();
+        assert_eq!(
+            (session.total_size, session.chunk_size),
+            (None, MIN_CHUNK_SIZE)
+        );
+        assert_eq!(session.max_size(), 3 * MIN_CHUNK_SIZE);
+        assert!(session.fits_chunk(1, MIN_CHUNK_SIZE));
+        assert!(!session.fits_chunk(1, MIN_CHUNK_SIZE - 1));
+        assert!(session.fits_chunk(2, 1));
+        assert!(!session.fits_chunk(2, 0));
+        assert!(!session.fits_chunk(2, MIN_CHUNK_SIZE + 1));
+
+        // A small first chunk can't be split into more chunks.
+        assert_eq!(
+            start_session(2, None, None, &[1, 2, 3], None),
+            Err(UploadFileError::InvalidChunkSize)
+        );
+    }
+
+    #[test]
+    fn invalid_chunks_are_rejected() {
+        let (mut state, file_id) = setup();
+
+        assert_eq!(
+            upload_chunk(&mut state, file_id, 4, vec![1]),
+            Err(UploadFileError::ChunkOutOfRange)
+        );
+        assert_eq!(
+            upload_chunk(&mut state, file_id, 0, vec![1; CHUNK]),
+            Err(UploadFileError::ChunkAlreadyUploaded)
+        );
+        assert_eq!(
+            upload_chunk(&mut state, file_id, 1, vec![1; CHUNK - 1]),
+            Err(UploadFileError::InvalidChunkSize)
+        );
+        // Only the last chunk is smaller than the chunk size.
+        assert_eq!(
+            upload_chunk(&mut state, file_id, 3, vec![1; CHUNK]),
+            Err(UploadFileError::InvalidChunkSize)
+        );
+        assert_eq!(
+            upload_chunk(&mut state, 7, 1, vec![1, 2, 3]),
+            Err(UploadFileError::NotRequested)
+        );
+        assert_eq!(state.num_chunks_uploaded(file_id), 1);
+    }
+
+    #[test]
+    fn progress_lists_missing_chunks() {
+        let (mut state, file_id) = setup();
+
+        upload_chunk(&mut state, file_id, 3, vec![10]).unwrap();
+        assert_eq!(
+            get_upload_progress(&state, Principal::from_slice(&[0, 1, 2]), file_id, None),
+            Err(UploadFileError::PermissionError)
+        );
+        assert_eq!(
+            get_upload_progress(&state, OWNER, file_id, None),
+            Ok(UploadProgress {
+                num_chunks: 4,
+                missing_chunks: vec![1, 2],
+            })
+        );
+
+        // Resume the upload with the missing chunks.
+        upload_chunk(&mut state, file_id, 2, vec![7; CHUNK]).unwrap();
+        state.check_indexes();
+        upload_chunk(&mut state, file_id, 1, vec![4; CHUNK]).unwrap();
+        assert_eq!(
+            get_upload_progress(&state, OWNER, file_id, None),
+            Err(UploadFileError::AlreadyUploaded)
+        );
+        assert!(state.upload_sessions.is_empty());
+        assert_eq!(
+            upload_chunk(&mut state, file_id, 1, vec![4; CHUNK]),
+            Err(UploadFileError::AlreadyUploaded)
+        );
+    }
+
+    #[test]
+    fn aborted_atomic_upload_is_removed() {
+        let (mut state, file_id) = setup();
+
+        assert_eq!(
+            abort_upload(&mut state, Principal::from_slice(&[0, 1, 2]), file_id, None),
+            Err(UploadFileError::PermissionError)
+        );
+        abort_upload(&mut state, OWNER, file_id, None).unwrap();
+
+        assert!(state.file_data.is_empty());
+        assert!(state.file_contents.is_empty());
+        assert!(state.upload_sessions.is_empty());
+        assert!(state.owned_files(OWNER).is_empty());
+        assert_eq!(
+            abort_upload(&mut state, OWNER, file_id, None),
+            Err(UploadFileError::NotRequested)
+        );
+    }
+
+    #[test]
+    fn requested_upload_can_be_aborted_by_its_uploader() {
+        let mut state = State::default();
+        set_user_info(
+            &mut state,
+            Principal::anonymous(),
+            User {
+                username: "John".to_string(),
+                public_key: vec![1, 2, 3],
+            },
+        );
+        let alias = request_file(Principal::anonymous(), "request", &mut state).unwrap();
+        let file_id = 0;
+        upload_file(
+            file_id,
+            alias.clone(),
+            vec![1; CHUNK],
+            "jpeg".to_string(),
+            2,
+            Some(2 * MIN_CHUNK_SIZE),
+            Some(MIN_CHUNK_SIZE),
+            &mut state,
+        )
+        .unwrap();
+
+        let uploader = Principal::from_slice(&[0, 1, 2]);
+        assert_eq!(
+            get_upload_progress(&state, uploader, file_id, Some("wrong".to_string())),
+            Err(UploadFileError::PermissionError)
+        );
+        assert_eq!(
+            get_upload_progress(&state, uploader, file_id, Some(alias.clone()))
+                .map(|progress| progress.missing_chunks),
+            Ok(vec![1])
+        );
+        assert_eq!(
+            abort_upload(&mut state, uploader, file_id, Some("wrong".to_string())),
+            Err(UploadFileError::PermissionError)
+        );
+        abort_upload(&mut state, uploader, file_id, Some(alias.clone())).unwrap();
+        assert_eq!(
+            state.file_data.get(&file_id).unwrap().content,
+            FileContent::Pending { alias }
+        );
+        assert!(state.upload_sessions.is_empty());
+    }
+
+    #[test]
+    fn expired_request_upload_reverts_to_pending() {
+        let mut state = State::default();
+        set_user_info(
+            &mut state,
+            Principal::anonymous(),
+            User {
+                username: "John".to_string(),
+                public_key: vec![1, 2, 3],
+            },
+        );
+        let alias = request_file(Principal::anonymous(), "request", &mut state).unwrap();
+        let file_id = 0;
+        upload_file(
+            file_id,
+            alias.clone(),
+            vec![1; CHUNK],
+            "jpeg".to_string(),
+            2,
+            Some(2 * MIN_CHUNK_SIZE),
+            Some(MIN_CHUNK_SIZE),
+            &mut state,
+        )
+        .unwrap();
+
+        // The session isn't reclaimed before it expires.
+        let expires_at = state.upload_sessions.get(&file_id).unwrap().expires_at;
+        assert_eq!(reclaim_expired_uploads(&mut state, expires_at - 1), 0);
+        assert_eq!(reclaim_expired_uploads(&mut state, expires_at), 1);
+
+        let file = state.file_data.get(&file_id).unwrap();
+        assert_eq!(
+            file.content,
+            FileContent::Pending {
+                alias: alias.clone()
+            }
+        );
+        assert_eq!(file.metadata.uploaded_at, None);
+        assert_eq!(state.num_chunks_uploaded(file_id), 0);
+        assert_eq!(state.file_alias_index.get(&alias), Some(file_id));
+        assert!(state.upload_sessions.is_empty());
+        state.check_indexes();
+
+        // The request can be uploaded to again.
+        upload_file(
+            file_id,
+            alias.clone(),
+            vec![1, 2, 3],
+            "jpeg".to_string(),
+            1,
+            Some(3),
+            Some(3),
+            &mut state,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn expired_uploads_are_reclaimed_in_batches() {
+        let mut state = State::default();
+        for file_id in 0..=MAX_RECLAIMED_UPLOADS as u64 {
+            let session = start_session(1, None, None, &[], None).unwrap();
+            state.insert_upload_session(file_id, session);
+        }
+
+        assert_eq!(
+            reclaim_expired_uploads(&mut state, u64::MAX),
+            MAX_RECLAIMED_UPLOADS
+        );
+        assert_eq!(reclaim_expired_uploads(&mut state, u64::MAX), 1);
+        assert_eq!(reclaim_expired_uploads(&mut state, u64::MAX), 0);
+        assert!(state.upload_sessions.is_empty());
+        state.check_indexes();
+    }
+}
@@ -2,11 +2,19 @@ use crate::{State, User};
 use candid::Principal;
 
 pub fn set_user_info(state: &mut State, caller: Principal, user: User) {
-    state.users.insert(caller, user);
+    if let Some(previous) = state.users.insert(caller, user.clone()) {
+        state.indexes.remove_user(caller, &previous);
+    }
+    state.indexes.add_user(caller, &user);
 }
 
 pub fn username_exists(state: &State, username: String) -> bool {
-    state.users.values().any(|val| val.username == username)
+    state.indexes.usernames.contains_key(&username)
+}
+
+/// Returns the principal of the user registered with the username, if any.
+pub(crate) fn principal_by_username(state: &State, username: &str) -> Option<Principal> {
+    state.indexes.principal_by_username(username)
 }
 
 pub fn get_user_key(state: &State, caller: Principal) -> Vec<u8> {
@@ -16,5 +24,4 @@ pub fn get_user_key(state: &State, caller: Principal) -> Vec<u8> {
         .get(&caller)
         .expect("user does not exist.")
         .public_key
-        .clone()
 }
@@ -0,0 +1,569 @@
+use crate::memory::{
+    get_file_group_index_memory, get_file_sharees_index_memory, get_folder_children_index_memory,
+    get_folder_files_index_memory, get_group_alias_by_id_index_memory,
+    get_invitee_invitations_index_memory, get_share_expiries_index_memory,
+    get_upload_expiries_index_memory, get_usernames_index_memory, Memory,
+};
+use crate::{
+    File, Folder, Invitation, Invitee, InviteeInvitation, RequestGroup, Share, UploadSession, User,
+};
+use candid::Principal;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::ops::Bound::{Excluded, Included, Unbounded};
+
+/// Stands for the root folder in the folder indexes. Folder IDs are assigned
+/// incrementally, so no folder ever gets this ID.
+const ROOT_FOLDER: u64 = u64::MAX;
+
+/// Reverse lookups over the state, so that listing and deleting files doesn't
+/// require scanning every request group and every user's shares.
+///
+/// The indexes are derived from the rest of the state and are kept up to date
+/// by every endpoint that mutates it.
+pub struct Indexes {
+    /// Mapping between a file ID and the request group it belongs to.
+    pub file_group: StableBTreeMap<u64, u64, Memory>,
+
+    /// Mapping between a group ID and its alias.
+    pub group_alias: StableBTreeMap<u64, String, Memory>,
+
+    /// Pairs of a file ID and a principal the file is shared with.
+    pub file_sharees: StableBTreeMap<(u64, Principal), (), Memory>,
+
+    /// Triples of when a share expires, its sharee and its file ID.
+    pub share_expiries: StableBTreeMap<(u64, Principal, u64), (), Memory>,
+
+    /// Triples of an owner, a folder ID and the ID of a folder inside it.
+    pub folder_children: StableBTreeMap<(Principal, u64, u64), (), Memory>,
+
+    /// Triples of an owner, a folder ID and the ID of an owned file inside it.
+    pub folder_files: StableBTreeMap<(Principal, u64, u64), (), Memory>,
+
+    /// The invitations to each invitee.
+    pub invitee_invitations: StableBTreeMap<InviteeInvitation, (), Memory>,
+
+    /// Pairs of when an upload session expires and the ID of its file.
+    pub upload_expiries: StableBTreeMap<(u64, u64), (), Memory>,
+
+    /// Mapping between a username and the principal registered with it.
+    pub usernames: StableBTreeMap<String, Principal, Memory>,
+}
+
+impl Indexes {
+    pub fn init() -> Self {
+        Self {
+            file_group: StableBTreeMap::init(get_file_group_index_memory()),
+            group_alias: StableBTreeMap::init(get_group_alias_by_id_index_memory()),
+            file_sharees: StableBTreeMap::init(get_file_sharees_index_memory()),
+            share_expiries: StableBTreeMap::init(get_share_expiries_index_memory()),
+            folder_children: StableBTreeMap::init(get_folder_children_index_memory()),
+            folder_files: StableBTreeMap::init(get_folder_files_index_memory()),
+            invitee_invitations: StableBTreeMap::init(get_invitee_invitations_index_memory()),
+            upload_expiries: StableBTreeMap::init(get_upload_expiries_index_memory()),
+            usernames: StableBTreeMap::init(get_usernames_index_memory()),
+        }
+    }
+
+    /// Rebuilds the indexes from the primary maps.
+    pub fn rebuild(
+        &mut self,
+        request_groups: &StableBTreeMap<u64, RequestGroup, Memory>,
+        group_alias_index: &StableBTreeMap<String, u64, Memory>,
+        file_shares: &StableBTreeMap<(Principal, u64), Share, Memory>,
+    ) {
+        clear(&mut self.file_group);
+        clear(&mut self.group_alias);
+        clear(&mut self.file_sharees);
+        clear(&mut self.share_expiries);
+
+        for (_, group) in request_groups.iter() {
+            for file_id in group.files {
+                self.file_group.insert(file_id, group.group_id);
+            }
+        }
+
+        for (alias, group_id) in group_alias_index.iter() {
+            self.group_alias.insert(group_id, alias);
+        }
+
+        for ((sharee, file_id), share) in file_shares.iter() {
+            self.add_share(file_id, sharee, &share);
+        }
+    }
+
+    /// Rebuilds the folder indexes from the primary maps.
+    pub fn rebuild_folders(
+        &mut self,
+        folders: &StableBTreeMap<u64, Folder, Memory>,
+        file_owners: &StableBTreeMap<(Principal, u64), (), Memory>,
+        file_data: &StableBTreeMap<u64, File, Memory>,
+    ) {
+        clear(&mut self.folder_children);
+        clear(&mut self.folder_files);
+
+        for (folder_id, folder) in folders.iter() {
+            self.add_folder(folder.owner, folder.parent_id, folder_id);
+        }
+
+        for ((owner, file_id), _) in file_owners.iter() {
+            if let Some(file) = file_data.get(&file_id) {
+                self.add_file(owner, file.metadata.folder_id, file_id);
+            }
+        }
+    }
+
+    /// Rebuilds the invitee index from the invitations.
+    pub fn rebuild_invitations(
+        &mut self,
+        invitations: &StableBTreeMap<(u64, u64), Invitation, Memory>,
+    ) {
+        clear(&mut self.invitee_invitations);
+
+        for (_, invitation) in invitations.iter() {
+            self.add_invitation(&invitation);
+        }
+    }
+
+    /// Rebuilds the upload expiry index from the upload sessions.
+    pub fn rebuild_upload_sessions(
+        &mut self,
+        upload_sessions: &StableBTreeMap<u64, UploadSession, Memory>,
+    ) {
+        clear(&mut self.upload_expiries);
+
+        for (file_id, session) in upload_sessions.iter() {
+            self.add_upload_session(file_id, &session);
+        }
+    }
+
+    /// Rebuilds the username index from the users. Should several users share
+    /// a username, the first one keeps it.
+    pub fn rebuild_users(&mut self, users: &StableBTreeMap<Principal, User, Memory>) {
+        clear(&mut self.usernames);
+
+        for (principal, user) in users.iter() {
+            if !self.usernames.contains_key(&user.username) {
+                self.add_user(principal, &user);
+            }
+        }
+    }
+
+    /// Returns the principal of the user registered with the username, if any.
+    pub fn principal_by_username(&self, username: &str) -> Option<Principal> {
+        self.usernames.get(&username.to_string())
+    }
+
+    pub fn add_user(&mut self, principal: Principal, user: &User) {
+        self.usernames.insert(user.username.clone(), principal);
+    }
+
+    pub fn remove_user(&mut self, principal: Principal, user: &User) {
+        if self.principal_by_username(&user.username) == Some(principal) {
+            self.usernames.remove(&user.username);
+        }
+    }
+
+    /// Returns the IDs of up to `limit` files whose upload sessions expired
+    /// before `now`, earliest first.
+    pub fn expired_uploads(&self, now: u64, limit: usize) -> Vec<u64> {
+        self.upload_expiries
+            .range(..=(now, u64::MAX))
+            .take(limit)
+            .map(|((_, file_id), _)| file_id)
+            .collect()
+    }
+
+    pub fn add_upload_session(&mut self, file_id: u64, session: &UploadSession) {
+        self.upload_expiries
+            .insert((session.expires_at, file_id), ());
+    }
+
+    pub fn remove_upload_session(&mut self, file_id: u64, session: &UploadSession) {
+        self.upload_expiries.remove(&(session.expires_at, file_id));
+    }
+
+    /// Returns the file and invitation IDs of the invitations to the invitee.
+    pub fn invitations_to(&self, invitee: &Invitee) -> Vec<(u64, u64)> {
+        let first = InviteeInvitation {
+            invitee: invitee.clone(),
+            file_id: 0,
+            invitation_id: 0,
+        };
+        self.invitee_invitations
+            .range(first..)
+            .take_while(|(key, _)| key.invitee == *invitee)
+            .map(|(key, _)| (key.file_id, key.invitation_id))
+            .collect()
+    }
+
+    pub fn add_invitation(&mut self, invitation: &Invitation) {
+        self.invitee_invitations
+            .insert(invitee_invitation(invitation), ());
+    }
+
+    pub fn remove_invitation(&mut self, invitation: &Invitation) {
+        self.invitee_invitations
+            .remove(&invitee_invitation(invitation));
+    }
+
+    /// Returns the principals the given file is shared with.
+    pub fn sharees(&self, file_id: u64) -> Vec<Principal> {
+        self.file_sharees
+            .range((file_id, Principal::management_canister())..)
+            .take_while(|((id, _), _)| *id == file_id)
+            .map(|((_, sharee), _)| sharee)
+            .collect()
+    }
+
+    /// Returns the sharees and file IDs of up to `limit` shares that expired
+    /// before `now`, earliest first.
+    pub fn expired_shares(&self, now: u64, limit: usize) -> Vec<(Principal, u64)> {
+        self.share_expiries
+            .iter()
+            .take_while(|((expires_at, _, _), _)| *expires_at <= now)
+            .take(limit)
+            .map(|((_, sharee, file_id), _)| (sharee, file_id))
+            .collect()
+    }
+
+    pub fn add_share(&mut self, file_id: u64, sharee: Principal, share: &Share) {
+        self.file_sharees.insert((file_id, sharee), ());
+        if let Some(expires_at) = share.expires_at {
+            self.share_expiries
+                .insert((expires_at, sharee, file_id), ());
+        }
+    }
+
+    pub fn remove_share(&mut self, file_id: u64, sharee: Principal, share: &Share) {
+        self.file_sharees.remove(&(file_id, sharee));
+        if let Some(expires_at) = share.expires_at {
+            self.share_expiries.remove(&(expires_at, sharee, file_id));
+        }
+    }
+
+    /// Returns the IDs of the owner's folders inside the given folder, or
+    /// inside the root, starting after the given folder ID.
+    pub fn subfolders(
+        &self,
+        owner: Principal,
+        parent_id: Option<u64>,
+        after: Option<u64>,
+    ) -> impl Iterator<Item = u64> + '_ {
+        folder_range(&self.folder_children, owner, parent_id, after)
+    }
+
+    /// Returns the IDs of the owner's files inside the given folder, or
+    /// inside the root, starting after the given file ID.
+    pub fn folder_files(
+        &self,
+        owner: Principal,
+        folder_id: Option<u64>,
+        after: Option<u64>,
+    ) -> impl Iterator<Item = u64> + '_ {
+        folder_range(&self.folder_files, owner, folder_id, after)
+    }
+
+    pub fn add_folder(&mut self, owner: Principal, parent_id: Option<u64>, folder_id: u64) {
+        self.folder_children
+            .insert((owner, parent_id.unwrap_or(ROOT_FOLDER), folder_id), ());
+    }
+
+    pub fn remove_folder(&mut self, owner: Principal, parent_id: Option<u64>, folder_id: u64) {
+        self.folder_children
+            .remove(&(owner, parent_id.unwrap_or(ROOT_FOLDER), folder_id));
+    }
+
+    pub fn add_file(&mut self, owner: Principal, folder_id: Option<u64>, file_id: u64) {
+        self.folder_files
+            .insert((owner, folder_id.unwrap_or(ROOT_FOLDER), file_id), ());
+    }
+
+    pub fn remove_file(&mut self, owner: Principal, folder_id: Option<u64>, file_id: u64) {
+        self.folder_files
+            .remove(&(owner, folder_id.unwrap_or(ROOT_FOLDER), file_id));
+    }
+
+    /// Asserts that the indexes match the primary maps.
+    #[cfg(test)]
+    pub fn check(
+        &self,
+        request_groups: &StableBTreeMap<u64, RequestGroup, Memory>,
+        group_alias_index: &StableBTreeMap<String, u64, Memory>,
+        file_shares: &StableBTreeMap<(Principal, u64), Share, Memory>,
+    ) {
+        use std::collections::{BTreeMap, BTreeSet};
+
+        let file_group: BTreeMap<u64, u64> = request_groups
+            .iter()
+            .flat_map(|(_, group)| group.files.into_iter().map(move |id| (id, group.group_id)))
+            .collect();
+        let group_alias: BTreeMap<u64, String> = group_alias_index
+            .iter()
+            .map(|(alias, group_id)| (group_id, alias))
+            .collect();
+        let file_sharees: BTreeSet<(u64, Principal)> = file_shares
+            .iter()
+            .map(|((sharee, file_id), _)| (file_id, sharee))
+            .collect();
+        let share_expiries: BTreeSet<(u64, Principal, u64)> = file_shares
+            .iter()
+            .filter_map(|((sharee, file_id), share)| Some((share.expires_at?, sharee, file_id)))
+            .collect();
+
+        assert_eq!(
+            self.file_group.iter().collect::<BTreeMap<_, _>>(),
+            file_group,
+            "file group index is inconsistent"
+        );
+        assert_eq!(
+            self.group_alias.iter().collect::<BTreeMap<_, _>>(),
+            group_alias,
+            "group alias index is inconsistent"
+        );
+        assert_eq!(
+            self.file_sharees
+                .iter()
+                .map(|(key, _)| key)
+                .collect::<BTreeSet<_>>(),
+            file_sharees,
+            "file sharees index is inconsistent"
+        );
+        assert_eq!(
+            self.share_expiries
+                .iter()
+                .map(|(key, _)| key)
+                .collect::<BTreeSet<_>>(),
+            share_expiries,
+            "share expiries index is inconsistent"
+        );
+    }
+
+    /// Asserts that the folder indexes match the primary maps.
+    #[cfg(test)]
+    pub fn check_folders(
+        &self,
+        folders: &StableBTreeMap<u64, Folder, Memory>,
+        file_owners: &StableBTreeMap<(Principal, u64), (), Memory>,
+        file_data: &StableBTreeMap<u64, File, Memory>,
+    ) {
+        use std::collections::BTreeSet;
+
+        let folder_children: BTreeSet<(Principal, u64, u64)> = folders
+            .iter()
+            .map(|(folder_id, folder)| {
+                (
+                    folder.owner,
+                    folder.parent_id.unwrap_or(ROOT_FOLDER),
+                    folder_id,
+                )
+            })
+            .collect();
+        let folder_files: BTreeSet<(Principal, u64, u64)> = file_owners
+            .iter()
+            .filter_map(|((owner, file_id), _)| {
+                let folder_id = file_data.get(&file_id)?.metadata.folder_id;
+                Some((owner, folder_id.unwrap_or(ROOT_FOLDER), file_id))
+            })
+            .collect();
+
+        assert_eq!(
+            self.folder_children
+                .iter()
+                .map(|(key, _)| key)
+                .collect::<BTreeSet<_>>(),
+            folder_children,
+            "folder children index is inconsistent"
+        );
+        assert_eq!(
+            self.folder_files
+                .iter()
+                .map(|(key, _)| key)
+                .collect::<BTreeSet<_>>(),
+            folder_files,
+            "folder files index is inconsistent"
+        );
+    }
+
+    /// Asserts that the invitee index matches the invitations.
+    #[cfg(test)]
+    pub fn check_invitations(&self, invitations: &StableBTreeMap<(u64, u64), Invitation, Memory>) {
+        use std::collections::BTreeSet;
+
+        let invitee_invitations: BTreeSet<InviteeInvitation> = invitations
+            .iter()
+            .map(|(_, invitation)| invitee_invitation(&invitation))
+            .collect();
+
+        assert_eq!(
+            self.invitee_invitations
+                .iter()
+                .map(|(key, _)| key)
+                .collect::<BTreeSet<_>>(),
+            invitee_invitations,
+            "invitee invitations index is inconsistent"
+        );
+    }
+
+    /// Asserts that the upload expiry index matches the upload sessions.
+    #[cfg(test)]
+    pub fn check_upload_sessions(
+        &self,
+        upload_sessions: &StableBTreeMap<u64, UploadSession, Memory>,
+    ) {
+        use std::collections::BTreeSet;
+
+        let upload_expiries: BTreeSet<(u64, u64)> = upload_sessions
+            .iter()
+            .map(|(file_id, session)| (session.expires_at, file_id))
+            .collect();
+
+        assert_eq!(
+            self.upload_expiries
+                .iter()
+                .map(|(key, _)| key)
+                .collect::<BTreeSet<_>>(),
+            upload_expiries,
+            "upload expiries index is inconsistent"
+        );
+    }
+
+    /// Asserts that the username index matches the users.
+    #[cfg(test)]
+    pub fn check_users(&self, users: &StableBTreeMap<Principal, User, Memory>) {
+        use std::collections::BTreeSet;
+
+        let usernames: BTreeSet<String> = users.iter().map(|(_, user)| user.username).collect();
+
+        assert_eq!(
+            self.usernames
+                .iter()
+                .map(|(username, _)| username)
+                .collect::<BTreeSet<_>>(),
+            usernames,
+            "usernames index is inconsistent"
+        );
+        for (username, principal) in self.usernames.iter() {
+            assert_eq!(
+                users.get(&principal).map(|user| user.username),
+                Some(username),
+                "usernames index is inconsistent"
+            );
+        }
+    }
+}
+
+fn invitee_invitation(invitation: &Invitation) -> InviteeInvitation {
+    InviteeInvitation {
+        invitee: invitation.invitee.clone(),
+        file_id: invitation.file_id,
+        invitation_id: invitation.invitation_id,
+    }
+}
+
+/// Iterates over the IDs stored under an owner and a folder in one of the folder indexes.
+fn folder_range(
+    index: &StableBTreeMap<(Principal, u64, u64), (), Memory>,
+    owner: Principal,
+    folder_id: Option<u64>,
+    after: Option<u64>,
+) -> impl Iterator<Item = u64> + '_ {
+    let folder_id = folder_id.unwrap_or(ROOT_FOLDER);
+    let start = match after {
+        Some(after) => Excluded((owner, folder_id, after)),
+        None => Included((owner, folder_id, 0)),
+    };
+    let end = match folder_id.checked_add(1) {
+        Some(next) => Excluded((owner, next, 0)),
+        None => Unbounded,
+    };
+    index
+        .range((start, end))
+        .take_while(move |((key_owner, _, _), _)| *key_owner == owner)
+        .map(|((_, _, id), _)| id)
+}
+
+fn clear<K: Storable + Ord + Clone, V: Storable>(map: &mut StableBTreeMap<K, V, Memory>) {
+    let keys: Vec<K> = map.iter().map(|(key, _)| key).collect();
+    for key in keys {
+        map.remove(&key);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        api::{
+            delete_file, multi_request, request_file, revoke_share, set_user_info, share_file,
+            upload_file,
+        },
+        MultiRequestInput, State, User,
+    };
+    use candid::Principal;
+
+    #[test]
+    fn indexes_stay_consistent() {
+        let mut state = State::default();
+        let owner = Principal::anonymous();
+        let sharee = Principal::from_slice(&[0, 1, 2]);
+        for (principal, username) in [(owner, "John"), (sharee, "Jane")] {
+            set_user_info(
+                &mut state,
+                principal,
+                User {
+                    username: username.to_string(),
+                    public_key: vec![1, 2, 3],
+                },
+            );
+        }
+
+        let alias = request_file(owner, "request", &mut state).unwrap();
+        let response = multi_request(
+            owner,
+            MultiRequestInput {
+                group_name: "group".to_string(),
+                file_names: vec!["a".to_string(), "b".to_string()],
+                save_as_template: false,
+                expires_at: None,
+                due_at: None,
+            },
+            &mut state,
+        )
+        .unwrap();
+        state.check_indexes();
+        assert_eq!(state.indexes.file_group.get(&1), Some(response.group_id));
+        assert_eq!(
+            state.indexes.group_alias.get(&response.group_id),
+            Some(response.group_alias.clone())
+        );
+
+        for (file_id, alias) in [(0, alias), (1, response.group_alias)] {
+            upload_file(
+                file_id,
+                alias,
+                vec![1, 2, 3],
+                "jpeg".to_string(),
+                1,
+                Some(3),
+                Some(3),
+                &mut state,
+            )
+            .unwrap();
+            share_file(&mut state, owner, sharee, file_id);
+        }
+        state.check_indexes();
+
+        revoke_share(&mut state, owner, sharee, 0);
+        state.check_indexes();
+        assert!(state.indexes.sharees(0).is_empty());
+
+        delete_file(&mut state, owner, 1);
+        state.check_indexes();
+        assert!(!state.indexes.file_group.contains_key(&1));
+        assert!(state.indexes.sharees(1).is_empty());
+        assert_eq!(state.group_file_ids(response.group_id), vec![2]);
+
+        // Rebuilding from scratch, as done when migrating a version 1 state, yields the same indexes.
+        state.rebuild_indexes();
+        state.check_indexes();
+    }
+}
@@ -1,8 +1,10 @@
 mod aliases;
 pub mod api;
+mod indexes;
 mod memory;
 mod upgrade;
 use crate::aliases::{AliasGenerator, Randomness};
+use crate::indexes::Indexes;
 use candid::CandidType;
 use candid::Principal;
 use ic_stable_structures::{
@@ -12,6 +14,7 @@ use ic_stable_structures::{
 };
 use memory::{get_user_canisters_memory, Memory}; // Assuming get_user_canisters_memory will be added to memory.rs
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::borrow::Cow;
 use std::cell::RefCell;
 use std::collections::BTreeMap;
@@ -77,12 +80,124 @@ impl Storable for CanisterInfoVec {
 
 // --- End New Structs ---
 
+/// Implements `Storable` for types that are kept in stable maps, encoding them with ciborium.
+macro_rules! impl_cbor_storable {
+    ($($t:ty),*) => {
+        $(
+            impl Storable for $t {
+                fn to_bytes(&self) -> Cow<[u8]> {
+                    let mut bytes = vec![];
+                    ciborium::ser::into_writer(self, &mut bytes).unwrap();
+                    Cow::Owned(bytes)
+                }
+
+                fn from_bytes(bytes: Cow<[u8]>) -> Self {
+                    ciborium::de::from_reader(bytes.as_ref()).unwrap()
+                }
+
+                const BOUND: ic_stable_structures::storable::Bound =
+                    ic_stable_structures::storable::Bound::Unbounded;
+            }
+        )*
+    };
+}
+
+impl_cbor_storable!(
+    User,
+    File,
+    RequestGroup,
+    Folder,
+    Templates,
+    UploadSession,
+    StorageUsage,
+    StorageQuota,
+    VetkdKeyId,
+    RetiredAlias,
+    TimedAlias,
+    Notification,
+    Invitation,
+    InviteeInvitation
+);
+
+/// What a sharee may do with a shared file. Each level includes the ones below it.
+#[derive(
+    CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub enum SharePermission {
+    /// See the file's metadata.
+    #[serde(rename = "view")]
+    View,
+    /// Download the file and derive its key.
+    #[serde(rename = "download")]
+    Download,
+    /// Share the file with others, up to their own permission.
+    #[serde(rename = "reshare")]
+    Reshare,
+    /// Rename, delete and revoke shares of the file.
+    #[serde(rename = "manage")]
+    Manage,
+}
+
+/// A grant of access to a file.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Share {
+    pub permission: SharePermission,
+    /// When the share ends, if ever.
+    pub expires_at: Option<u64>,
+}
+
+impl Share {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// Shares are stored as their permission, followed by their expiry if they have one.
+impl Storable for Share {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        let mut bytes = vec![self.permission as u8];
+        if let Some(expires_at) = self.expires_at {
+            bytes.extend_from_slice(&expires_at.to_be_bytes());
+        }
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let permission = match bytes.first() {
+            // Shares that were granted before permissions existed are stored
+            // without a value and allowed to download the file.
+            None | Some(1) => SharePermission::Download,
+            Some(0) => SharePermission::View,
+            Some(2) => SharePermission::Reshare,
+            Some(3) => SharePermission::Manage,
+            Some(byte) => panic!("invalid share permission {byte}"),
+        };
+        let expires_at = bytes
+            .get(1..9)
+            .map(|expires_at| u64::from_be_bytes(expires_at.try_into().unwrap()));
+        Share {
+            permission,
+            expires_at,
+        }
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Bounded {
+            max_size: 9,
+            is_fixed_size: false,
+        };
+}
+
+/// A user's templates, keyed by template name.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+struct Templates(BTreeMap<String, Template>);
+
 // Memory IDs - Assuming existing IDs are 0, 1, 2 in memory.rs
 const USER_CANISTERS_MEMORY_ID: MemoryId = MemoryId::new(3); // Ensure this ID is unique
 
 thread_local! {
-    /// Initialize the state randomness with the current time.
-    static STATE: RefCell<State> = RefCell::new(State::new(&get_randomness_seed()[..]));
+    /// The alias generator is seeded once the canister got randomness.
+    static STATE: RefCell<State> = RefCell::new(State::new(init_alias_generator()));
 
     // The memory manager is used for managing memory chunks
     // Assuming MEMORY_MANAGER is already defined similarly in memory.rs or here
@@ -124,6 +239,9 @@ pub struct RequestGroup {
     pub files: Vec<u64>, // file_ids in this group
     pub requester: Principal,
     pub created_at: u64,
+    /// When the group's alias stops accepting uploads, if ever.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -131,6 +249,19 @@ pub struct MultiRequestInput {
     pub group_name: String,
     pub file_names: Vec<String>,
     pub save_as_template: bool,
+    /// When the aliases of the group and its files expire, if ever.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    /// When the group's files are due, if ever.
+    #[serde(default)]
+    pub due_at: Option<u64>,
+}
+
+/// Optional settings of a single file request.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RequestOptions {
+    pub expires_at: Option<u64>,
+    pub due_at: Option<u64>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -162,6 +293,49 @@ pub struct GroupInfo {
     pub requester: PublicUser,
 }
 
+/// A folder in a user's file tree. Folders without a parent live at the root.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Folder {
+    pub folder_id: u64,
+    pub name: String,
+    pub parent_id: Option<u64>,
+    pub owner: Principal,
+    pub created_at: u64,
+}
+
+/// The direct children of a folder (or of the root when no folder is given).
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FolderContents {
+    pub folder_id: Option<u64>,
+    pub folders: Vec<Folder>,
+    pub files: Vec<PublicFileMetadata>,
+    /// Where the next page starts, if there is one.
+    pub next_cursor: Option<FolderCursor>,
+}
+
+/// The last entry of a page of a folder listing. Folders are listed before files.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FolderCursor {
+    #[serde(rename = "folder")]
+    Folder(u64),
+    #[serde(rename = "file")]
+    File(u64),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum FolderError {
+    #[serde(rename = "not_found")]
+    NotFound,
+    #[serde(rename = "permission_error")]
+    PermissionError,
+    #[serde(rename = "name_exists")]
+    NameExists,
+    #[serde(rename = "not_empty")]
+    NotEmpty,
+    #[serde(rename = "invalid_parent")]
+    InvalidParent,
+}
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct User {
     pub username: String,
@@ -192,12 +366,98 @@ pub struct FileMetadata {
     pub requester_principal: Principal,
     pub requested_at: u64,
     pub uploaded_at: Option<u64>,
+    /// The folder the file lives in, or `None` if it is at the root.
+    #[serde(default)]
+    pub folder_id: Option<u64>,
+    /// The SHA-256 hash of each chunk, set once the file is uploaded.
+    #[serde(default)]
+    pub chunk_hashes: Vec<Vec<u8>>,
+    /// The hash over all chunk hashes, set once the file is uploaded.
+    #[serde(default)]
+    pub root_hash: Option<Vec<u8>>,
+    /// How the vetKD key that encrypts the file is derived.
+    #[serde(default)]
+    pub key_derivation: KeyDerivation,
+    /// The version of the file's next per-file key, set while the file waits
+    /// to be re-encrypted after a share was revoked.
+    #[serde(default)]
+    pub pending_key_version: Option<u32>,
+    /// When the alias of a pending request expires, if ever.
+    #[serde(default)]
+    pub alias_expires_at: Option<u64>,
+    /// When the requested file is due, if ever.
+    #[serde(default)]
+    pub due_at: Option<u64>,
+}
+
+impl FileMetadata {
+    /// Returns the vetKD derivation id of the key that encrypts the given file.
+    pub fn derivation_id(&self, file_id: u64) -> Vec<u8> {
+        match self.key_derivation {
+            KeyDerivation::Owner => self.requester_principal.as_slice().to_vec(),
+            KeyDerivation::PerFile { key_version } => {
+                file_derivation_id(self.requester_principal, file_id, key_version)
+            }
+        }
+    }
+}
+
+/// How the vetKD key that encrypts a file is derived.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub enum KeyDerivation {
+    /// The owner's principal is the derivation id, so all of the owner's files
+    /// share one key. Files stored before keys were scoped per file use it
+    /// until `migrate_to_per_file_keys` has them re-encrypted.
+    #[default]
+    #[serde(rename = "owner")]
+    Owner,
+    /// The derivation id is made of the owner's principal, the file id and the
+    /// version of the file's key.
+    #[serde(rename = "per_file")]
+    PerFile { key_version: u32 },
+}
+
+/// What a client needs to encrypt or decrypt a file with its vetKD key.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FileKeyInfo {
+    pub file_id: u64,
+    pub derivation_id: Vec<u8>,
+    pub key_derivation: KeyDerivation,
+    /// The derivation id of the key to re-encrypt the file with, if the owner
+    /// has to re-encrypt it.
+    pub reencryption_derivation_id: Option<Vec<u8>>,
+}
+
+/// What someone fulfilling a request needs to encrypt the file for the
+/// requester's vetKD identity with IBE.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AliasKeyInfo {
+    pub file_id: u64,
+    /// The public key of the canister's vetKD key.
+    pub public_key: Vec<u8>,
+    pub derivation_id: Vec<u8>,
+    pub key_derivation: KeyDerivation,
+}
+
+/// A chunk of a file re-encrypted for its new key.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReencryptedChunk {
+    pub file_id: u64,
+    pub chunk_id: u64,
+    pub contents: Vec<u8>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub enum FileStatus {
     #[serde(rename = "pending")]
     Pending { alias: String, requested_at: u64 },
+    /// A pending request whose due date has passed.
+    #[serde(rename = "overdue")]
+    Overdue {
+        alias: String,
+        requested_at: u64,
+        due_at: u64,
+    },
     #[serde(rename = "partially_uploaded")]
     PartiallyUploaded,
     #[serde(rename = "uploaded")]
@@ -214,14 +474,136 @@ pub struct PublicFileMetadata {
     pub file_name: String,
     pub group_name: String,
     pub group_alias: Option<String>,
+    pub folder_id: Option<u64>,
+    pub root_hash: Option<Vec<u8>>,
+    pub due_at: Option<u64>,
     pub file_status: FileStatus,
-    pub shared_with: Vec<PublicUser>,
+    pub shared_with: Vec<PublicSharee>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileStatusFilter {
+    /// Matches overdue requests too.
+    #[serde(rename = "pending")]
+    Pending,
+    #[serde(rename = "overdue")]
+    Overdue,
+    #[serde(rename = "partially_uploaded")]
+    PartiallyUploaded,
+    #[serde(rename = "uploaded")]
+    Uploaded,
+}
+
+/// The order of the file listings by file ID, i.e. by creation time. Other
+/// orderings, such as by upload time or name, aren't supported.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    #[serde(rename = "ascending")]
+    Ascending,
+    #[serde(rename = "descending")]
+    Descending,
+}
+
+/// A page request for the file listing endpoints. Files are ordered by their ID,
+/// which follows the order in which they were created.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ListFilesQuery {
+    /// The `next_cursor` returned with the previous page.
+    pub cursor: Option<u64>,
+    pub limit: Option<u64>,
+    pub status: Option<FileStatusFilter>,
+    pub group_id: Option<u64>,
+    pub name_prefix: Option<String>,
+    /// Only files uploaded at or after this time.
+    pub uploaded_from: Option<u64>,
+    /// Only files uploaded before this time.
+    pub uploaded_to: Option<u64>,
+    pub sort_order: Option<SortOrder>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ListFilesResponse {
+    pub files: Vec<PublicFileMetadata>,
+    /// Set if there are more files to fetch.
+    pub next_cursor: Option<u64>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub enum GetAliasInfoError {
     #[serde(rename = "not_found")]
     NotFound,
+    #[serde(rename = "expired")]
+    Expired,
+    /// The alias was regenerated or disabled by the requester.
+    #[serde(rename = "closed")]
+    Closed,
+}
+
+/// An event in a user's notification feed.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Notification {
+    pub notification_id: u64,
+    pub created_at: u64,
+    pub kind: NotificationKind,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum NotificationKind {
+    /// A requested file wasn't uploaded by its due date.
+    #[serde(rename = "overdue_request")]
+    OverdueRequest {
+        file_id: u64,
+        file_name: String,
+        due_at: u64,
+    },
+}
+
+/// A request whose alias is managed by its requester.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AliasTarget {
+    #[serde(rename = "file")]
+    File(u64),
+    #[serde(rename = "group")]
+    Group(u64),
+}
+
+/// Why an alias no longer accepts uploads.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AliasRetirement {
+    Expired,
+    Closed,
+}
+
+/// An alias that used to open a request, kept so that its users can be told
+/// why it stopped working.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RetiredAlias {
+    pub target: AliasTarget,
+    pub reason: AliasRetirement,
+    #[serde(default)]
+    pub retired_at: u64,
+}
+
+/// An alias along with when it expires or was retired, as kept in the alias
+/// expiry and retirement maps.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimedAlias {
+    pub at: u64,
+    pub alias: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AliasError {
+    #[serde(rename = "not_found")]
+    NotFound,
+    #[serde(rename = "permission_error")]
+    PermissionError,
+    #[serde(rename = "already_uploaded")]
+    AlreadyUploaded,
+    /// No unused alias could be generated, e.g. because the alias generator
+    /// isn't seeded yet. Trying again later may succeed.
+    #[serde(rename = "unavailable")]
+    Unavailable,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -266,6 +648,8 @@ pub struct FileData {
     // Remove owner_key field as it's not needed with VetKD
     // owner_key: Vec<u8>,
     num_chunks: u64,
+    chunk_hash: Option<Vec<u8>>,
+    root_hash: Option<Vec<u8>>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, PartialEq, Debug)]
@@ -280,12 +664,261 @@ pub enum FileDownloadResponse {
     FoundFile(FileData),
 }
 
-#[derive(Debug, CandidType, Serialize, Deserialize)]
+#[derive(Debug, CandidType, Serialize, Deserialize, PartialEq)]
 pub enum UploadFileError {
     #[serde(rename = "not_requested")]
     NotRequested,
     #[serde(rename = "already_uploaded")]
     AlreadyUploaded,
+    #[serde(rename = "hash_mismatch")]
+    HashMismatch,
+    #[serde(rename = "upload_not_started")]
+    UploadNotStarted,
+    #[serde(rename = "chunk_out_of_range")]
+    ChunkOutOfRange,
+    #[serde(rename = "chunk_already_uploaded")]
+    ChunkAlreadyUploaded,
+    #[serde(rename = "chunk_too_large")]
+    ChunkTooLarge,
+    #[serde(rename = "invalid_chunk_size")]
+    InvalidChunkSize,
+    #[serde(rename = "invalid_file_size")]
+    InvalidFileSize,
+    #[serde(rename = "permission_error")]
+    PermissionError,
+    #[serde(rename = "quota_exceeded")]
+    QuotaExceeded,
+    #[serde(rename = "no_key_rotation")]
+    NoKeyRotation,
+    #[serde(rename = "missing_chunks")]
+    MissingChunks,
+    #[serde(rename = "alias_mismatch")]
+    AliasMismatch,
+    #[serde(rename = "alias_expired")]
+    AliasExpired,
+}
+
+/// The layout of a file that is uploaded in multiple chunks, as declared by
+/// the uploader when the upload starts.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UploadSession {
+    /// None if the uploader didn't declare the size of the file.
+    pub total_size: Option<u64>,
+    pub chunk_size: u64,
+    pub num_chunks: u64,
+    /// The alias of the request the file is uploaded for, if any.
+    #[serde(default)]
+    pub alias: Option<String>,
+    #[serde(default)]
+    pub created_at: u64,
+    /// When the upload is abandoned if no further chunk arrives.
+    #[serde(default)]
+    pub expires_at: u64,
+}
+
+impl UploadSession {
+    /// Returns whether the given chunk has the expected size. Only the last
+    /// chunk may be smaller than the chunk size, and only a single chunk may be
+    /// empty.
+    pub fn fits_chunk(&self, chunk_id: u64, len: u64) -> bool {
+        if chunk_id + 1 < self.num_chunks {
+            return len == self.chunk_size;
+        }
+        match self.total_size {
+            Some(total_size) => len == total_size - self.chunk_size * (self.num_chunks - 1),
+            None => len <= self.chunk_size && (len > 0 || self.num_chunks == 1),
+        }
+    }
+
+    /// The largest size the file can have once uploaded.
+    pub fn max_size(&self) -> u64 {
+        self.total_size
+            .unwrap_or(self.chunk_size.saturating_mul(self.num_chunks))
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UploadProgress {
+    pub num_chunks: u64,
+    /// The IDs of the chunks that still need to be uploaded.
+    pub missing_chunks: Vec<u64>,
+}
+
+/// The storage taken up by a user's files.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StorageUsage {
+    pub bytes: u64,
+    pub file_count: u64,
+}
+
+/// The most storage a user's files may take up.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StorageQuota {
+    pub max_bytes: u64,
+    pub max_files: u64,
+}
+
+impl Default for StorageQuota {
+    fn default() -> Self {
+        Self {
+            max_bytes: 1 << 30,
+            max_files: 10_000,
+        }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StorageUsageResponse {
+    pub usage: StorageUsage,
+    pub quota: StorageQuota,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Debug, PartialEq)]
+pub enum AdminError {
+    #[serde(rename = "permission_error")]
+    PermissionError,
+    #[serde(rename = "not_found")]
+    NotFound,
+    #[serde(rename = "key_in_use")]
+    KeyInUse,
+    /// The alias format's aliases could be guessed.
+    #[serde(rename = "weak_alias_format")]
+    WeakAliasFormat,
+}
+
+/// A user as seen by admins.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AdminUserInfo {
+    pub ic_principal: Principal,
+    pub username: String,
+    pub usage: StorageUsage,
+    pub suspended: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StorageStats {
+    pub user_count: u64,
+    pub file_count: u64,
+    pub stored_bytes: u64,
+    pub upload_session_count: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum VetkdError {
+    #[serde(rename = "anonymous_caller")]
+    AnonymousCaller,
+    #[serde(rename = "not_found")]
+    NotFound,
+    #[serde(rename = "permission_error")]
+    PermissionError,
+    /// The vetKD system API rejected the call.
+    #[serde(rename = "system_api_error")]
+    SystemApiError(String),
+}
+
+/// Settings of the canister that admins can change.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CanisterConfig {
+    /// The name of the vetKD key that file keys are derived from.
+    pub vetkd_key_name: String,
+    /// The derivation path of the canister's vetKD keys.
+    #[serde(default)]
+    pub vetkd_derivation_path: Vec<Vec<u8>>,
+    /// How the secret aliases of requests are generated.
+    #[serde(default)]
+    pub alias_format: AliasFormat,
+}
+
+impl Default for CanisterConfig {
+    fn default() -> Self {
+        Self {
+            vetkd_key_name: VetkdKeyProfile::Local.key_name().to_string(),
+            vetkd_derivation_path: vec![],
+            alias_format: AliasFormat::default(),
+        }
+    }
+}
+
+impl CanisterConfig {
+    /// Whether the configs derive different keys for the same derivation id.
+    pub fn changes_vetkd_key(&self, other: &CanisterConfig) -> bool {
+        self.vetkd_key_id() != other.vetkd_key_id()
+    }
+
+    pub fn vetkd_key_id(&self) -> VetkdKeyId {
+        VetkdKeyId {
+            name: self.vetkd_key_name.clone(),
+            derivation_path: self.vetkd_derivation_path.clone(),
+        }
+    }
+}
+
+/// A vetKD key together with the derivation path the canister uses it with.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VetkdKeyId {
+    pub name: String,
+    pub derivation_path: Vec<Vec<u8>>,
+}
+
+/// How many aliases are generated before giving up on finding an unused one.
+pub const MAX_ALIAS_ATTEMPTS: usize = 16;
+
+/// The shape of generated aliases. Anyone who guesses an alias can upload to
+/// its request, so the format determines how hard that is.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AliasFormat {
+    /// Dash-separated words followed by a numeric suffix, e.g.
+    /// "brave-quiet-green-mountain-0421".
+    #[serde(rename = "words")]
+    Words { num_words: u8, suffix_digits: u8 },
+    /// A token of base58 characters.
+    #[serde(rename = "base58")]
+    Base58 { length: u8 },
+}
+
+impl Default for AliasFormat {
+    /// About 53 bits of entropy.
+    fn default() -> Self {
+        AliasFormat::Words {
+            num_words: 4,
+            suffix_digits: 4,
+        }
+    }
+}
+
+/// The environments the canister is deployed to, each with its own vetKD key.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VetkdKeyProfile {
+    /// The key of the local vetKD system API canister.
+    #[serde(rename = "local")]
+    Local,
+    #[serde(rename = "staging")]
+    Staging,
+    #[serde(rename = "mainnet")]
+    Mainnet,
+}
+
+impl VetkdKeyProfile {
+    pub fn key_name(self) -> &'static str {
+        match self {
+            VetkdKeyProfile::Local => "insecure_test_key_1",
+            VetkdKeyProfile::Staging => "test_key_1",
+            VetkdKeyProfile::Mainnet => "key_1",
+        }
+    }
+}
+
+/// The arguments the canister is installed or upgraded with. Settings that
+/// are left out keep their current value.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct CanisterArgs {
+    pub vetkd_key_profile: Option<VetkdKeyProfile>,
+    /// Takes precedence over `vetkd_key_profile`.
+    pub vetkd_key_name: Option<String>,
+    pub vetkd_derivation_path: Option<Vec<Vec<u8>>>,
+    /// Allows switching the vetKD key while files are encrypted with the current one.
+    pub force_key_change: Option<bool>,
+    pub alias_format: Option<AliasFormat>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Debug, PartialEq)]
@@ -296,6 +929,77 @@ pub enum FileSharingResponse {
     PermissionError,
     #[serde(rename = "ok")]
     Ok,
+    #[serde(rename = "unknown_user")]
+    UnknownUser,
+    /// Another file in the same folder has the name.
+    #[serde(rename = "name_exists")]
+    NameExists,
+}
+
+/// Who an invitation is for.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Invitee {
+    /// Whoever registers with this username.
+    #[serde(rename = "username")]
+    Username(String),
+    /// Whoever redeems this code.
+    #[serde(rename = "code")]
+    Code(String),
+}
+
+/// A share with someone who isn't registered yet, made once they are.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Invitation {
+    pub invitation_id: u64,
+    pub file_id: u64,
+    pub inviter: Principal,
+    pub invitee: Invitee,
+    pub permission: SharePermission,
+    /// When the share ends once it is made, if ever.
+    pub expires_at: Option<u64>,
+    pub created_at: u64,
+}
+
+/// An invitation to the invitee, as kept in the invitee index.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct InviteeInvitation {
+    pub invitee: Invitee,
+    pub file_id: u64,
+    pub invitation_id: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Debug, PartialEq)]
+pub enum InviteResponse {
+    /// The invitee is registered, and the file was shared with them.
+    #[serde(rename = "shared")]
+    Shared,
+    #[serde(rename = "invited")]
+    Invited(Invitation),
+    #[serde(rename = "pending_error")]
+    PendingError,
+    #[serde(rename = "permission_error")]
+    PermissionError,
+    /// No invitation code could be generated. Trying again later may succeed.
+    #[serde(rename = "alias_unavailable")]
+    AliasUnavailable,
+}
+
+/// Shares every file in `file_ids` with every user in `user_ids`.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ShareFilesRequest {
+    pub file_ids: Vec<u64>,
+    pub user_ids: Vec<Principal>,
+    /// Defaults to the download permission.
+    pub permission: Option<SharePermission>,
+    pub expires_at: Option<u64>,
+}
+
+/// The outcome of sharing or revoking one file with one user in a batch.
+#[derive(CandidType, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ShareResult {
+    pub file_id: u64,
+    pub user_id: Principal,
+    pub result: FileSharingResponse,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -304,6 +1008,10 @@ pub struct Template {
     pub file_names: Vec<String>,
 }
 
+/// The state of the canister.
+///
+/// All maps live in stable structures, so only the counters are serialized
+/// on upgrades.
 #[derive(Serialize, Deserialize)]
 pub struct State {
     // Keeps track of how many files have been requested so far
@@ -311,19 +1019,24 @@ pub struct State {
     file_count: u64,
 
     /// Keeps track of usernames vs. their principals.
-    pub users: BTreeMap<Principal, User>,
+    #[serde(skip, default = "init_users")]
+    pub users: StableBTreeMap<Principal, User, Memory>,
 
     /// Mapping between file IDs and file information.
-    pub file_data: BTreeMap<u64, File>,
+    #[serde(skip, default = "init_file_data")]
+    pub file_data: StableBTreeMap<u64, File, Memory>,
 
     /// Mapping between file aliases (randomly generated links) and file ID.
-    pub file_alias_index: BTreeMap<String, u64>,
+    #[serde(skip, default = "init_file_alias_index")]
+    pub file_alias_index: StableBTreeMap<String, u64, Memory>,
 
-    /// Mapping between a user's principal and the list of files that are owned by the user.
-    pub file_owners: BTreeMap<Principal, Vec<u64>>,
+    /// Pairs of a user's principal and the ID of a file that is owned by the user.
+    #[serde(skip, default = "init_file_owners")]
+    pub file_owners: StableBTreeMap<(Principal, u64), (), Memory>,
 
-    /// Mapping between a user's principal and the list of files that are shared with them.
-    pub file_shares: BTreeMap<Principal, Vec<u64>>,
+    /// Pairs of a user's principal and the ID of a file that is shared with them.
+    #[serde(skip, default = "init_file_shares")]
+    pub file_shares: StableBTreeMap<(Principal, u64), Share, Memory>,
 
     /// The contents of the file (stored in stable memory).
     #[serde(skip, default = "init_file_contents")]
@@ -337,14 +1050,108 @@ pub struct State {
     group_count: u64,
 
     /// Mapping between group IDs and request groups
-    pub request_groups: BTreeMap<u64, RequestGroup>,
+    #[serde(skip, default = "init_request_groups")]
+    pub request_groups: StableBTreeMap<u64, RequestGroup, Memory>,
 
     /// Mapping between group aliases and group IDs
-    group_alias_index: BTreeMap<String, u64>,
-    /// Mapping between group IDs and their file IDs
-    group_files: BTreeMap<u64, Vec<u64>>,
-
-    user_templates: BTreeMap<Principal, BTreeMap<String, Template>>,
+    #[serde(skip, default = "init_group_alias_index")]
+    group_alias_index: StableBTreeMap<String, u64, Memory>,
+    /// Pairs of a group ID and the ID of a file in the group
+    #[serde(skip, default = "init_group_files")]
+    group_files: StableBTreeMap<(u64, u64), (), Memory>,
+
+    #[serde(skip, default = "init_user_templates")]
+    user_templates: StableBTreeMap<Principal, Templates, Memory>,
+
+    /// Counter for folder IDs
+    #[serde(default)]
+    folder_count: u64,
+
+    /// Mapping between folder IDs and folders
+    #[serde(skip, default = "init_folders")]
+    pub folders: StableBTreeMap<u64, Folder, Memory>,
+
+    /// Mapping between the IDs of files being uploaded in chunks and their upload sessions
+    #[serde(skip, default = "init_upload_sessions")]
+    pub upload_sessions: StableBTreeMap<u64, UploadSession, Memory>,
+
+    /// The bytes and files each user stores, which count against their quota.
+    #[serde(skip, default = "init_storage_usage")]
+    pub storage_usage: StableBTreeMap<Principal, StorageUsage, Memory>,
+
+    /// Quotas of users that don't get the default quota.
+    #[serde(skip, default = "init_storage_quotas")]
+    pub storage_quotas: StableBTreeMap<Principal, StorageQuota, Memory>,
+
+    /// The quota of users without a quota of their own.
+    #[serde(default)]
+    pub default_quota: StorageQuota,
+
+    /// Principals that can use the admin endpoints besides the canister's controllers.
+    #[serde(skip, default = "init_admins")]
+    pub admins: StableBTreeMap<Principal, (), Memory>,
+
+    /// Principals that may no longer call the canister.
+    #[serde(skip, default = "init_suspended_users")]
+    pub suspended_users: StableBTreeMap<Principal, (), Memory>,
+
+    #[serde(default)]
+    pub config: CanisterConfig,
+
+    /// File ids reserved for uploads that haven't started yet, by who reserved
+    /// them, along with when they were reserved.
+    #[serde(skip, default = "init_reserved_file_ids")]
+    pub reserved_file_ids: StableBTreeMap<(Principal, u64), u64, Memory>,
+
+    /// Former sharees of a file and the derivation id of the key they held.
+    #[serde(skip, default = "init_revoked_shares")]
+    pub revoked_shares: StableBTreeMap<(u64, Principal), Vec<u8>, Memory>,
+
+    /// Chunks of files re-encrypted for their new key, waiting to replace the file contents.
+    #[serde(skip, default = "init_reencrypted_contents")]
+    pub reencrypted_contents: StableBTreeMap<(FileId, ChunkId), Vec<u8>, Memory>,
+
+    /// The public keys of the vetKD keys the canister was configured with.
+    #[serde(skip, default = "init_vetkd_public_keys")]
+    pub vetkd_public_keys: StableBTreeMap<VetkdKeyId, Vec<u8>, Memory>,
+
+    /// Aliases that were regenerated, disabled or expired.
+    #[serde(skip, default = "init_retired_aliases")]
+    pub retired_aliases: StableBTreeMap<String, RetiredAlias, Memory>,
+
+    /// Aliases that expire, keyed by when they expire. Aliases that were
+    /// replaced or used up in the meantime are skipped once they come due.
+    #[serde(skip, default = "init_alias_expiries")]
+    pub alias_expiries: StableBTreeMap<TimedAlias, (), Memory>,
+
+    /// The retired aliases, keyed by when they were retired.
+    #[serde(skip, default = "init_alias_retirements")]
+    pub alias_retirements: StableBTreeMap<TimedAlias, (), Memory>,
+
+    /// Requests with a due date that haven't been reminded of yet, keyed by
+    /// (due date, file ID).
+    #[serde(skip, default = "init_due_requests")]
+    pub due_requests: StableBTreeMap<(u64, u64), (), Memory>,
+
+    /// Each user's notification feed, keyed by (user, notification ID).
+    #[serde(skip, default = "init_notifications")]
+    pub notifications: StableBTreeMap<(Principal, u64), Notification, Memory>,
+
+    /// Used to assign IDs to notifications.
+    #[serde(default)]
+    notification_count: u64,
+
+    /// Shares waiting for their invitee to register, keyed by (file ID, invitation ID).
+    #[serde(skip, default = "init_invitations")]
+    pub invitations: StableBTreeMap<(u64, u64), Invitation, Memory>,
+
+    /// Used to assign IDs to invitations.
+    #[serde(default)]
+    invitation_count: u64,
+
+    /// Reverse lookups that are derived from the maps above.
+    #[serde(skip, default = "Indexes::init")]
+    indexes: Indexes,
     // Note: user_canisters map is now managed separately via USER_CANISTERS thread_local
 }
 
@@ -365,35 +1172,275 @@ impl State {
         group_id
     }
 
-    fn new(rand_seed: &[u8]) -> Self {
+    /// Schedules the expiry of an alias, if it expires.
+    pub(crate) fn schedule_alias_expiry(&mut self, alias: &str, expires_at: Option<u64>) {
+        if let Some(expires_at) = expires_at.filter(|_| !alias.is_empty()) {
+            self.alias_expiries.insert(
+                TimedAlias {
+                    at: expires_at,
+                    alias: alias.to_string(),
+                },
+                (),
+            );
+        }
+    }
+
+    /// Schedules a reminder for when the request is due, if it has a due date.
+    pub(crate) fn schedule_reminder(&mut self, file_id: u64, due_at: Option<u64>) {
+        if let Some(due_at) = due_at {
+            self.due_requests.insert((due_at, file_id), ());
+        }
+    }
+
+    /// Adds a notification to the user's feed.
+    pub(crate) fn notify(&mut self, user: Principal, kind: NotificationKind) {
+        let notification_id = self.notification_count;
+        self.notification_count += 1;
+        self.notifications.insert(
+            (user, notification_id),
+            Notification {
+                notification_id,
+                created_at: get_time(),
+                kind,
+            },
+        );
+    }
+
+    /// Records an invitation to share the file once the invitee registers.
+    pub(crate) fn invite(
+        &mut self,
+        inviter: Principal,
+        invitee: Invitee,
+        file_id: u64,
+        permission: SharePermission,
+        expires_at: Option<u64>,
+    ) -> Invitation {
+        let invitation = Invitation {
+            invitation_id: self.invitation_count,
+            file_id,
+            inviter,
+            invitee,
+            permission,
+            expires_at,
+            created_at: get_time(),
+        };
+        self.invitation_count += 1;
+        self.invitations
+            .insert((file_id, invitation.invitation_id), invitation.clone());
+        self.indexes.add_invitation(&invitation);
+        invitation
+    }
+
+    pub(crate) fn remove_invitation(
+        &mut self,
+        file_id: u64,
+        invitation_id: u64,
+    ) -> Option<Invitation> {
+        let invitation = self.invitations.remove(&(file_id, invitation_id))?;
+        self.indexes.remove_invitation(&invitation);
+        Some(invitation)
+    }
+
+    /// Starts or refreshes the upload session of a file.
+    pub(crate) fn insert_upload_session(&mut self, file_id: u64, session: UploadSession) {
+        if let Some(old) = self.upload_sessions.insert(file_id, session.clone()) {
+            self.indexes.remove_upload_session(file_id, &old);
+        }
+        self.indexes.add_upload_session(file_id, &session);
+    }
+
+    pub(crate) fn remove_upload_session(&mut self, file_id: u64) -> Option<UploadSession> {
+        let session = self.upload_sessions.remove(&file_id)?;
+        self.indexes.remove_upload_session(file_id, &session);
+        Some(session)
+    }
+
+    /// Returns an alias that is used by neither a file nor a request group,
+    /// and wasn't retired recently.
+    pub(crate) fn generate_alias(&mut self) -> Result<String, AliasError> {
+        for _ in 0..MAX_ALIAS_ATTEMPTS {
+            let alias = self
+                .alias_generator
+                .next(&self.config.alias_format)
+                .ok_or(AliasError::Unavailable)?;
+            if !self.file_alias_index.contains_key(&alias)
+                && !self.group_alias_index.contains_key(&alias)
+                && !self.retired_aliases.contains_key(&alias)
+            {
+                return Ok(alias);
+            }
+        }
+        Err(AliasError::Unavailable)
+    }
+
+    pub(crate) fn generate_folder_id(&mut self) -> u64 {
+        let folder_id = self.folder_count;
+        self.folder_count += 1;
+        folder_id
+    }
+
+    fn new(alias_generator: AliasGenerator) -> Self {
         Self {
             file_count: 0,
-            users: BTreeMap::new(),
-            file_data: BTreeMap::new(),
-            file_alias_index: BTreeMap::new(),
-            file_owners: BTreeMap::new(),
-            file_shares: BTreeMap::new(),
-            alias_generator: AliasGenerator::new(Randomness::try_from(rand_seed).unwrap()),
+            users: init_users(),
+            file_data: init_file_data(),
+            file_alias_index: init_file_alias_index(),
+            file_owners: init_file_owners(),
+            file_shares: init_file_shares(),
+            alias_generator,
             file_contents: init_file_contents(),
             group_count: 0,
-            request_groups: BTreeMap::new(),
-            group_alias_index: BTreeMap::new(),
-            group_files: BTreeMap::new(),
-            user_templates: BTreeMap::new(),
+            request_groups: init_request_groups(),
+            group_alias_index: init_group_alias_index(),
+            group_files: init_group_files(),
+            user_templates: init_user_templates(),
+            folder_count: 0,
+            folders: init_folders(),
+            upload_sessions: init_upload_sessions(),
+            storage_usage: init_storage_usage(),
+            storage_quotas: init_storage_quotas(),
+            default_quota: StorageQuota::default(),
+            admins: init_admins(),
+            suspended_users: init_suspended_users(),
+            config: CanisterConfig::default(),
+            reserved_file_ids: init_reserved_file_ids(),
+            revoked_shares: init_revoked_shares(),
+            reencrypted_contents: init_reencrypted_contents(),
+            vetkd_public_keys: init_vetkd_public_keys(),
+            retired_aliases: init_retired_aliases(),
+            alias_expiries: init_alias_expiries(),
+            alias_retirements: init_alias_retirements(),
+            due_requests: init_due_requests(),
+            notifications: init_notifications(),
+            notification_count: 0,
+            invitations: init_invitations(),
+            invitation_count: 0,
+            indexes: Indexes::init(),
+        }
+    }
+
+    /// Returns the IDs of the files owned by the given user.
+    pub fn owned_files(&self, owner: Principal) -> Vec<u64> {
+        self.file_owners
+            .range((owner, 0)..=(owner, u64::MAX))
+            .map(|((_, file_id), _)| file_id)
+            .collect()
+    }
+
+    pub fn is_owner(&self, owner: Principal, file_id: u64) -> bool {
+        self.file_owners.contains_key(&(owner, file_id))
+    }
+
+    /// Returns the IDs of the files shared with the given user, leaving out
+    /// expired shares.
+    pub fn shared_files(&self, user: Principal) -> Vec<u64> {
+        let now = get_time();
+        self.file_shares
+            .range((user, 0)..=(user, u64::MAX))
+            .filter(|(_, share)| !share.is_expired(now))
+            .map(|((_, file_id), _)| file_id)
+            .collect()
+    }
+
+    /// Whether the user owns the file or it is shared with them with at least
+    /// the given permission, and the share hasn't expired.
+    pub fn has_permission(
+        &self,
+        user: Principal,
+        file_id: u64,
+        permission: SharePermission,
+    ) -> bool {
+        self.is_owner(user, file_id)
+            || self.file_shares.get(&(user, file_id)).is_some_and(|share| {
+                share.permission >= permission && !share.is_expired(get_time())
+            })
+    }
+
+    /// Returns the IDs of the files in the given request group.
+    pub(crate) fn group_file_ids(&self, group_id: u64) -> Vec<u64> {
+        self.group_files
+            .range((group_id, 0)..=(group_id, u64::MAX))
+            .map(|((_, file_id), _)| file_id)
+            .collect()
+    }
+
+    /// Hashes the chunks of a file that has been fully uploaded and stores
+    /// the hashes in its metadata.
+    pub(crate) fn hash_file_contents(&self, file_id: u64, metadata: &mut FileMetadata) {
+        metadata.chunk_hashes = self
+            .file_contents
+            .range((file_id, 0)..=(file_id, u64::MAX))
+            .map(|(_, chunk)| sha256(&chunk))
+            .collect();
+        metadata.root_hash = Some(root_hash(&metadata.chunk_hashes));
+    }
+
+    /// Returns the IDs of the stored chunks of a file.
+    pub(crate) fn chunk_ids(&self, file_id: u64) -> impl Iterator<Item = u64> + '_ {
+        self.file_contents
+            .range((file_id, 0)..=(file_id, u64::MAX))
+            .map(|((_, chunk_id), _)| chunk_id)
+    }
+
+    /// Removes the stored chunks of a file.
+    pub(crate) fn remove_file_contents(&mut self, file_id: u64) {
+        let chunk_ids: Vec<u64> = self.chunk_ids(file_id).collect();
+        for chunk_id in chunk_ids {
+            self.file_contents.remove(&(file_id, chunk_id));
         }
     }
 
+    /// Rebuilds the reverse indexes from the rest of the state.
+    #[cfg(test)]
+    pub(crate) fn rebuild_indexes(&mut self) {
+        self.indexes.rebuild(
+            &self.request_groups,
+            &self.group_alias_index,
+            &self.file_shares,
+        );
+        self.indexes
+            .rebuild_folders(&self.folders, &self.file_owners, &self.file_data);
+        self.indexes.rebuild_invitations(&self.invitations);
+        self.indexes.rebuild_upload_sessions(&self.upload_sessions);
+        self.indexes.rebuild_users(&self.users);
+    }
+
+    /// Asserts that the reverse indexes match the rest of the state.
+    #[cfg(test)]
+    pub(crate) fn check_indexes(&self) {
+        self.indexes.check(
+            &self.request_groups,
+            &self.group_alias_index,
+            &self.file_shares,
+        );
+        self.indexes
+            .check_folders(&self.folders, &self.file_owners, &self.file_data);
+        self.indexes.check_invitations(&self.invitations);
+        self.indexes.check_upload_sessions(&self.upload_sessions);
+        self.indexes.check_users(&self.users);
+    }
+
     /// Returns the number of uploaded chunks for the given file id
     pub(crate) fn num_chunks_uploaded(&self, file_id: u64) -> u64 {
         self.file_contents
             .range((Included((file_id, 0u64)), Excluded(((file_id + 1), 0u64))))
             .count() as u64
     }
+
+    /// Returns the number of bytes stored for the given file id
+    pub(crate) fn stored_bytes(&self, file_id: u64) -> u64 {
+        self.file_contents
+            .range((Included((file_id, 0u64)), Excluded(((file_id + 1), 0u64))))
+            .map(|(_, chunk)| chunk.len() as u64)
+            .sum()
+    }
 }
 
 impl Default for State {
     fn default() -> Self {
-        State::new(vec![0; 32].as_slice())
+        State::new(AliasGenerator::new(
+            Randomness::try_from([0; 32].as_slice()).unwrap(),
+        ))
     }
 }
 
@@ -412,8 +1459,27 @@ pub fn with_state_mut<R>(f: impl FnOnce(&mut State) -> R) -> R {
 }
 
 /// Returns an unused file alias.
-pub fn generate_alias() -> String {
-    with_state_mut(|s| s.alias_generator.next())
+pub fn generate_alias() -> Result<String, AliasError> {
+    with_state_mut(|s| s.generate_alias())
+}
+
+/// Seeds the alias generator from the management canister's randomness, as
+/// it generates no aliases before.
+pub async fn reseed_alias_generator() -> Result<(), String> {
+    let randomness = match ic_cdk::api::management_canister::main::raw_rand().await {
+        Ok((bytes,)) => Randomness::try_from(bytes.as_slice()).map_err(str::to_string),
+        Err((code, message)) => Err(format!("{:?}: {}", code, message)),
+    };
+    match randomness {
+        Ok(randomness) => {
+            with_state_mut(|s| s.alias_generator.reseed(randomness));
+            Ok(())
+        }
+        Err(err) => {
+            ic_cdk::println!("Failed to reseed the alias generator: {}", err);
+            Err(err)
+        }
+    }
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -423,6 +1489,16 @@ pub struct PublicUser {
     pub ic_principal: Principal,
 }
 
+/// A user a file is shared with, along with what they may do with it.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PublicSharee {
+    pub username: String,
+    pub public_key: Vec<u8>,
+    pub ic_principal: Principal,
+    pub permission: SharePermission,
+    pub expires_at: Option<u64>,
+}
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum GetUsersResponse {
     #[serde(rename = "permission_error")]
@@ -468,11 +1544,17 @@ pub enum DeleteCanisterResponse {
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct UploadFileRequest {
     pub file_id: u64,
+    /// The alias of the request, or of the request group the file belongs to.
+    pub alias: String,
     pub file_content: Vec<u8>,
     pub file_type: String,
     // Not needed for VetKD
     // pub owner_key: Vec<u8>,
     pub num_chunks: u64,
+    /// Defaults to the size of a single-chunk file, or unchecked otherwise.
+    pub total_size: Option<u64>,
+    /// Defaults to the size of the first chunk.
+    pub chunk_size: Option<u64>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -480,6 +1562,10 @@ pub struct UploadFileContinueRequest {
     pub file_id: u64,
     pub chunk_id: u64,
     pub contents: Vec<u8>,
+    /// The SHA-256 hash of `contents`, if the client wants it verified.
+    pub sha256: Option<Vec<u8>>,
+    /// The alias the upload was started with, if the file was requested.
+    pub alias: Option<String>,
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -493,16 +1579,63 @@ pub fn get_time() -> u64 {
     12345
 }
 
-fn get_randomness_seed() -> Vec<u8> {
-    // this is an array of u8 of length 8.
-    let time_seed = ic_cdk::api::time().to_be_bytes();
-    // we need to extend this to an array of size 32 by adding to it an array of size 24 full of 0s.
-    let zeroes_arr: [u8; 24] = [0; 24];
-    [&time_seed[..], &zeroes_arr[..]].concat()
+#[cfg(target_arch = "wasm32")]
+pub fn is_controller(principal: &Principal) -> bool {
+    ic_cdk::api::is_controller(principal)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn is_controller(_principal: &Principal) -> bool {
+    // Tests have no controllers, so admins are added explicitly.
+    false
+}
+
+/// Guard that rejects calls from suspended principals.
+pub fn caller_not_suspended() -> Result<(), String> {
+    if with_state(|s| api::is_suspended(s, ic_cdk::caller())) {
+        return Err("The caller is suspended".to_string());
+    }
+    Ok(())
 }
 
 fn init_alias_generator() -> AliasGenerator {
-    AliasGenerator::new(Randomness::try_from(get_randomness_seed().as_slice()).unwrap())
+    if cfg!(target_arch = "wasm32") {
+        // Seeded with `raw_rand` right after install and upgrade.
+        AliasGenerator::unseeded()
+    } else {
+        // Tests have no `raw_rand`, so they are seeded with the fixed time.
+        let mut seed = get_time().to_be_bytes().to_vec();
+        seed.resize(32, 0);
+        AliasGenerator::new(Randomness::try_from(seed.as_slice()).unwrap())
+    }
+}
+
+/// Returns the SHA-256 hash of the given data.
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+/// Returns the root hash of a file, which is the SHA-256 hash of its chunk
+/// hashes concatenated in chunk order.
+pub fn root_hash(chunk_hashes: &[Vec<u8>]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    for chunk_hash in chunk_hashes {
+        hasher.update(chunk_hash);
+    }
+    hasher.finalize().to_vec()
+}
+
+/// Builds the derivation id of a per-file key. The principal is length-prefixed
+/// so that no two files share a derivation id.
+pub fn file_derivation_id(owner: Principal, file_id: u64, key_version: u32) -> Vec<u8> {
+    let owner = owner.as_slice();
+    [
+        &[owner.len() as u8][..],
+        owner,
+        &file_id.to_be_bytes(),
+        &key_version.to_be_bytes(),
+    ]
+    .concat()
 }
 
 pub fn ceil_division(dividend: usize, divisor: usize) -> usize {
@@ -516,3 +1649,103 @@ pub fn ceil_division(dividend: usize, divisor: usize) -> usize {
 fn init_file_contents() -> StableBTreeMap<(FileId, ChunkId), Vec<u8>, Memory> {
     StableBTreeMap::init(crate::memory::get_file_contents_memory())
 }
+
+fn init_users() -> StableBTreeMap<Principal, User, Memory> {
+    StableBTreeMap::init(crate::memory::get_users_memory())
+}
+
+fn init_file_data() -> StableBTreeMap<u64, File, Memory> {
+    StableBTreeMap::init(crate::memory::get_file_data_memory())
+}
+
+fn init_file_alias_index() -> StableBTreeMap<String, u64, Memory> {
+    StableBTreeMap::init(crate::memory::get_file_alias_index_memory())
+}
+
+fn init_file_owners() -> StableBTreeMap<(Principal, u64), (), Memory> {
+    StableBTreeMap::init(crate::memory::get_file_owners_memory())
+}
+
+fn init_file_shares() -> StableBTreeMap<(Principal, u64), Share, Memory> {
+    StableBTreeMap::init(crate::memory::get_file_shares_memory())
+}
+
+fn init_request_groups() -> StableBTreeMap<u64, RequestGroup, Memory> {
+    StableBTreeMap::init(crate::memory::get_request_groups_memory())
+}
+
+fn init_group_alias_index() -> StableBTreeMap<String, u64, Memory> {
+    StableBTreeMap::init(crate::memory::get_group_alias_index_memory())
+}
+
+fn init_group_files() -> StableBTreeMap<(u64, u64), (), Memory> {
+    StableBTreeMap::init(crate::memory::get_group_files_memory())
+}
+
+fn init_user_templates() -> StableBTreeMap<Principal, Templates, Memory> {
+    StableBTreeMap::init(crate::memory::get_user_templates_memory())
+}
+
+fn init_folders() -> StableBTreeMap<u64, Folder, Memory> {
+    StableBTreeMap::init(crate::memory::get_folders_memory())
+}
+
+fn init_upload_sessions() -> StableBTreeMap<u64, UploadSession, Memory> {
+    StableBTreeMap::init(crate::memory::get_upload_sessions_memory())
+}
+
+fn init_storage_usage() -> StableBTreeMap<Principal, StorageUsage, Memory> {
+    StableBTreeMap::init(crate::memory::get_storage_usage_memory())
+}
+
+fn init_storage_quotas() -> StableBTreeMap<Principal, StorageQuota, Memory> {
+    StableBTreeMap::init(crate::memory::get_storage_quotas_memory())
+}
+
+fn init_admins() -> StableBTreeMap<Principal, (), Memory> {
+    StableBTreeMap::init(crate::memory::get_admins_memory())
+}
+
+fn init_suspended_users() -> StableBTreeMap<Principal, (), Memory> {
+    StableBTreeMap::init(crate::memory::get_suspended_users_memory())
+}
+
+fn init_reserved_file_ids() -> StableBTreeMap<(Principal, u64), u64, Memory> {
+    StableBTreeMap::init(crate::memory::get_reserved_file_ids_memory())
+}
+
+fn init_revoked_shares() -> StableBTreeMap<(u64, Principal), Vec<u8>, Memory> {
+    StableBTreeMap::init(crate::memory::get_revoked_shares_memory())
+}
+
+fn init_reencrypted_contents() -> StableBTreeMap<(FileId, ChunkId), Vec<u8>, Memory> {
+    StableBTreeMap::init(crate::memory::get_reencrypted_contents_memory())
+}
+
+fn init_vetkd_public_keys() -> StableBTreeMap<VetkdKeyId, Vec<u8>, Memory> {
+    StableBTreeMap::init(crate::memory::get_vetkd_public_keys_memory())
+}
+
+fn init_retired_aliases() -> StableBTreeMap<String, RetiredAlias, Memory> {
+    StableBTreeMap::init(crate::memory::get_retired_aliases_memory())
+}
+
+fn init_alias_expiries() -> StableBTreeMap<TimedAlias, (), Memory> {
+    StableBTreeMap::init(crate::memory::get_alias_expiries_memory())
+}
+
+fn init_alias_retirements() -> StableBTreeMap<TimedAlias, (), Memory> {
+    StableBTreeMap::init(crate::memory::get_alias_retirements_memory())
+}
+
+fn init_due_requests() -> StableBTreeMap<(u64, u64), (), Memory> {
+    StableBTreeMap::init(crate::memory::get_due_requests_memory())
+}
+
+fn init_notifications() -> StableBTreeMap<(Principal, u64), Notification, Memory> {
+    StableBTreeMap::init(crate::memory::get_notifications_memory())
+}
+
+fn init_invitations() -> StableBTreeMap<(u64, u64), Invitation, Memory> {
+    StableBTreeMap::init(crate::memory::get_invitations_memory())
+}
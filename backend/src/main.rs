@@ -3,12 +3,39 @@ use backend::api::UploadFileAtomicRequest;
 use backend::*;
 // Import necessary types from the api module (which re-exports from canister_management)
 // use backend::api::{GetUserCanistersResponse, RegisterCanisterResponse};
+use backend::vetkd::controller::vetkd_public_key::refresh_public_key;
 use candid::Principal;
 use ic_cdk::api::caller;
-use ic_cdk_macros::{post_upgrade, pre_upgrade, query, update};
-
-#[update]
-fn set_user(username: String, public_key: Vec<u8>) -> SetUserResponse {
+use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
+use std::time::Duration;
+
+/// How often abandoned uploads are reclaimed.
+const UPLOAD_GC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often expired shares are removed. Expiry is also checked whenever a
+/// share is used.
+const SHARE_EXPIRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often requesters are reminded of overdue requests.
+const REMINDER_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often expired aliases are retired. Expiry is also checked whenever an
+/// alias is used, so this only tidies up the indexes.
+const ALIAS_EXPIRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long to wait before retrying to seed the alias generator, doubled after
+/// each failure up to `MAX_RESEED_BACKOFF`.
+const MIN_RESEED_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESEED_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+#[update(guard = "caller_not_suspended")]
+fn set_user(
+    username: String,
+    public_key: Vec<u8>,
+    // Redeems an invitation. Invitations to the username are redeemed on the
+    // first registration and dropped on a rename.
+    invite_code: Option<String>,
+) -> SetUserResponse {
     if with_state(|s| backend::api::username_exists(s, username.clone())) {
         SetUserResponse::UsernameExists
     } else {
@@ -16,39 +43,49 @@ fn set_user(username: String, public_key: Vec<u8>) -> SetUserResponse {
             username,
             public_key,
         };
-        with_state_mut(|s| backend::api::set_user_info(s, caller(), user));
+        with_state_mut(|s| backend::api::register_user(s, caller(), user, invite_code));
         SetUserResponse::Ok
     }
 }
 
-#[query]
+#[query(guard = "caller_not_suspended")]
 fn username_exists(username: String) -> bool {
     with_state(|s| backend::api::username_exists(s, username))
 }
 
-#[query]
+#[query(guard = "caller_not_suspended")]
 fn who_am_i() -> WhoamiResponse {
     with_state(|s| match s.users.get(&ic_cdk::api::caller()) {
         None => WhoamiResponse::UnknownUser,
         Some(user) => WhoamiResponse::KnownUser(PublicUser {
-            username: user.username.clone(),
-            public_key: user.public_key.clone(),
+            username: user.username,
+            public_key: user.public_key,
             ic_principal: ic_cdk::api::caller(),
         }),
     })
 }
 
-#[query]
+#[query(guard = "caller_not_suspended")]
 fn get_requests() -> Vec<PublicFileMetadata> {
     with_state(|s| backend::api::get_requests(s, caller()))
 }
 
-#[query]
+#[query(guard = "caller_not_suspended")]
 fn get_shared_files() -> Vec<PublicFileMetadata> {
     with_state(|s| backend::api::get_shared_files(s, caller()))
 }
 
-#[query]
+#[query(guard = "caller_not_suspended")]
+fn list_requests(query: ListFilesQuery) -> ListFilesResponse {
+    with_state(|s| backend::api::list_requests(s, caller(), query))
+}
+
+#[query(guard = "caller_not_suspended")]
+fn list_shared_files(query: ListFilesQuery) -> ListFilesResponse {
+    with_state(|s| backend::api::list_shared_files(s, caller(), query))
+}
+
+#[query(guard = "caller_not_suspended")]
 fn get_file_owner_principal(file_id: u64) -> Result<Vec<u8>, String> {
     with_state(|s| {
         s.file_data
@@ -58,56 +95,107 @@ fn get_file_owner_principal(file_id: u64) -> Result<Vec<u8>, String> {
     })
 }
 
-#[query]
+#[query(guard = "caller_not_suspended")]
 fn get_alias_info(alias: String) -> Result<AliasInfo, GetAliasInfoError> {
     with_state(|s| backend::api::get_alias_info(s, alias))
 }
 
-#[update]
+#[update(guard = "caller_not_suspended")]
 fn upload_file(request: UploadFileRequest) -> Result<(), UploadFileError> {
     with_state_mut(|s| {
         backend::api::upload_file(
             request.file_id,
+            request.alias,
             request.file_content,
             request.file_type,
             // request.owner_key,
             request.num_chunks,
+            request.total_size,
+            request.chunk_size,
             s,
         )
     })
 }
 
-#[update]
-fn upload_file_atomic(request: UploadFileAtomicRequest) -> u64 {
+#[update(guard = "caller_not_suspended")]
+fn upload_file_atomic(request: UploadFileAtomicRequest) -> Result<u64, UploadFileError> {
     with_state_mut(|s| backend::api::upload_file_atomic(caller(), request, s))
 }
 
-#[update]
-fn upload_file_continue(request: UploadFileContinueRequest) {
-    with_state_mut(|s| backend::api::upload_file_continue(request, s))
+#[query(guard = "caller_not_suspended")]
+fn get_upload_progress(
+    file_id: u64,
+    alias: Option<String>,
+) -> Result<UploadProgress, UploadFileError> {
+    with_state(|s| backend::api::get_upload_progress(s, caller(), file_id, alias))
 }
 
-#[update]
-fn request_file(request_name: String) -> String {
-    with_state_mut(|s| backend::api::request_file(caller(), request_name, s))
+#[update(guard = "caller_not_suspended")]
+fn upload_file_continue(request: UploadFileContinueRequest) -> Result<(), UploadFileError> {
+    with_state_mut(|s| backend::api::upload_file_continue(request, caller(), s))
 }
 
-#[update]
-fn multi_request(input: MultiRequestInput) -> MultiRequestResponse {
+#[update(guard = "caller_not_suspended")]
+fn reserve_file_id() -> Result<FileKeyInfo, UploadFileError> {
+    with_state_mut(|s| backend::api::reserve_file_id(s, caller()))
+}
+
+#[update(guard = "caller_not_suspended")]
+fn abort_upload(file_id: u64, alias: Option<String>) -> Result<(), UploadFileError> {
+    with_state_mut(|s| backend::api::abort_upload(s, caller(), file_id, alias))
+}
+
+#[query(guard = "caller_not_suspended")]
+fn get_storage_usage() -> StorageUsageResponse {
+    with_state(|s| backend::api::get_storage_usage(s, caller()))
+}
+
+#[update(guard = "caller_not_suspended")]
+fn request_file(
+    request_name: String,
+    expires_at: Option<u64>,
+    due_at: Option<u64>,
+) -> Result<String, AliasError> {
+    let options = RequestOptions { expires_at, due_at };
+    with_state_mut(|s| backend::api::request_file_with_options(caller(), request_name, options, s))
+}
+
+#[query(guard = "caller_not_suspended")]
+fn get_notifications() -> Vec<Notification> {
+    with_state(|s| backend::api::get_notifications(s, caller()))
+}
+
+#[update(guard = "caller_not_suspended")]
+fn dismiss_notification(notification_id: u64) -> bool {
+    with_state_mut(|s| backend::api::dismiss_notification(s, caller(), notification_id))
+}
+
+#[update(guard = "caller_not_suspended")]
+fn regenerate_alias(target: AliasTarget, expires_at: Option<u64>) -> Result<String, AliasError> {
+    with_state_mut(|s| backend::api::regenerate_alias(s, caller(), target, expires_at))
+}
+
+#[update(guard = "caller_not_suspended")]
+fn disable_alias(target: AliasTarget) -> Result<(), AliasError> {
+    with_state_mut(|s| backend::api::disable_alias(s, caller(), target))
+}
+
+#[update(guard = "caller_not_suspended")]
+fn multi_request(input: MultiRequestInput) -> Result<MultiRequestResponse, AliasError> {
     with_state_mut(|s| backend::api::multi_request(caller(), input, s))
 }
 
-#[query]
+#[query(guard = "caller_not_suspended")]
 fn get_user_templates() -> Vec<Template> {
     with_state(|s| backend::api::get_user_templates(s, caller()))
 }
 
-#[query]
+#[query(guard = "caller_not_suspended")]
 fn get_template(name: String) -> Result<Template, GetAliasInfoError> {
     with_state(|s| backend::api::get_template(s, caller(), name))
 }
 
-#[update]
+#[update(guard = "caller_not_suspended")]
 fn delete_template(name: String) {
     with_state_mut(|s| {
         backend::api::delete_template(s, caller(), name).unwrap_or_else(|err| {
@@ -116,99 +204,343 @@ fn delete_template(name: String) {
     });
 }
 
-#[query]
+#[query(guard = "caller_not_suspended")]
 fn get_request_groups() -> Vec<PublicRequestGroup> {
     with_state(|s| backend::api::get_request_groups(s, caller()))
 }
 
-#[query]
+#[query(guard = "caller_not_suspended")]
 fn get_group_by_alias(alias: String) -> Result<GroupInfo, GetAliasInfoError> {
     with_state(|s| backend::api::get_group_by_alias(s, alias))
 }
 
-#[query]
+#[query(guard = "caller_not_suspended")]
 fn download_file(file_id: u64, chunk_id: u64) -> FileDownloadResponse {
     with_state(|s| backend::api::download_file(s, file_id, chunk_id, caller()))
 }
 
-#[update]
+#[update(guard = "caller_not_suspended")]
 fn share_file(
     user_id: Principal,
     file_id: u64,
     // file_key not needed as we have vetkeys now
     // file_key_encrypted_for_user: Vec<u8>,
+    // Shares can download the file unless told otherwise.
+    permission: Option<SharePermission>,
+    expires_at: Option<u64>,
 ) -> FileSharingResponse {
-    with_state_mut(|s| backend::api::share_file(s, caller(), user_id, file_id))
+    let permission = permission.unwrap_or(SharePermission::Download);
+    with_state_mut(|s| {
+        backend::api::share_file_with_permission(
+            s,
+            caller(),
+            user_id,
+            file_id,
+            permission,
+            expires_at,
+        )
+    })
 }
 
-#[update]
-fn share_file_with_users(
-    user_id: Vec<Principal>,
+#[update(guard = "caller_not_suspended")]
+fn share_file_by_username(
+    username: String,
     file_id: u64,
-    file_key_encrypted_for_user: Vec<Vec<u8>>,
-) {
+    permission: Option<SharePermission>,
+    expires_at: Option<u64>,
+) -> InviteResponse {
+    let permission = permission.unwrap_or(SharePermission::Download);
     with_state_mut(|s| {
-        for (id, _key) in user_id.iter().zip(file_key_encrypted_for_user.iter()) {
-            backend::api::share_file(s, caller(), *id, file_id);
-        }
-    });
+        backend::api::share_file_by_username(s, caller(), username, file_id, permission, expires_at)
+    })
 }
 
-#[update]
+#[update(guard = "caller_not_suspended")]
+fn create_invitation(
+    file_id: u64,
+    permission: Option<SharePermission>,
+    expires_at: Option<u64>,
+) -> InviteResponse {
+    let permission = permission.unwrap_or(SharePermission::Download);
+    with_state_mut(|s| {
+        backend::api::create_invitation(s, caller(), file_id, permission, expires_at)
+    })
+}
+
+#[query(guard = "caller_not_suspended")]
+fn get_invitations() -> Vec<Invitation> {
+    with_state(|s| backend::api::get_invitations(s, caller()))
+}
+
+#[update(guard = "caller_not_suspended")]
+fn cancel_invitation(file_id: u64, invitation_id: u64) -> FileSharingResponse {
+    with_state_mut(|s| backend::api::cancel_invitation(s, caller(), file_id, invitation_id))
+}
+
+#[update(guard = "caller_not_suspended")]
+fn accept_invitation(code: String) -> FileSharingResponse {
+    with_state_mut(|s| backend::api::accept_invitation(s, caller(), code))
+}
+
+#[update(guard = "caller_not_suspended")]
+fn share_files(request: ShareFilesRequest) -> Vec<ShareResult> {
+    with_state_mut(|s| backend::api::share_files(s, caller(), request))
+}
+
+#[update(guard = "caller_not_suspended")]
+fn revoke_shares(file_ids: Vec<u64>, user_ids: Vec<Principal>) -> Vec<ShareResult> {
+    with_state_mut(|s| backend::api::revoke_shares(s, caller(), file_ids, user_ids))
+}
+
+#[update(guard = "caller_not_suspended")]
 fn revoke_share(user_id: Principal, file_id: u64) -> FileSharingResponse {
     with_state_mut(|s| backend::api::revoke_share(s, caller(), user_id, file_id))
 }
 
-#[update]
+#[query(guard = "caller_not_suspended")]
+fn get_files_to_reencrypt() -> Vec<u64> {
+    with_state(|s| backend::api::get_files_to_reencrypt(s, caller()))
+}
+
+#[update(guard = "caller_not_suspended")]
+fn upload_reencrypted_chunk(request: ReencryptedChunk) -> Result<(), UploadFileError> {
+    with_state_mut(|s| backend::api::upload_reencrypted_chunk(s, caller(), request))
+}
+
+#[update(guard = "caller_not_suspended")]
+fn commit_reencryption(file_id: u64, num_chunks: u64) -> Result<(), UploadFileError> {
+    with_state_mut(|s| backend::api::commit_reencryption(s, caller(), file_id, num_chunks))
+}
+
+#[update(guard = "caller_not_suspended")]
+fn migrate_to_per_file_keys() -> Vec<u64> {
+    with_state_mut(|s| backend::api::migrate_to_per_file_keys(s, caller()))
+}
+
+#[update(guard = "caller_not_suspended")]
 fn delete_file(file_id: u64) -> FileSharingResponse {
     with_state_mut(|s| backend::api::delete_file(s, caller(), file_id))
 }
 
-#[update]
+#[update(guard = "caller_not_suspended")]
 fn rename_file(file_id: u64, new_name: String) -> FileSharingResponse {
     with_state_mut(|s| backend::api::rename_file(s, caller(), file_id, new_name))
 }
 
-#[query]
+#[update(guard = "caller_not_suspended")]
+fn create_folder(name: String, parent_id: Option<u64>) -> Result<u64, FolderError> {
+    with_state_mut(|s| backend::api::create_folder(s, caller(), name, parent_id))
+}
+
+#[update(guard = "caller_not_suspended")]
+fn rename_folder(folder_id: u64, new_name: String) -> Result<(), FolderError> {
+    with_state_mut(|s| backend::api::rename_folder(s, caller(), folder_id, new_name))
+}
+
+#[update(guard = "caller_not_suspended")]
+fn move_folder(folder_id: u64, parent_id: Option<u64>) -> Result<(), FolderError> {
+    with_state_mut(|s| backend::api::move_folder(s, caller(), folder_id, parent_id))
+}
+
+#[update(guard = "caller_not_suspended")]
+fn delete_folder(folder_id: u64) -> Result<(), FolderError> {
+    with_state_mut(|s| backend::api::delete_folder(s, caller(), folder_id))
+}
+
+#[update(guard = "caller_not_suspended")]
+fn move_file(file_id: u64, folder_id: Option<u64>) -> Result<(), FolderError> {
+    with_state_mut(|s| backend::api::move_file(s, caller(), file_id, folder_id))
+}
+
+#[query(guard = "caller_not_suspended")]
+fn list_folder(
+    folder_id: Option<u64>,
+    cursor: Option<FolderCursor>,
+    limit: Option<u64>,
+) -> Result<FolderContents, FolderError> {
+    with_state(|s| backend::api::list_folder(s, caller(), folder_id, cursor, limit))
+}
+
+#[query(guard = "caller_not_suspended")]
 fn get_users() -> GetUsersResponse {
     with_state(|s| backend::api::get_users(s, caller()))
 }
 
 // --- New Canister Management Endpoints ---
 
-#[query]
+#[query(guard = "caller_not_suspended")]
 fn get_user_canisters() -> GetUserCanistersResponse {
     // Directly call the implementation from the api module
     backend::api::get_user_canisters()
 }
 
-#[update]
+#[update(guard = "caller_not_suspended")]
 async fn register_canister(canister_id: Principal, name: String) -> RegisterCanisterResponse {
     // Directly call the implementation from the api module
     // Note: This function in api is async, so we need await here.
     backend::api::register_canister(canister_id, name).await
 }
 
-#[update]
+#[update(guard = "caller_not_suspended")]
 async fn rename_canister(canister_id: Principal, new_name: String) -> RenameCanisterResponse {
     backend::api::rename_canister(canister_id, new_name).await
 }
 
-#[update]
+#[update(guard = "caller_not_suspended")]
 async fn unregister_canister(canister_id: Principal) -> DeleteCanisterResponse {
     backend::api::unregister_canister_internal(canister_id)
 }
 
 // --- End New Endpoints ---
 
+// --- Admin Endpoints ---
+
+#[update]
+fn add_admin(user: Principal) -> Result<(), AdminError> {
+    with_state_mut(|s| backend::api::add_admin(s, caller(), user))
+}
+
+#[update]
+fn remove_admin(user: Principal) -> Result<(), AdminError> {
+    with_state_mut(|s| backend::api::remove_admin(s, caller(), user))
+}
+
+#[query]
+fn list_admins() -> Result<Vec<Principal>, AdminError> {
+    with_state(|s| backend::api::list_admins(s, caller()))
+}
+
+#[query]
+fn list_all_users() -> Result<Vec<AdminUserInfo>, AdminError> {
+    with_state(|s| backend::api::list_all_users(s, caller()))
+}
+
+#[query]
+fn get_storage_stats() -> Result<StorageStats, AdminError> {
+    with_state(|s| backend::api::get_storage_stats(s, caller()))
+}
+
+#[update]
+fn suspend_user(user: Principal) -> Result<(), AdminError> {
+    with_state_mut(|s| backend::api::suspend_user(s, caller(), user))
+}
+
+#[update]
+fn unsuspend_user(user: Principal) -> Result<(), AdminError> {
+    with_state_mut(|s| backend::api::unsuspend_user(s, caller(), user))
+}
+
+#[update]
+fn force_delete_file(file_id: u64) -> Result<(), AdminError> {
+    with_state_mut(|s| backend::api::force_delete_file(s, caller(), file_id))
+}
+
+#[update]
+fn set_user_quota(user: Principal, quota: Option<StorageQuota>) -> Result<(), AdminError> {
+    with_state_mut(|s| backend::api::set_user_quota(s, caller(), user, quota))
+}
+
+#[update]
+fn set_default_quota(quota: StorageQuota) -> Result<(), AdminError> {
+    with_state_mut(|s| backend::api::set_default_quota(s, caller(), quota))
+}
+
+#[query]
+fn get_config() -> CanisterConfig {
+    with_state(|s| s.config.clone())
+}
+
+#[update]
+fn set_config(config: CanisterConfig, force_key_change: bool) -> Result<(), AdminError> {
+    with_state_mut(|s| backend::api::set_config(s, caller(), config, force_key_change))?;
+    refresh_public_key();
+    Ok(())
+}
+
+// --- End Admin Endpoints ---
+
+#[init]
+fn init(args: Option<CanisterArgs>) {
+    apply_canister_args(args);
+    refresh_public_key();
+    reseed_aliases();
+    start_upload_gc();
+    start_alias_expiry();
+    start_reminders();
+    start_share_expiry();
+}
+
 #[pre_upgrade]
 fn pre_upgrade() {
     backend::pre_upgrade();
 }
 
 #[post_upgrade]
-fn post_upgrade() {
+fn post_upgrade(args: Option<CanisterArgs>) {
     backend::post_upgrade();
+    apply_canister_args(args);
+    refresh_public_key();
+    reseed_aliases();
+    start_upload_gc();
+    start_alias_expiry();
+    start_reminders();
+    start_share_expiry();
+}
+
+// Trapping rolls back the install or upgrade, so a key isn't switched by accident.
+fn apply_canister_args(args: Option<CanisterArgs>) {
+    if let Some(args) = args {
+        if let Err(err) = with_state_mut(|s| backend::api::apply_canister_args(s, args)) {
+            ic_cdk::trap(&format!("Invalid canister arguments: {:?}", err));
+        }
+    }
+}
+
+// Calls can't be made from init and post_upgrade, so the alias generator is
+// reseeded from a timer right after them. No aliases are generated until it is.
+fn reseed_aliases() {
+    schedule_reseed(Duration::ZERO);
+}
+
+fn schedule_reseed(delay: Duration) {
+    ic_cdk_timers::set_timer(delay, move || {
+        ic_cdk::spawn(async move {
+            if reseed_alias_generator().await.is_err() {
+                schedule_reseed((delay * 2).clamp(MIN_RESEED_BACKOFF, MAX_RESEED_BACKOFF));
+            }
+        })
+    });
+}
+
+// Timers don't survive upgrades, so this is started again after each one.
+fn start_upload_gc() {
+    ic_cdk_timers::set_timer_interval(UPLOAD_GC_INTERVAL, || {
+        with_state_mut(|s| {
+            backend::api::reclaim_expired_uploads(s, get_time());
+            backend::api::expire_reserved_file_ids(s, get_time());
+        });
+    });
+}
+
+fn start_alias_expiry() {
+    ic_cdk_timers::set_timer_interval(ALIAS_EXPIRY_INTERVAL, || {
+        with_state_mut(|s| {
+            backend::api::expire_aliases(s, get_time());
+            backend::api::prune_retired_aliases(s, get_time());
+        });
+    });
+}
+
+fn start_reminders() {
+    ic_cdk_timers::set_timer_interval(REMINDER_INTERVAL, || {
+        with_state_mut(|s| backend::api::send_reminders(s, get_time()));
+    });
+}
+
+fn start_share_expiry() {
+    ic_cdk_timers::set_timer_interval(SHARE_EXPIRY_INTERVAL, || {
+        with_state_mut(|s| backend::api::expire_shares(s, get_time()));
+    });
 }
 
 fn main() {}
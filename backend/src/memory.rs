@@ -6,6 +6,44 @@ const UPGRADES: MemoryId = MemoryId::new(0);
 const FILE_CONTENTS: MemoryId = MemoryId::new(1);
 // Assuming MemoryId(2) might be used for recipient_file_contents or similar
 const USER_CANISTERS: MemoryId = MemoryId::new(3); // Add new MemoryId
+const USERS: MemoryId = MemoryId::new(4);
+const FILE_DATA: MemoryId = MemoryId::new(5);
+const FILE_ALIAS_INDEX: MemoryId = MemoryId::new(6);
+const FILE_OWNERS: MemoryId = MemoryId::new(7);
+// Shares before they were stored along with their permission and expiry.
+const LEGACY_FILE_SHARES: MemoryId = MemoryId::new(8);
+const REQUEST_GROUPS: MemoryId = MemoryId::new(9);
+const GROUP_ALIAS_INDEX: MemoryId = MemoryId::new(10);
+const GROUP_FILES: MemoryId = MemoryId::new(11);
+const USER_TEMPLATES: MemoryId = MemoryId::new(12);
+const FOLDERS: MemoryId = MemoryId::new(13);
+const FILE_GROUP_INDEX: MemoryId = MemoryId::new(14);
+const GROUP_ALIAS_BY_ID_INDEX: MemoryId = MemoryId::new(15);
+const FILE_SHAREES_INDEX: MemoryId = MemoryId::new(16);
+const UPLOAD_SESSIONS: MemoryId = MemoryId::new(17);
+const STORAGE_USAGE: MemoryId = MemoryId::new(18);
+const STORAGE_QUOTAS: MemoryId = MemoryId::new(19);
+const ADMINS: MemoryId = MemoryId::new(20);
+const SUSPENDED_USERS: MemoryId = MemoryId::new(21);
+// Reserved file ids before reservations recorded when they were made.
+const LEGACY_RESERVED_FILE_IDS: MemoryId = MemoryId::new(22);
+const REVOKED_SHARES: MemoryId = MemoryId::new(23);
+const REENCRYPTED_CONTENTS: MemoryId = MemoryId::new(24);
+const VETKD_PUBLIC_KEYS: MemoryId = MemoryId::new(25);
+const RETIRED_ALIASES: MemoryId = MemoryId::new(26);
+const DUE_REQUESTS: MemoryId = MemoryId::new(27);
+const NOTIFICATIONS: MemoryId = MemoryId::new(28);
+const INVITATIONS: MemoryId = MemoryId::new(29);
+const FOLDER_CHILDREN_INDEX: MemoryId = MemoryId::new(30);
+const FOLDER_FILES_INDEX: MemoryId = MemoryId::new(31);
+const RESERVED_FILE_IDS: MemoryId = MemoryId::new(32);
+const FILE_SHARES: MemoryId = MemoryId::new(33);
+const INVITEE_INVITATIONS_INDEX: MemoryId = MemoryId::new(34);
+const UPLOAD_EXPIRIES_INDEX: MemoryId = MemoryId::new(35);
+const ALIAS_EXPIRIES: MemoryId = MemoryId::new(36);
+const ALIAS_RETIREMENTS: MemoryId = MemoryId::new(37);
+const SHARE_EXPIRIES_INDEX: MemoryId = MemoryId::new(38);
+const USERNAMES_INDEX: MemoryId = MemoryId::new(39);
 
 pub type Memory = VirtualMemory<DefaultMemoryImpl>;
 
@@ -16,15 +54,163 @@ thread_local! {
         RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
 }
 
+fn get_memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
 pub fn get_upgrades_memory() -> Memory {
-    MEMORY_MANAGER.with(|m| m.borrow().get(UPGRADES))
+    get_memory(UPGRADES)
 }
 
 pub fn get_file_contents_memory() -> Memory {
-    MEMORY_MANAGER.with(|m| m.borrow().get(FILE_CONTENTS))
+    get_memory(FILE_CONTENTS)
 }
 
 // Add function to get memory for the user canisters map
 pub fn get_user_canisters_memory() -> Memory {
-    MEMORY_MANAGER.with(|m| m.borrow().get(USER_CANISTERS))
+    get_memory(USER_CANISTERS)
+}
+
+pub fn get_users_memory() -> Memory {
+    get_memory(USERS)
+}
+
+pub fn get_file_data_memory() -> Memory {
+    get_memory(FILE_DATA)
+}
+
+pub fn get_file_alias_index_memory() -> Memory {
+    get_memory(FILE_ALIAS_INDEX)
+}
+
+pub fn get_file_owners_memory() -> Memory {
+    get_memory(FILE_OWNERS)
+}
+
+pub fn get_legacy_file_shares_memory() -> Memory {
+    get_memory(LEGACY_FILE_SHARES)
+}
+
+pub fn get_file_shares_memory() -> Memory {
+    get_memory(FILE_SHARES)
+}
+
+pub fn get_request_groups_memory() -> Memory {
+    get_memory(REQUEST_GROUPS)
+}
+
+pub fn get_group_alias_index_memory() -> Memory {
+    get_memory(GROUP_ALIAS_INDEX)
+}
+
+pub fn get_group_files_memory() -> Memory {
+    get_memory(GROUP_FILES)
+}
+
+pub fn get_user_templates_memory() -> Memory {
+    get_memory(USER_TEMPLATES)
+}
+
+pub fn get_folders_memory() -> Memory {
+    get_memory(FOLDERS)
+}
+
+pub fn get_file_group_index_memory() -> Memory {
+    get_memory(FILE_GROUP_INDEX)
+}
+
+pub fn get_group_alias_by_id_index_memory() -> Memory {
+    get_memory(GROUP_ALIAS_BY_ID_INDEX)
+}
+
+pub fn get_file_sharees_index_memory() -> Memory {
+    get_memory(FILE_SHAREES_INDEX)
+}
+
+pub fn get_upload_sessions_memory() -> Memory {
+    get_memory(UPLOAD_SESSIONS)
+}
+
+pub fn get_storage_usage_memory() -> Memory {
+    get_memory(STORAGE_USAGE)
+}
+
+pub fn get_storage_quotas_memory() -> Memory {
+    get_memory(STORAGE_QUOTAS)
+}
+
+pub fn get_admins_memory() -> Memory {
+    get_memory(ADMINS)
+}
+
+pub fn get_suspended_users_memory() -> Memory {
+    get_memory(SUSPENDED_USERS)
+}
+
+pub fn get_legacy_reserved_file_ids_memory() -> Memory {
+    get_memory(LEGACY_RESERVED_FILE_IDS)
+}
+
+pub fn get_reserved_file_ids_memory() -> Memory {
+    get_memory(RESERVED_FILE_IDS)
+}
+
+pub fn get_revoked_shares_memory() -> Memory {
+    get_memory(REVOKED_SHARES)
+}
+
+pub fn get_reencrypted_contents_memory() -> Memory {
+    get_memory(REENCRYPTED_CONTENTS)
+}
+
+pub fn get_vetkd_public_keys_memory() -> Memory {
+    get_memory(VETKD_PUBLIC_KEYS)
+}
+
+pub fn get_retired_aliases_memory() -> Memory {
+    get_memory(RETIRED_ALIASES)
+}
+
+pub fn get_due_requests_memory() -> Memory {
+    get_memory(DUE_REQUESTS)
+}
+
+pub fn get_notifications_memory() -> Memory {
+    get_memory(NOTIFICATIONS)
+}
+
+pub fn get_invitations_memory() -> Memory {
+    get_memory(INVITATIONS)
+}
+
+pub fn get_folder_children_index_memory() -> Memory {
+    get_memory(FOLDER_CHILDREN_INDEX)
+}
+
+pub fn get_folder_files_index_memory() -> Memory {
+    get_memory(FOLDER_FILES_INDEX)
+}
+
+pub fn get_invitee_invitations_index_memory() -> Memory {
+    get_memory(INVITEE_INVITATIONS_INDEX)
+}
+
+pub fn get_upload_expiries_index_memory() -> Memory {
+    get_memory(UPLOAD_EXPIRIES_INDEX)
+}
+
+pub fn get_alias_expiries_memory() -> Memory {
+    get_memory(ALIAS_EXPIRIES)
+}
+
+pub fn get_alias_retirements_memory() -> Memory {
+    get_memory(ALIAS_RETIREMENTS)
+}
+
+pub fn get_share_expiries_index_memory() -> Memory {
+    get_memory(SHARE_EXPIRIES_INDEX)
+}
+
+pub fn get_usernames_index_memory() -> Memory {
+    get_memory(USERNAMES_INDEX)
 }
@@ -1,11 +1,73 @@
-use crate::{memory, STATE};
-use ic_stable_structures::{writer::Writer, Memory as _};
+use crate::api::{MAX_CHUNK_SIZE, UPLOAD_SESSION_TTL};
+use crate::indexes::Indexes;
+use crate::{
+    init_alias_expiries, init_alias_retirements, init_file_alias_index, init_file_contents,
+    init_file_data, init_file_owners, init_file_shares, init_folders, init_group_alias_index,
+    init_group_files, init_invitations, init_request_groups, init_reserved_file_ids,
+    init_retired_aliases, init_storage_usage, init_upload_sessions, init_user_templates,
+    init_users, memory, File, FileContent, Folder, RequestGroup, Share, SharePermission, State,
+    Template, Templates, TimedAlias, UploadSession, User, STATE,
+};
+use candid::Principal;
+use ciborium::value::Value;
+use ic_stable_structures::{
+    storable::Bound, writer::Writer, Memory as _, StableBTreeMap, Storable,
+};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+/// The version of the state schema written by `pre_upgrade`.
+///
+/// Bump it whenever the serialized state changes in a way that older states
+/// can't be decoded into, and add the matching migration to `MIGRATIONS`.
+const STATE_VERSION: u32 = 12;
+
+/// Transforms a serialized state from one schema version to the next.
+type Migration = fn(Value) -> Value;
+
+/// `MIGRATIONS[i]` upgrades a state from version `i + 1` to version `i + 2`.
+const MIGRATIONS: [Migration; STATE_VERSION as usize - 1] = [
+    // 1 -> 2: the maps move from the serialized state into stable structures.
+    move_maps_to_stable_memory,
+    // 2 -> 3: storage usage is tracked per user.
+    account_storage_usage,
+    // 3 -> 4: folders and their files are indexed by owner and parent.
+    index_folders,
+    // 4 -> 5: reserved file ids are kept by owner, along with when they were reserved.
+    date_reserved_file_ids,
+    // 5 -> 6: shares are kept along with their permission and expiry.
+    move_file_shares,
+    // 6 -> 7: invitations are indexed by invitee.
+    index_invitations,
+    // 7 -> 8: upload sessions are indexed by when they expire.
+    index_upload_sessions,
+    // 8 -> 9: uploads started before sessions existed get a session.
+    start_legacy_upload_sessions,
+    // 9 -> 10: aliases are kept by when they expire or were retired.
+    index_aliases,
+    // 10 -> 11: shares are indexed by when they expire.
+    index_share_expiries,
+    // 11 -> 12: users are indexed by their usernames.
+    index_usernames,
+];
+
+/// The upgrade blob: the serialized state along with its schema version.
+#[derive(Serialize)]
+struct VersionedState<'a> {
+    version: u32,
+    state: &'a State,
+}
+
+#[derive(Deserialize)]
+struct VersionedValue {
+    version: u32,
+    state: Value,
+}
 
 pub fn pre_upgrade() {
     // Serialize the state.
-    let mut state_bytes = vec![];
-    crate::with_state(|state| ciborium::ser::into_writer(state, &mut state_bytes))
-        .expect("failed to encode state");
+    let state_bytes = crate::with_state(encode_state);
 
     // Write the length of the serialized bytes to memory, followed by the
     // by the bytes themselves.
@@ -29,6 +91,753 @@ pub fn post_upgrade() {
     memory.read(4, &mut state_bytes);
 
     // Deserialize and set the state.
-    let state = ciborium::de::from_reader(&*state_bytes).expect("failed to decode state");
+    let state = decode_state(&state_bytes);
     STATE.with(|s| *s.borrow_mut() = state);
 }
+
+fn encode_state(state: &State) -> Vec<u8> {
+    let mut state_bytes = vec![];
+    ciborium::ser::into_writer(
+        &VersionedState {
+            version: STATE_VERSION,
+            state,
+        },
+        &mut state_bytes,
+    )
+    .expect("failed to encode state");
+    state_bytes
+}
+
+/// Decodes the state written by `pre_upgrade`, migrating it from older schema
+/// versions first if needed.
+fn decode_state(bytes: &[u8]) -> State {
+    let blob: Value = ciborium::de::from_reader(bytes).expect("failed to decode state");
+
+    // States written before the schema was versioned are stored as is.
+    let (version, mut state) = match blob.deserialized::<VersionedValue>() {
+        Ok(versioned) => (versioned.version, versioned.state),
+        Err(_) => (1, blob),
+    };
+    if version == 0 || version > STATE_VERSION {
+        panic!("unsupported state version {version}, expected at most {STATE_VERSION}");
+    }
+
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        state = migration(state);
+    }
+
+    state.deserialized().expect("failed to decode state")
+}
+
+/// The maps that were serialized along with the rest of the state in version 1.
+#[derive(Deserialize)]
+struct LegacyMaps {
+    #[serde(default)]
+    users: BTreeMap<Principal, User>,
+    #[serde(default)]
+    file_data: BTreeMap<u64, File>,
+    #[serde(default)]
+    file_alias_index: BTreeMap<String, u64>,
+    #[serde(default)]
+    file_owners: BTreeMap<Principal, Vec<u64>>,
+    #[serde(default)]
+    file_shares: BTreeMap<Principal, Vec<u64>>,
+    #[serde(default)]
+    request_groups: BTreeMap<u64, RequestGroup>,
+    #[serde(default)]
+    group_alias_index: BTreeMap<String, u64>,
+    #[serde(default)]
+    group_files: BTreeMap<u64, Vec<u64>>,
+    #[serde(default)]
+    user_templates: BTreeMap<Principal, BTreeMap<String, Template>>,
+    #[serde(default)]
+    folders: BTreeMap<u64, Folder>,
+}
+
+const LEGACY_MAP_FIELDS: [&str; 10] = [
+    "users",
+    "file_data",
+    "file_alias_index",
+    "file_owners",
+    "file_shares",
+    "request_groups",
+    "group_alias_index",
+    "group_files",
+    "user_templates",
+    "folders",
+];
+
+fn move_maps_to_stable_memory(state: Value) -> Value {
+    let legacy: LegacyMaps = state
+        .deserialized()
+        .expect("failed to decode version 1 state");
+
+    let mut users = init_users();
+    for (principal, user) in legacy.users {
+        users.insert(principal, user);
+    }
+    let mut file_data = init_file_data();
+    for (file_id, file) in legacy.file_data {
+        file_data.insert(file_id, file);
+    }
+    let mut file_alias_index = init_file_alias_index();
+    for (alias, file_id) in legacy.file_alias_index {
+        file_alias_index.insert(alias, file_id);
+    }
+    let mut file_owners = init_file_owners();
+    for (owner, file_ids) in legacy.file_owners {
+        for file_id in file_ids {
+            file_owners.insert((owner, file_id), ());
+        }
+    }
+    let mut file_shares = init_file_shares();
+    for (sharee, file_ids) in legacy.file_shares {
+        for file_id in file_ids {
+            file_shares.insert(
+                (sharee, file_id),
+                Share {
+                    permission: SharePermission::Download,
+                    expires_at: None,
+                },
+            );
+        }
+    }
+    let mut request_groups = init_request_groups();
+    for (group_id, group) in legacy.request_groups {
+        request_groups.insert(group_id, group);
+    }
+    let mut group_alias_index = init_group_alias_index();
+    for (alias, group_id) in legacy.group_alias_index {
+        group_alias_index.insert(alias, group_id);
+    }
+    let mut group_files = init_group_files();
+    for (group_id, file_ids) in legacy.group_files {
+        for file_id in file_ids {
+            group_files.insert((group_id, file_id), ());
+        }
+    }
+    let mut user_templates = init_user_templates();
+    for (principal, templates) in legacy.user_templates {
+        user_templates.insert(principal, Templates(templates));
+    }
+    let mut folders = init_folders();
+    for (folder_id, folder) in legacy.folders {
+        folders.insert(folder_id, folder);
+    }
+
+    // The indexes weren't persisted in version 1 either.
+    Indexes::init().rebuild(&request_groups, &group_alias_index, &file_shares);
+
+    match state {
+        Value::Map(fields) => Value::Map(
+            fields
+                .into_iter()
+                .filter(|(key, _)| {
+                    !key.as_text()
+                        .is_some_and(|key| LEGACY_MAP_FIELDS.contains(&key))
+                })
+                .collect(),
+        ),
+        state => state,
+    }
+}
+
+/// Counts the files that were stored before quotas existed against their owners.
+fn account_storage_usage(state: Value) -> Value {
+    let file_contents = init_file_contents();
+    let mut storage_usage = init_storage_usage();
+    for (file_id, file) in init_file_data().iter() {
+        if let FileContent::Pending { .. } = file.content {
+            continue;
+        }
+        let owner = file.metadata.requester_principal;
+        let mut usage = storage_usage.get(&owner).unwrap_or_default();
+        usage.bytes += file_contents
+            .range((file_id, 0)..(file_id + 1, 0))
+            .map(|(_, chunk)| chunk.len() as u64)
+            .sum::<u64>();
+        usage.file_count += 1;
+        storage_usage.insert(owner, usage);
+    }
+    state
+}
+
+/// Indexes the folders and files that were created before the folder indexes existed.
+fn index_folders(state: Value) -> Value {
+    Indexes::init().rebuild_folders(&init_folders(), &init_file_owners(), &init_file_data());
+    state
+}
+
+/// Moves the reserved file ids to their new map. They are dated to the upgrade,
+/// so that they expire a day later if they aren't used.
+fn date_reserved_file_ids(state: Value) -> Value {
+    let mut legacy: StableBTreeMap<u64, Principal, _> =
+        StableBTreeMap::init(memory::get_legacy_reserved_file_ids_memory());
+    let mut reserved_file_ids = init_reserved_file_ids();
+    let now = crate::get_time();
+    for (file_id, owner) in legacy.iter() {
+        reserved_file_ids.insert((owner, file_id), now);
+    }
+    let file_ids: Vec<_> = legacy.iter().map(|(file_id, _)| file_id).collect();
+    for file_id in file_ids {
+        legacy.remove(&file_id);
+    }
+    state
+}
+
+/// Indexes the invitations that were made before the invitee index existed.
+fn index_invitations(state: Value) -> Value {
+    Indexes::init().rebuild_invitations(&init_invitations());
+    state
+}
+
+/// Indexes the upload sessions that were started before the expiry index existed.
+fn index_upload_sessions(state: Value) -> Value {
+    Indexes::init().rebuild_upload_sessions(&init_upload_sessions());
+    state
+}
+
+/// Starts sessions for the uploads that were started before sessions existed,
+/// so that they can be aborted and are reclaimed if they aren't continued.
+/// Those uploads could only be continued by their owner and didn't declare
+/// their layout, so their chunk size is that of their first chunk.
+fn start_legacy_upload_sessions(state: Value) -> Value {
+    let file_contents = init_file_contents();
+    let mut upload_sessions = init_upload_sessions();
+    let mut indexes = Indexes::init();
+    let now = crate::get_time();
+    for (file_id, file) in init_file_data().iter() {
+        let FileContent::PartiallyUploaded { num_chunks, .. } = file.content else {
+            continue;
+        };
+        if upload_sessions.contains_key(&file_id) {
+            continue;
+        }
+        let chunk_size = file_contents
+            .get(&(file_id, 0))
+            .map_or(MAX_CHUNK_SIZE, |chunk| (chunk.len() as u64).max(1));
+        let session = UploadSession {
+            total_size: None,
+            chunk_size,
+            num_chunks,
+            alias: None,
+            created_at: now,
+            expires_at: now + UPLOAD_SESSION_TTL,
+        };
+        indexes.add_upload_session(file_id, &session);
+        upload_sessions.insert(file_id, session);
+    }
+    state
+}
+
+/// Schedules the expiry of the aliases made before aliases were kept by expiry.
+/// The aliases retired until then are dated to the upgrade, so that they are
+/// forgotten once the retention period passes.
+fn index_aliases(state: Value) -> Value {
+    let file_data = init_file_data();
+    let request_groups = init_request_groups();
+    let mut alias_expiries = init_alias_expiries();
+    let mut schedule = |alias: String, expires_at: Option<u64>| {
+        if let Some(at) = expires_at {
+            alias_expiries.insert(TimedAlias { at, alias }, ());
+        }
+    };
+    for (alias, file_id) in init_file_alias_index().iter() {
+        if let Some(file) = file_data.get(&file_id) {
+            schedule(alias, file.metadata.alias_expires_at);
+        }
+    }
+    for (alias, group_id) in init_group_alias_index().iter() {
+        if let Some(group) = request_groups.get(&group_id) {
+            schedule(alias, group.expires_at);
+        }
+    }
+
+    let mut retired_aliases = init_retired_aliases();
+    let mut alias_retirements = init_alias_retirements();
+    let now = crate::get_time();
+    let retired: Vec<_> = retired_aliases.iter().collect();
+    for (alias, mut retired_alias) in retired {
+        retired_alias.retired_at = now;
+        alias_retirements.insert(
+            TimedAlias {
+                at: now,
+                alias: alias.clone(),
+            },
+            (),
+        );
+        retired_aliases.insert(alias, retired_alias);
+    }
+    state
+}
+
+/// Indexes the shares that were made before the share expiry index existed.
+fn index_share_expiries(state: Value) -> Value {
+    Indexes::init().rebuild(
+        &init_request_groups(),
+        &init_group_alias_index(),
+        &init_file_shares(),
+    );
+    state
+}
+
+/// Indexes the users that registered before the username index existed.
+fn index_usernames(state: Value) -> Value {
+    Indexes::init().rebuild_users(&init_users());
+    state
+}
+
+/// A share as stored in the legacy map. Its bound is that of `()`, which the
+/// map was created with, as a map can't be loaded with a larger bound.
+struct LegacyShare(Share);
+
+impl Storable for LegacyShare {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        unreachable!("legacy shares are only read")
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        LegacyShare(Share::from_bytes(bytes))
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 0,
+        is_fixed_size: false,
+    };
+}
+
+/// Moves the shares to their new map, whose values are large enough for a
+/// permission and an expiry. Shares stored without a value can download the file.
+fn move_file_shares(state: Value) -> Value {
+    let mut legacy: StableBTreeMap<(Principal, u64), LegacyShare, _> =
+        StableBTreeMap::init(memory::get_legacy_file_shares_memory());
+    let mut file_shares = init_file_shares();
+    for (key, LegacyShare(share)) in legacy.iter() {
+        file_shares.insert(key, share);
+    }
+    let keys: Vec<_> = legacy.iter().map(|(key, _)| key).collect();
+    for key in keys {
+        legacy.remove(&key);
+    }
+    state
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{
+        abort_upload, disable_alias, request_file, request_file_with_options, reserve_file_id,
+        set_user_info, upload_file_atomic, UploadFileAtomicRequest, MIN_CHUNK_SIZE,
+    };
+    use crate::{AliasTarget, Invitee, RequestOptions, StorageQuota, StorageUsage};
+
+    #[test]
+    fn migrations_cover_every_version() {
+        assert_eq!(MIGRATIONS.len() as u32, STATE_VERSION - 1);
+    }
+
+    #[test]
+    fn loads_version_1_state() {
+        // File contents were already kept in stable memory in version 1.
+        init_file_contents().insert((0, 0), vec![1, 2, 3]);
+
+        let state = decode_state(include_bytes!("../fixtures/state_v1.cbor"));
+        let owner = Principal::anonymous();
+        let sharee = Principal::from_slice(&[0, 1, 2]);
+
+        assert_eq!(state.file_count, 3);
+        assert_eq!(state.group_count, 1);
+        assert_eq!(state.folder_count, 0);
+        assert_eq!(state.users.get(&owner).unwrap().username, "John");
+        assert_eq!(state.users.get(&sharee).unwrap().username, "Jane");
+
+        // The keys that used to be stored with uploaded files are dropped.
+        assert_eq!(
+            state.file_data.get(&0).unwrap().content,
+            FileContent::Uploaded {
+                num_chunks: 1,
+                file_type: "jpeg".to_string(),
+            }
+        );
+        assert_eq!(state.file_data.get(&0).unwrap().metadata.folder_id, None);
+        assert_eq!(
+            state.file_alias_index.get(&"pending-alias".to_string()),
+            Some(1)
+        );
+        assert_eq!(state.owned_files(owner), vec![0, 1, 2]);
+        assert_eq!(state.shared_files(sharee), vec![0]);
+        assert_eq!(state.request_groups.get(&0).unwrap().files, vec![2]);
+        assert_eq!(
+            state.group_alias_index.get(&"group-alias".to_string()),
+            Some(0)
+        );
+        assert_eq!(state.group_file_ids(0), vec![2]);
+        assert_eq!(
+            state.user_templates.get(&owner).unwrap().0["template"].file_names,
+            vec!["a".to_string()]
+        );
+        assert!(state.folders.is_empty());
+
+        // Only the uploaded file counts against the owner's quota.
+        assert_eq!(
+            state.storage_usage.get(&owner),
+            Some(StorageUsage {
+                bytes: 3,
+                file_count: 1,
+            })
+        );
+
+        // The indexes are built from the migrated maps.
+        assert_eq!(state.indexes.file_group.get(&2), Some(0));
+        assert_eq!(state.indexes.sharees(0), vec![sharee]);
+        state.check_indexes();
+    }
+
+    #[test]
+    fn loads_version_2_state() {
+        let state = decode_state(include_bytes!("../fixtures/state_v2.cbor"));
+
+        assert_eq!(state.file_count, 3);
+        assert_eq!(state.group_count, 1);
+        assert_eq!(state.folder_count, 1);
+        assert_eq!(state.default_quota, StorageQuota::default());
+    }
+
+    #[test]
+    fn loads_version_3_state() {
+        // Folders were already kept in stable memory in version 3.
+        let owner = Principal::anonymous();
+        init_folders().insert(
+            0,
+            Folder {
+                folder_id: 0,
+                name: "docs".to_string(),
+                parent_id: None,
+                owner,
+                created_at: 0,
+            },
+        );
+
+        let state = decode_state(include_bytes!("../fixtures/state_v3.cbor"));
+
+        assert_eq!(state.file_count, 3);
+        assert_eq!(state.group_count, 1);
+        assert_eq!(state.folder_count, 1);
+        assert_eq!(
+            state.default_quota,
+            StorageQuota {
+                max_bytes: 1000,
+                max_files: 10,
+            }
+        );
+
+        // The folder indexes are built from the existing folders.
+        assert_eq!(
+            state
+                .indexes
+                .subfolders(owner, None, None)
+                .collect::<Vec<_>>(),
+            vec![0]
+        );
+        state.check_indexes();
+    }
+
+    #[test]
+    fn loads_version_4_state() {
+        let owner = Principal::from_slice(&[0, 1, 2]);
+        let mut legacy: StableBTreeMap<u64, Principal, _> =
+            StableBTreeMap::init(memory::get_legacy_reserved_file_ids_memory());
+        legacy.insert(7, owner);
+
+        let state = decode_state(include_bytes!("../fixtures/state_v4.cbor"));
+
+        assert_eq!(state.file_count, 3);
+        assert_eq!(state.folder_count, 1);
+
+        assert_eq!(
+            state.reserved_file_ids.iter().collect::<Vec<_>>(),
+            vec![((owner, 7), crate::get_time())]
+        );
+        assert!(legacy.is_empty());
+    }
+
+    #[test]
+    fn loads_version_5_state() {
+        let sharee = Principal::from_slice(&[0, 1, 3]);
+        // Shares were stored without a value before they had permissions.
+        let mut legacy: StableBTreeMap<(Principal, u64), (), _> =
+            StableBTreeMap::init(memory::get_legacy_file_shares_memory());
+        legacy.insert((sharee, 0), ());
+
+        let state = decode_state(include_bytes!("../fixtures/state_v5.cbor"));
+
+        assert_eq!(state.file_count, 3);
+
+        assert_eq!(
+            state.file_shares.get(&(sharee, 0)),
+            Some(Share {
+                permission: SharePermission::Download,
+                expires_at: None,
+            })
+        );
+        assert!(legacy.is_empty());
+    }
+
+    /// A share as stored while shares had a permission but no expiry.
+    struct PermissionOnly(SharePermission);
+
+    impl Storable for PermissionOnly {
+        fn to_bytes(&self) -> Cow<[u8]> {
+            Cow::Owned(vec![self.0 as u8])
+        }
+
+        fn from_bytes(_: Cow<[u8]>) -> Self {
+            unreachable!("only written by the test")
+        }
+
+        const BOUND: Bound = Bound::Bounded {
+            max_size: 1,
+            is_fixed_size: false,
+        };
+    }
+
+    #[test]
+    fn shares_keep_their_permission_when_moved() {
+        let sharee = Principal::from_slice(&[0, 1, 3]);
+        let mut legacy: StableBTreeMap<(Principal, u64), PermissionOnly, _> =
+            StableBTreeMap::init(memory::get_legacy_file_shares_memory());
+        legacy.insert((sharee, 0), PermissionOnly(SharePermission::Manage));
+        legacy.insert((sharee, 1), PermissionOnly(SharePermission::View));
+
+        let state = decode_state(include_bytes!("../fixtures/state_v5.cbor"));
+
+        let permissions: Vec<_> = state
+            .file_shares
+            .iter()
+            .map(|(key, share)| (key, share.permission))
+            .collect();
+        assert_eq!(
+            permissions,
+            vec![
+                ((sharee, 0), SharePermission::Manage),
+                ((sharee, 1), SharePermission::View),
+            ]
+        );
+        assert!(legacy.is_empty());
+    }
+
+    #[test]
+    fn loads_version_6_state() {
+        let owner = Principal::from_slice(&[0, 1, 2]);
+        let mut state = State::default();
+        // Invitations were made without being indexed in version 6. The
+        // fixture counts the invitation made here.
+        let invitation = state.invite(
+            owner,
+            Invitee::Username("jane".to_string()),
+            0,
+            SharePermission::View,
+            None,
+        );
+        clear_invitee_index(&mut state);
+
+        let state = decode_state(include_bytes!("../fixtures/state_v6.cbor"));
+
+        assert_eq!(state.invitation_count, 1);
+        assert_eq!(
+            state
+                .indexes
+                .invitations_to(&Invitee::Username("jane".to_string())),
+            vec![(0, invitation.invitation_id)]
+        );
+        state.check_indexes();
+    }
+
+    fn clear_invitee_index(state: &mut State) {
+        let keys: Vec<_> = state
+            .indexes
+            .invitee_invitations
+            .iter()
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            state.indexes.invitee_invitations.remove(&key);
+        }
+    }
+
+    #[test]
+    fn loads_version_7_state() {
+        // Upload sessions were started without being indexed in version 7.
+        init_upload_sessions().insert(
+            0,
+            UploadSession {
+                total_size: None,
+                chunk_size: 3,
+                num_chunks: 2,
+                alias: None,
+                created_at: 0,
+                expires_at: 10,
+            },
+        );
+
+        let state = decode_state(include_bytes!("../fixtures/state_v7.cbor"));
+
+        assert_eq!(state.indexes.expired_uploads(10, 10), vec![0]);
+        state.check_indexes();
+    }
+
+    #[test]
+    fn loads_version_8_state() {
+        let owner = Principal::from_slice(&[0, 1, 2]);
+        let mut state = State::default();
+        set_user_info(
+            &mut state,
+            owner,
+            User {
+                username: "John".to_string(),
+                public_key: vec![1, 2, 3],
+            },
+        );
+        let file_id = upload_file_atomic(
+            owner,
+            UploadFileAtomicRequest {
+                name: "file_name".to_string(),
+                content: vec![1; MIN_CHUNK_SIZE as usize],
+                file_type: "image/jpeg".to_string(),
+                num_chunks: 2,
+                total_size: None,
+                chunk_size: None,
+                file_id: reserve_file_id(&mut state, owner).unwrap().file_id,
+            },
+            &mut state,
+        )
+        .unwrap();
+        // Uploads were started without a session before version 8.
+        state.remove_upload_session(file_id);
+
+        let mut state = decode_state(include_bytes!("../fixtures/state_v8.cbor"));
+
+        let session = state.upload_sessions.get(&file_id).unwrap();
+        assert_eq!((session.chunk_size, session.alias), (MIN_CHUNK_SIZE, None));
+        assert_eq!(session.expires_at, crate::get_time() + UPLOAD_SESSION_TTL);
+        state.check_indexes();
+
+        // The upload can now be aborted, or is reclaimed once it expires.
+        abort_upload(&mut state, owner, file_id, None).unwrap();
+        assert!(state.file_data.is_empty());
+    }
+
+    #[test]
+    fn loads_version_9_state() {
+        let owner = Principal::from_slice(&[0, 1, 2]);
+        let mut state = State::default();
+        set_user_info(
+            &mut state,
+            owner,
+            User {
+                username: "John".to_string(),
+                public_key: vec![1, 2, 3],
+            },
+        );
+        let options = RequestOptions {
+            expires_at: Some(10),
+            ..Default::default()
+        };
+        let alias = request_file_with_options(owner, "request", options, &mut state).unwrap();
+        let retired = request_file(owner, "retired", &mut state).unwrap();
+        disable_alias(&mut state, owner, AliasTarget::File(1)).unwrap();
+        // Aliases weren't kept by expiry, nor dated when retired, in version 9.
+        for map in [&mut state.alias_expiries, &mut state.alias_retirements] {
+            let keys: Vec<_> = map.iter().map(|(key, _)| key).collect();
+            for key in keys {
+                map.remove(&key);
+            }
+        }
+        let mut retired_alias = state.retired_aliases.get(&retired).unwrap();
+        retired_alias.retired_at = 0;
+        state.retired_aliases.insert(retired.clone(), retired_alias);
+
+        let state = decode_state(include_bytes!("../fixtures/state_v9.cbor"));
+
+        let now = crate::get_time();
+        assert_eq!(
+            state.alias_expiries.iter().collect::<Vec<_>>(),
+            vec![(TimedAlias { at: 10, alias }, ())]
+        );
+        assert_eq!(
+            state.alias_retirements.iter().collect::<Vec<_>>(),
+            vec![(
+                TimedAlias {
+                    at: now,
+                    alias: retired.clone()
+                },
+                ()
+            )]
+        );
+        assert_eq!(state.retired_aliases.get(&retired).unwrap().retired_at, now);
+    }
+
+    #[test]
+    fn loads_version_10_state() {
+        let sharee = Principal::from_slice(&[0, 1, 3]);
+        // Shares were made without indexing their expiry in version 10.
+        init_file_shares().insert(
+            (sharee, 0),
+            Share {
+                permission: SharePermission::View,
+                expires_at: Some(10),
+            },
+        );
+
+        let state = decode_state(include_bytes!("../fixtures/state_v10.cbor"));
+
+        assert_eq!(state.indexes.expired_shares(10, 10), vec![(sharee, 0)]);
+        state.check_indexes();
+    }
+
+    #[test]
+    fn loads_version_11_state() {
+        let jane = Principal::from_slice(&[0, 1, 3]);
+        // Users registered without indexing their usernames in version 11.
+        init_users().insert(
+            jane,
+            User {
+                username: "jane".to_string(),
+                public_key: vec![1, 2, 3],
+            },
+        );
+
+        let state = decode_state(include_bytes!("../fixtures/state_v11.cbor"));
+
+        assert_eq!(state.indexes.principal_by_username("jane"), Some(jane));
+        state.check_indexes();
+    }
+
+    #[test]
+    fn encoded_state_is_versioned() {
+        let mut state = State::default();
+        state.generate_file_id();
+
+        let blob: Value = ciborium::de::from_reader(&*encode_state(&state)).unwrap();
+        let versioned: VersionedValue = blob.deserialized().unwrap();
+        assert_eq!(versioned.version, STATE_VERSION);
+
+        let decoded = decode_state(&encode_state(&state));
+        assert_eq!(decoded.file_count, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "unsupported state version")]
+    fn rejects_newer_versions() {
+        let mut bytes = vec![];
+        ciborium::ser::into_writer(
+            &VersionedState {
+                version: STATE_VERSION + 1,
+                state: &State::default(),
+            },
+            &mut bytes,
+        )
+        .unwrap();
+        decode_state(&bytes);
+    }
+}
@@ -0,0 +1,189 @@
+use super::vetkd_public_key::public_key;
+use crate::api::{authorize_alias, is_expired};
+use crate::vetkd::system_api::{SystemApi, VetKdApi};
+use crate::{
+    caller_not_suspended, get_time, with_state, AliasKeyInfo, FileContent, FileMetadata, State,
+    VetkdError,
+};
+use ic_cdk::update;
+
+/// Lets anyone with the alias of a request encrypt the file for the requester,
+/// so that only the requester and their sharees can decrypt it. A group alias
+/// needs the id of the file in the group.
+#[update(guard = "caller_not_suspended")]
+async fn get_alias_key_info(
+    alias: String,
+    file_id: Option<u64>,
+) -> Result<AliasKeyInfo, VetkdError> {
+    alias_key_info(&SystemApi, alias, file_id).await
+}
+
+/// Resolves an alias to the pending file it may upload, like `upload_file` does.
+fn resolve_alias(
+    state: &State,
+    alias: &str,
+    file_id: Option<u64>,
+) -> Result<(u64, FileMetadata), VetkdError> {
+    let file_id = file_id
+        .or_else(|| state.file_alias_index.get(&alias.to_string()))
+        .ok_or(VetkdError::NotFound)?;
+    let file = state.file_data.get(&file_id).ok_or(VetkdError::NotFound)?;
+    // Only requests that wait for their upload have an alias.
+    let FileContent::Pending { alias: file_alias } = &file.content else {
+        return Err(VetkdError::NotFound);
+    };
+    authorize_alias(state, file_id, file_alias, alias).map_err(|_| VetkdError::PermissionError)?;
+    if is_expired(file.metadata.alias_expires_at, get_time()) {
+        return Err(VetkdError::PermissionError);
+    }
+    Ok((file_id, file.metadata))
+}
+
+pub(crate) async fn alias_key_info(
+    api: &impl VetKdApi,
+    alias: String,
+    file_id: Option<u64>,
+) -> Result<AliasKeyInfo, VetkdError> {
+    let (file_id, metadata) = with_state(|s| resolve_alias(s, &alias, file_id))?;
+
+    Ok(AliasKeyInfo {
+        file_id,
+        public_key: public_key(api).await?,
+        derivation_id: metadata.derivation_id(file_id),
+        key_derivation: metadata.key_derivation,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{
+        multi_request, request_file, request_file_with_options, set_user_info, upload_file,
+    };
+    use crate::vetkd::system_api::{block_on, MockVetKdApi};
+    use crate::{
+        file_derivation_id, with_state_mut, KeyDerivation, MultiRequestInput, RequestOptions, User,
+    };
+    use candid::Principal;
+
+    const REQUESTER: Principal = Principal::from_slice(&[0, 1, 2]);
+
+    fn get_info(alias: &str) -> Result<AliasKeyInfo, VetkdError> {
+        get_file_info(alias, None)
+    }
+
+    fn get_file_info(alias: &str, file_id: Option<u64>) -> Result<AliasKeyInfo, VetkdError> {
+        block_on(alias_key_info(
+            &MockVetKdApi::default(),
+            alias.to_string(),
+            file_id,
+        ))
+    }
+
+    fn add_requester(s: &mut State) {
+        set_user_info(
+            s,
+            REQUESTER,
+            User {
+                username: "John".to_string(),
+                public_key: vec![1, 2, 3],
+            },
+        );
+    }
+
+    #[test]
+    fn requests_are_encrypted_for_the_requester() {
+        let alias = with_state_mut(|s| {
+            add_requester(s);
+            request_file(REQUESTER, "request", s).unwrap()
+        });
+
+        assert_eq!(
+            get_info(&alias),
+            Ok(AliasKeyInfo {
+                file_id: 0,
+                public_key: b"insecure_test_key_1".to_vec(),
+                derivation_id: file_derivation_id(REQUESTER, 0, 0),
+                key_derivation: KeyDerivation::PerFile { key_version: 0 },
+            })
+        );
+
+        // The alias can't be used once the file is uploaded.
+        with_state_mut(|s| {
+            upload_file(
+                0,
+                alias.clone(),
+                vec![1, 2, 3],
+                "jpeg".to_string(),
+                1,
+                Some(3),
+                Some(3),
+                s,
+            )
+            .unwrap()
+        });
+        assert_eq!(get_info(&alias), Err(VetkdError::NotFound));
+    }
+
+    #[test]
+    fn unknown_aliases_are_rejected() {
+        assert_eq!(get_info("unknown"), Err(VetkdError::NotFound));
+    }
+
+    #[test]
+    fn group_aliases_need_a_file_of_the_group() {
+        let (group_alias, other_alias) = with_state_mut(|s| {
+            add_requester(s);
+            let group = multi_request(
+                REQUESTER,
+                MultiRequestInput {
+                    group_name: "group".to_string(),
+                    file_names: vec!["a".to_string(), "b".to_string()],
+                    save_as_template: false,
+                    expires_at: None,
+                    due_at: None,
+                },
+                s,
+            )
+            .unwrap();
+            (
+                group.group_alias,
+                request_file(REQUESTER, "other", s).unwrap(),
+            )
+        });
+
+        assert_eq!(
+            get_file_info(&group_alias, Some(1)).map(|info| info.derivation_id),
+            Ok(file_derivation_id(REQUESTER, 1, 0))
+        );
+        assert_eq!(get_info(&group_alias), Err(VetkdError::NotFound));
+        // Neither alias opens a file outside of what it was made for.
+        assert_eq!(
+            get_file_info(&group_alias, Some(2)),
+            Err(VetkdError::PermissionError)
+        );
+        assert_eq!(
+            get_file_info(&other_alias, Some(0)),
+            Err(VetkdError::PermissionError)
+        );
+    }
+
+    #[test]
+    fn expired_aliases_are_rejected() {
+        let alias = with_state_mut(|s| {
+            add_requester(s);
+            request_file_with_options(
+                REQUESTER,
+                "request",
+                RequestOptions {
+                    expires_at: Some(get_time()),
+                    due_at: None,
+                },
+                s,
+            )
+            .unwrap()
+        });
+
+        assert_eq!(get_info(&alias), Err(VetkdError::PermissionError));
+    }
+}
@@ -1,2 +1,3 @@
+pub mod alias_key_info;
 pub mod vetkd_encrypted_key;
 pub mod vetkd_public_key;
@@ -1,52 +1,254 @@
+use crate::api::holds_revoked_key;
 use crate::declarations::vetkd_system_api::{
-    vetkd_system_api, VetkdCurve, VetkdDeriveEncryptedKeyArgs, VetkdDeriveEncryptedKeyArgsKeyId,
+    VetkdCurve, VetkdDeriveEncryptedKeyArgs, VetkdDeriveEncryptedKeyArgsKeyId,
 };
-use crate::with_state;
+use crate::vetkd::system_api::{SystemApi, VetKdApi};
+use crate::{
+    caller_not_suspended, file_derivation_id, with_state, File, FileKeyInfo, KeyDerivation,
+    SharePermission, State, VetkdError,
+};
+use candid::Principal;
 // use ic_cdk::println;
-use ic_cdk::update;
+use ic_cdk::{query, update};
 use serde_bytes::ByteBuf;
 
-#[update]
+#[update(guard = "caller_not_suspended")]
 async fn vetkd_encrypted_key(
     encryption_public_key: Vec<u8>,
     file_id: Option<u64>,
-) -> Result<Vec<u8>, String> {
-    // println!("vetkd_encrypted_key called with file_id: {:?}", file_id);
-    // If a file_id is provided, use the file owner's principal as derivation_id
-    let derivation_id = if let Some(id) = file_id {
-        // println!("Looking up owner principal for file_id: {}", id);
-        // Look up the file's owner principal from metadata
-        let principal = with_state(|state| {
-            state
-                .file_data
-                .get(&id)
-                .map(|file| file.metadata.requester_principal.as_slice().to_vec())
-                .ok_or_else(|| "File not found".to_string())
-        })?;
-        // println!("Found owner principal for file_id {}: {:?}", id, principal);
-        principal
-    } else {
+) -> Result<Vec<u8>, VetkdError> {
+    let caller = ic_cdk::api::caller();
+    encrypted_key(&SystemApi, caller, encryption_public_key, file_id).await
+}
+
+#[query(guard = "caller_not_suspended")]
+fn get_file_key_info(file_id: u64) -> Result<FileKeyInfo, VetkdError> {
+    with_state(|state| file_key_info(state, ic_cdk::api::caller(), file_id))
+}
+
+/// Returns how the key of a file the caller can access is derived.
+pub(crate) fn file_key_info(
+    state: &State,
+    caller: Principal,
+    file_id: u64,
+) -> Result<FileKeyInfo, VetkdError> {
+    let file = authorize_file_access(state, caller, file_id)?;
+    Ok(FileKeyInfo {
+        file_id,
+        derivation_id: file.metadata.derivation_id(file_id),
+        key_derivation: file.metadata.key_derivation.clone(),
+        reencryption_derivation_id: reencryption_derivation_id(state, caller, file_id, &file),
+    })
+}
+
+/// Returns the derivation id of the file's pending key, which only the owner
+/// needs to re-encrypt the file.
+fn reencryption_derivation_id(
+    state: &State,
+    caller: Principal,
+    file_id: u64,
+    file: &File,
+) -> Option<Vec<u8>> {
+    let key_version = file.metadata.pending_key_version?;
+    state
+        .is_owner(caller, file_id)
+        .then(|| file_derivation_id(file.metadata.requester_principal, file_id, key_version))
+}
+
+/// Returns the file if the caller may access it the same way `download_file`
+/// allows.
+fn authorize_file_access(
+    state: &State,
+    caller: Principal,
+    file_id: u64,
+) -> Result<File, VetkdError> {
+    if caller == Principal::anonymous() {
+        return Err(VetkdError::AnonymousCaller);
+    }
+    let file = state.file_data.get(&file_id).ok_or(VetkdError::NotFound)?;
+    if !state.is_owner(caller, file_id)
+        && (!state.has_permission(caller, file_id, SharePermission::Download)
+            || holds_revoked_key(state, caller, file_id))
+    {
+        return Err(VetkdError::PermissionError);
+    }
+    Ok(file)
+}
+
+/// Derives the key of the given file, or the caller's own key if no file is
+/// given, encrypted for the caller.
+pub(crate) async fn encrypted_key(
+    api: &impl VetKdApi,
+    caller: Principal,
+    encryption_public_key: Vec<u8>,
+    file_id: Option<u64>,
+) -> Result<Vec<u8>, VetkdError> {
+    let args = with_state(|state| {
+        derive_encrypted_key_args(state, caller, encryption_public_key, file_id)
+    })?;
+    api.vetkd_derive_encrypted_key(args).await
+}
+
+/// Builds the arguments to derive a key, provided the caller may access the file.
+fn derive_encrypted_key_args(
+    state: &State,
+    caller: Principal,
+    encryption_public_key: Vec<u8>,
+    file_id: Option<u64>,
+) -> Result<VetkdDeriveEncryptedKeyArgs, VetkdError> {
+    // If a file_id is provided, use the key the file is encrypted with
+    let derivation_id = match file_id {
+        Some(id) => {
+            let key_info = file_key_info(state, caller, id)?;
+            // The owner's key decrypts all of their files, so only they get it.
+            // Sharees get the key once the file is migrated to a per-file key.
+            if key_info.key_derivation == KeyDerivation::Owner && !state.is_owner(caller, id) {
+                return Err(VetkdError::PermissionError);
+            }
+            key_info.derivation_id
+        }
         // Default to using the caller's principal
-        let caller = ic_cdk::api::caller().as_slice().to_vec();
-        // println!("Using caller principal as derivation_id: {:?}", caller);
-        caller
+        None if caller == Principal::anonymous() => return Err(VetkdError::AnonymousCaller),
+        None => caller.as_slice().to_vec(),
     };
 
-    let args = VetkdDeriveEncryptedKeyArgs {
+    Ok(VetkdDeriveEncryptedKeyArgs {
         key_id: VetkdDeriveEncryptedKeyArgsKeyId {
-            name: "insecure_test_key_1".to_string(),
+            name: state.config.vetkd_key_name.clone(),
             curve: VetkdCurve::Bls12381G2,
         },
-        derivation_path: vec![],
-        // Use requester_principal as derivation ID
+        derivation_path: state
+            .config
+            .vetkd_derivation_path
+            .iter()
+            .cloned()
+            .map(ByteBuf::from)
+            .collect(),
         derivation_id: ByteBuf::from(derivation_id),
         encryption_public_key: ByteBuf::from(encryption_public_key),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::{
+        reserve_file_id, set_user_info, share_file, upload_file_atomic, UploadFileAtomicRequest,
     };
+    use crate::vetkd::system_api::{block_on, MockVetKdApi};
+    use crate::{with_state_mut, User};
+
+    const OWNER: Principal = Principal::from_slice(&[0, 1, 2]);
+    const SHAREE: Principal = Principal::from_slice(&[3, 4, 5]);
+    const STRANGER: Principal = Principal::from_slice(&[6, 7, 8]);
+
+    /// Uploads a file owned by `OWNER` and shared with `SHAREE`.
+    fn setup() -> u64 {
+        with_state_mut(|state| {
+            for (principal, username) in [(OWNER, "John"), (SHAREE, "Jane")] {
+                set_user_info(
+                    state,
+                    principal,
+                    User {
+                        username: username.to_string(),
+                        public_key: vec![1, 2, 3],
+                    },
+                );
+            }
+            let file_id = upload_file_atomic(
+                OWNER,
+                UploadFileAtomicRequest {
+                    name: "file_name".to_string(),
+                    content: vec![1, 2, 3],
+                    file_type: "image/jpeg".to_string(),
+                    num_chunks: 1,
+                    total_size: Some(3),
+                    chunk_size: Some(3),
+                    file_id: reserve_file_id(state, OWNER).unwrap().file_id,
+                },
+                state,
+            )
+            .unwrap();
+            share_file(state, OWNER, SHAREE, file_id);
+            file_id
+        })
+    }
+
+    fn get_key(caller: Principal, file_id: Option<u64>) -> Result<Vec<u8>, VetkdError> {
+        block_on(encrypted_key(
+            &MockVetKdApi::default(),
+            caller,
+            vec![9],
+            file_id,
+        ))
+    }
+
+    #[test]
+    fn owners_and_sharees_get_the_file_key() {
+        let file_id = setup();
+        let file_key = [&file_derivation_id(OWNER, file_id, 0)[..], &[9]].concat();
+
+        assert_eq!(
+            with_state(|state| file_key_info(state, SHAREE, file_id)),
+            Ok(FileKeyInfo {
+                file_id,
+                derivation_id: file_derivation_id(OWNER, file_id, 0),
+                key_derivation: KeyDerivation::PerFile { key_version: 0 },
+                reencryption_derivation_id: None,
+            })
+        );
+        assert_eq!(get_key(OWNER, Some(file_id)), Ok(file_key.clone()));
+        assert_eq!(get_key(SHAREE, Some(file_id)), Ok(file_key));
+        // The key differs from the one of the owner's other files.
+        assert_ne!(get_key(OWNER, Some(file_id)), get_key(OWNER, None));
+    }
+
+    #[test]
+    fn owner_keys_are_only_derived_for_the_owner() {
+        let file_id = setup();
+        // Files stored before per-file keys are encrypted with the owner's key.
+        with_state_mut(|state| {
+            let mut file = state.file_data.get(&file_id).unwrap();
+            file.metadata.key_derivation = KeyDerivation::Owner;
+            state.file_data.insert(file_id, file);
+        });
+
+        assert_eq!(
+            get_key(OWNER, Some(file_id)),
+            Ok([OWNER.as_slice(), &[9]].concat())
+        );
+        assert_eq!(
+            get_key(SHAREE, Some(file_id)),
+            Err(VetkdError::PermissionError)
+        );
+    }
+
+    #[test]
+    fn others_are_rejected() {
+        let file_id = setup();
+
+        assert_eq!(
+            get_key(STRANGER, Some(file_id)),
+            Err(VetkdError::PermissionError)
+        );
+        assert_eq!(
+            get_key(Principal::anonymous(), Some(file_id)),
+            Err(VetkdError::AnonymousCaller)
+        );
+        assert_eq!(
+            get_key(Principal::anonymous(), None),
+            Err(VetkdError::AnonymousCaller)
+        );
+        assert_eq!(get_key(OWNER, Some(7)), Err(VetkdError::NotFound));
+    }
 
-    let (result,) = vetkd_system_api
-        .vetkd_derive_encrypted_key(args)
-        .await
-        .unwrap();
+    #[test]
+    fn callers_get_their_own_key_without_a_file() {
+        setup();
 
-    Ok(result.encrypted_key.to_vec())
+        assert_eq!(
+            get_key(STRANGER, None),
+            Ok([STRANGER.as_slice(), &[9]].concat())
+        );
+    }
 }
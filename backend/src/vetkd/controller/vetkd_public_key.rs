@@ -1,20 +1,110 @@
 use crate::declarations::vetkd_system_api::{
-    vetkd_system_api, VetkdCurve, VetkdPublicKeyArgs, VetkdPublicKeyArgsKeyId,
+    VetkdCurve, VetkdPublicKeyArgs, VetkdPublicKeyArgsKeyId,
 };
-use ic_cdk::update;
+use crate::vetkd::system_api::{SystemApi, VetKdApi};
+use crate::{caller_not_suspended, with_state, with_state_mut, State, VetkdError};
+use ic_cdk::{query, update};
+use serde_bytes::ByteBuf;
+use std::time::Duration;
+
+/// Returns the public key of the configured vetKD key, fetching it if it isn't cached yet.
+#[update(guard = "caller_not_suspended")]
+async fn vetkd_public_key() -> Result<Vec<u8>, VetkdError> {
+    public_key(&SystemApi).await
+}
+
+/// Returns the cached public key of the configured vetKD key, or `not_found`
+/// until it has been fetched.
+#[query(guard = "caller_not_suspended")]
+fn get_vetkd_public_key() -> Result<Vec<u8>, VetkdError> {
+    with_state(cached_public_key).ok_or(VetkdError::NotFound)
+}
+
+fn cached_public_key(state: &State) -> Option<Vec<u8>> {
+    state.vetkd_public_keys.get(&state.config.vetkd_key_id())
+}
+
+/// Fetches the public key of the configured vetKD key in the background, unless it is cached.
+pub fn refresh_public_key() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        ic_cdk::spawn(async {
+            if let Err(err) = public_key(&SystemApi).await {
+                ic_cdk::println!("Failed to fetch the vetKD public key: {:?}", err);
+            }
+        })
+    });
+}
+
+pub(crate) async fn public_key(api: &impl VetKdApi) -> Result<Vec<u8>, VetkdError> {
+    let key_id = with_state(|s| s.config.vetkd_key_id());
+    if let Some(public_key) = with_state(|s| s.vetkd_public_keys.get(&key_id)) {
+        return Ok(public_key);
+    }
 
-#[update]
-async fn vetkd_public_key() -> Result<Vec<u8>, String> {
     let args = VetkdPublicKeyArgs {
         key_id: VetkdPublicKeyArgsKeyId {
-            name: "insecure_test_key_1".to_string(),
+            name: key_id.name.clone(),
             curve: VetkdCurve::Bls12381G2,
         },
-        derivation_path: vec![],
+        derivation_path: key_id
+            .derivation_path
+            .iter()
+            .cloned()
+            .map(ByteBuf::from)
+            .collect(),
         canister_id: None,
     };
+    let public_key = api.vetkd_public_key(args).await?;
+    // The key is cached under the key id it was fetched for, even if the
+    // config changed during the call.
+    with_state_mut(|s| s.vetkd_public_keys.insert(key_id, public_key.clone()));
+    Ok(public_key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vetkd::system_api::{block_on, MockVetKdApi};
+    use crate::{AliasFormat, CanisterConfig};
+
+    fn configure(vetkd_key_name: &str) {
+        with_state_mut(|s| {
+            s.config = CanisterConfig {
+                vetkd_key_name: vetkd_key_name.to_string(),
+                vetkd_derivation_path: vec![b"_path".to_vec()],
+                alias_format: AliasFormat::default(),
+            }
+        });
+    }
+
+    #[test]
+    fn the_public_key_is_fetched_once_per_key() {
+        configure("key_1");
+        assert_eq!(with_state(cached_public_key), None);
 
-    let (result,) = vetkd_system_api.vetkd_public_key(args).await.unwrap();
+        assert_eq!(
+            block_on(public_key(&MockVetKdApi::default())),
+            Ok(b"key_1_path".to_vec())
+        );
+        assert_eq!(with_state(cached_public_key), Some(b"key_1_path".to_vec()));
+        // The cached key is served without calling the system API.
+        assert_eq!(
+            block_on(public_key(&MockVetKdApi { reject: true })),
+            Ok(b"key_1_path".to_vec())
+        );
 
-    Ok(result.public_key.to_vec())
+        // Another key is fetched again.
+        configure("test_key_1");
+        assert_eq!(with_state(cached_public_key), None);
+        assert_eq!(
+            block_on(public_key(&MockVetKdApi { reject: true })),
+            Err(VetkdError::SystemApiError(
+                "CanisterReject: rejected".to_string()
+            ))
+        );
+        assert_eq!(
+            block_on(public_key(&MockVetKdApi::default())),
+            Ok(b"test_key_1_path".to_vec())
+        );
+    }
 }
@@ -1 +1,2 @@
 pub mod controller;
+pub(crate) mod system_api;
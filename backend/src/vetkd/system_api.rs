@@ -0,0 +1,237 @@
+use crate::declarations::vetkd_system_api::{
+    vetkd_system_api, EcdsaPublicKeyArgs, EcdsaPublicKeyResult, SchnorrPublicKeyArgs,
+    SchnorrPublicKeyResult, SignWithEcdsaArgs, SignWithSchnorrArgs, VetkdDeriveEncryptedKeyArgs,
+    VetkdPublicKeyArgs,
+};
+use crate::VetkdError;
+use ic_cdk::api::call::{CallResult, RejectionCode};
+
+/// The calls the canister makes to the vetKD system API canister, so that
+/// they can be replaced in tests.
+pub(crate) trait VetKdApi {
+    async fn vetkd_public_key(&self, args: VetkdPublicKeyArgs) -> Result<Vec<u8>, VetkdError>;
+
+    async fn vetkd_derive_encrypted_key(
+        &self,
+        args: VetkdDeriveEncryptedKeyArgs,
+    ) -> Result<Vec<u8>, VetkdError>;
+
+    // No endpoint signs anything yet.
+    #[allow(dead_code)]
+    async fn ecdsa_public_key(
+        &self,
+        args: EcdsaPublicKeyArgs,
+    ) -> Result<EcdsaPublicKeyResult, VetkdError>;
+
+    #[allow(dead_code)]
+    async fn sign_with_ecdsa(&self, args: SignWithEcdsaArgs) -> Result<Vec<u8>, VetkdError>;
+
+    #[allow(dead_code)]
+    async fn schnorr_public_key(
+        &self,
+        args: SchnorrPublicKeyArgs,
+    ) -> Result<SchnorrPublicKeyResult, VetkdError>;
+
+    #[allow(dead_code)]
+    async fn sign_with_schnorr(&self, args: SignWithSchnorrArgs) -> Result<Vec<u8>, VetkdError>;
+}
+
+/// Calls the vetKD system API canister.
+pub(crate) struct SystemApi;
+
+impl VetKdApi for SystemApi {
+    async fn vetkd_public_key(&self, args: VetkdPublicKeyArgs) -> Result<Vec<u8>, VetkdError> {
+        let (result,) = check(vetkd_system_api.vetkd_public_key(args).await)?;
+        Ok(result.public_key.into_vec())
+    }
+
+    async fn vetkd_derive_encrypted_key(
+        &self,
+        args: VetkdDeriveEncryptedKeyArgs,
+    ) -> Result<Vec<u8>, VetkdError> {
+        let (result,) = check(vetkd_system_api.vetkd_derive_encrypted_key(args).await)?;
+        Ok(result.encrypted_key.into_vec())
+    }
+
+    async fn ecdsa_public_key(
+        &self,
+        args: EcdsaPublicKeyArgs,
+    ) -> Result<EcdsaPublicKeyResult, VetkdError> {
+        let (result,) = check(vetkd_system_api.ecdsa_public_key(args).await)?;
+        Ok(result)
+    }
+
+    async fn sign_with_ecdsa(&self, args: SignWithEcdsaArgs) -> Result<Vec<u8>, VetkdError> {
+        let (result,) = check(vetkd_system_api.sign_with_ecdsa(args).await)?;
+        Ok(result.signature.into_vec())
+    }
+
+    async fn schnorr_public_key(
+        &self,
+        args: SchnorrPublicKeyArgs,
+    ) -> Result<SchnorrPublicKeyResult, VetkdError> {
+        let (result,) = check(vetkd_system_api.schnorr_public_key(args).await)?;
+        Ok(result)
+    }
+
+    async fn sign_with_schnorr(&self, args: SignWithSchnorrArgs) -> Result<Vec<u8>, VetkdError> {
+        let (result,) = check(vetkd_system_api.sign_with_schnorr(args).await)?;
+        Ok(result.signature.into_vec())
+    }
+}
+
+/// Turns a rejected call into an error the caller can handle instead of trapping.
+fn check<R>(result: CallResult<R>) -> Result<R, VetkdError> {
+    result.map_err(|(code, message)| system_api_error(code, message))
+}
+
+fn system_api_error(code: RejectionCode, message: String) -> VetkdError {
+    VetkdError::SystemApiError(format!("{:?}: {}", code, message))
+}
+
+/// Answers deterministically without leaving the process. Keys and
+/// signatures are made of the call's inputs, so tests can check what was
+/// derived or signed.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct MockVetKdApi {
+    /// Makes every call fail as if the system API canister rejected it.
+    pub reject: bool,
+}
+
+#[cfg(test)]
+impl MockVetKdApi {
+    fn answer<R>(&self, result: R) -> Result<R, VetkdError> {
+        if self.reject {
+            return Err(system_api_error(
+                RejectionCode::CanisterReject,
+                "rejected".to_string(),
+            ));
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+fn concat_path(key_name: &str, derivation_path: &[serde_bytes::ByteBuf]) -> Vec<u8> {
+    let mut bytes = key_name.as_bytes().to_vec();
+    for segment in derivation_path {
+        bytes.extend_from_slice(segment);
+    }
+    bytes
+}
+
+#[cfg(test)]
+impl VetKdApi for MockVetKdApi {
+    async fn vetkd_public_key(&self, args: VetkdPublicKeyArgs) -> Result<Vec<u8>, VetkdError> {
+        self.answer(concat_path(&args.key_id.name, &args.derivation_path))
+    }
+
+    async fn vetkd_derive_encrypted_key(
+        &self,
+        args: VetkdDeriveEncryptedKeyArgs,
+    ) -> Result<Vec<u8>, VetkdError> {
+        self.answer([&args.derivation_id[..], &args.encryption_public_key[..]].concat())
+    }
+
+    async fn ecdsa_public_key(
+        &self,
+        args: EcdsaPublicKeyArgs,
+    ) -> Result<EcdsaPublicKeyResult, VetkdError> {
+        self.answer(EcdsaPublicKeyResult {
+            public_key: concat_path(&args.key_id.name, &args.derivation_path).into(),
+            chain_code: vec![0; 32].into(),
+        })
+    }
+
+    async fn sign_with_ecdsa(&self, args: SignWithEcdsaArgs) -> Result<Vec<u8>, VetkdError> {
+        let key = concat_path(&args.key_id.name, &args.derivation_path);
+        self.answer([key, args.message_hash.into_vec()].concat())
+    }
+
+    async fn schnorr_public_key(
+        &self,
+        args: SchnorrPublicKeyArgs,
+    ) -> Result<SchnorrPublicKeyResult, VetkdError> {
+        self.answer(SchnorrPublicKeyResult {
+            public_key: concat_path(&args.key_id.name, &args.derivation_path).into(),
+            chain_code: vec![0; 32].into(),
+        })
+    }
+
+    async fn sign_with_schnorr(&self, args: SignWithSchnorrArgs) -> Result<Vec<u8>, VetkdError> {
+        let key = concat_path(&args.key_id.name, &args.derivation_path);
+        self.answer([key, args.message.into_vec()].concat())
+    }
+}
+
+/// Runs a future that never has to wait, such as the calls of `MockVetKdApi`.
+#[cfg(test)]
+pub(crate) fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    use std::task::{Context, Poll, Waker};
+
+    let mut future = std::pin::pin!(future);
+    match future
+        .as_mut()
+        .poll(&mut Context::from_waker(Waker::noop()))
+    {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("the future isn't ready"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::declarations::vetkd_system_api::{
+        EcdsaCurve, SchnorrAlgorithm, SignWithEcdsaArgsKeyId, SignWithSchnorrArgsKeyId,
+    };
+
+    #[test]
+    fn signatures_are_made_of_their_inputs() {
+        assert_eq!(
+            block_on(MockVetKdApi::default().sign_with_ecdsa(SignWithEcdsaArgs {
+                key_id: SignWithEcdsaArgsKeyId {
+                    name: "key".to_string(),
+                    curve: EcdsaCurve::Secp256K1,
+                },
+                derivation_path: vec![vec![1].into()],
+                message_hash: vec![2].into(),
+            })),
+            Ok(b"key\x01\x02".to_vec())
+        );
+        assert_eq!(
+            block_on(
+                MockVetKdApi::default().sign_with_schnorr(SignWithSchnorrArgs {
+                    aux: None,
+                    key_id: SignWithSchnorrArgsKeyId {
+                        algorithm: SchnorrAlgorithm::Ed25519,
+                        name: "key".to_string(),
+                    },
+                    derivation_path: vec![],
+                    message: vec![3].into(),
+                })
+            ),
+            Ok(b"key\x03".to_vec())
+        );
+    }
+
+    #[test]
+    fn rejected_calls_are_errors() {
+        let api = MockVetKdApi { reject: true };
+
+        assert_eq!(
+            block_on(api.sign_with_ecdsa(SignWithEcdsaArgs {
+                key_id: SignWithEcdsaArgsKeyId {
+                    name: "key".to_string(),
+                    curve: EcdsaCurve::Secp256K1,
+                },
+                derivation_path: vec![],
+                message_hash: vec![4].into(),
+            })),
+            Err(VetkdError::SystemApiError(
+                "CanisterReject: rejected".to_string()
+            ))
+        );
+    }
+}
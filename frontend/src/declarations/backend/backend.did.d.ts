@@ -20,7 +20,7 @@ export type RenameCanisterResponse = { 'Ok' : null } |
   { 'NotAuthorized' : null } |
   { 'InternalError' : string };
 export type VetkdEncryptedKeyResponse = { 'Ok' : Uint8Array | number[] } |
-  { 'Err' : string };
+  { 'Err' : vetkd_error };
 export type VetkdPublicKeyResponse = { 'Ok' : Uint8Array | number[] } |
   { 'Err' : string };
 export type download_file_response = { 'found_file' : found_file } |
@@ -31,7 +31,25 @@ export interface file {
   'contents' : [] | [Uint8Array | number[]],
   'metadata' : file_metadata,
 }
+export type alias_error = { 'not_found' : null } |
+  { 'permission_error' : null } |
+  { 'already_uploaded' : null } |
+  { 'unavailable' : null };
+export interface alias_key_info {
+  'key_derivation' : key_derivation,
+  'public_key' : Uint8Array | number[],
+  'derivation_id' : Uint8Array | number[],
+  'file_id' : file_id,
+}
 export type file_id = bigint;
+export interface file_key_info {
+  'key_derivation' : key_derivation,
+  'derivation_id' : Uint8Array | number[],
+  'reencryption_derivation_id' : [] | [Uint8Array | number[]],
+  'file_id' : file_id,
+}
+export type key_derivation = { 'owner' : null } |
+  { 'per_file' : { 'key_version' : number } };
 export interface file_info {
   'alias' : string,
   'file_name' : string,
@@ -47,16 +65,26 @@ export interface file_metadata {
 }
 export type file_status = { 'partially_uploaded' : null } |
   { 'pending' : { 'alias' : string, 'requested_at' : bigint } } |
+  {
+    'overdue' : {
+      'alias' : string,
+      'requested_at' : bigint,
+      'due_at' : bigint,
+    }
+  } |
   { 'uploaded' : { 'uploaded_at' : bigint } };
 export interface found_file {
   'contents' : Uint8Array | number[],
   'file_type' : string,
   'num_chunks' : bigint,
 }
+export type get_alias_info_error = { 'closed' : null } |
+  { 'expired' : null } |
+  { 'not_found' : null };
 export type get_alias_info_response = {
     'Ok' : { 'user' : user, 'file_name' : string, 'file_id' : file_id }
   } |
-  { 'Err' : { 'not_found' : null } };
+  { 'Err' : get_alias_info_error };
 export type get_users_response = { 'permission_error' : null } |
   { 'users' : Array<user> };
 export interface group_info {
@@ -92,18 +120,41 @@ export interface upload_file_atomic_request {
   'name' : string,
   'file_type' : string,
   'num_chunks' : bigint,
+  'total_size' : [] | [bigint],
+  'chunk_size' : [] | [bigint],
+  'file_id' : file_id,
 }
+export type upload_file_atomic_response = { 'Ok' : file_id } |
+  { 'Err' : upload_file_error };
 export interface upload_file_continue_request {
   'contents' : Uint8Array | number[],
   'chunk_id' : bigint,
+  'alias' : [] | [string],
+  'sha256' : [] | [Uint8Array | number[]],
   'file_id' : file_id,
 }
-export type upload_file_error = { 'not_requested' : null } |
-  { 'already_uploaded' : null };
+export type upload_file_error = { 'already_uploaded' : null } |
+  { 'not_requested' : null } |
+  { 'hash_mismatch' : null } |
+  { 'upload_not_started' : null } |
+  { 'chunk_out_of_range' : null } |
+  { 'chunk_already_uploaded' : null } |
+  { 'chunk_too_large' : null } |
+  { 'invalid_chunk_size' : null } |
+  { 'invalid_file_size' : null } |
+  { 'permission_error' : null } |
+  { 'quota_exceeded' : null } |
+  { 'no_key_rotation' : null } |
+  { 'missing_chunks' : null } |
+  { 'alias_mismatch' : null } |
+  { 'alias_expired' : null };
 export interface upload_file_request {
   'file_type' : string,
+  'alias' : string,
   'num_chunks' : bigint,
+  'total_size' : [] | [bigint],
   'file_content' : Uint8Array | number[],
+  'chunk_size' : [] | [bigint],
   'file_id' : file_id,
 }
 export type upload_file_response = { 'Ok' : null } |
@@ -115,11 +166,25 @@ export interface user {
 }
 export type who_am_i_response = { 'known_user' : { 'username' : string } } |
   { 'unknown_user' : null };
+export type vetkd_error = { 'anonymous_caller' : null } |
+  { 'not_found' : null } |
+  { 'permission_error' : null } |
+  { 'system_api_error' : string };
 export interface _SERVICE {
   'delete_file' : ActorMethod<[file_id], share_file_response>,
   'delete_template' : ActorMethod<[string], undefined>,
   'download_file' : ActorMethod<[file_id, bigint], download_file_response>,
   'get_alias_info' : ActorMethod<[string], get_alias_info_response>,
+  'get_alias_key_info' : ActorMethod<
+    [string, [] | [file_id]],
+    { 'Ok' : alias_key_info } |
+      { 'Err' : vetkd_error }
+  >,
+  'get_file_key_info' : ActorMethod<
+    [file_id],
+    { 'Ok' : file_key_info } |
+      { 'Err' : vetkd_error }
+  >,
   'get_file_owner_principal' : ActorMethod<
     [bigint],
     { 'Ok' : Uint8Array | number[] } |
@@ -128,7 +193,7 @@ export interface _SERVICE {
   'get_group_by_alias' : ActorMethod<
     [string],
     { 'Ok' : group_info } |
-      { 'Err' : { 'not_found' : null } }
+      { 'Err' : get_alias_info_error }
   >,
   'get_request_groups' : ActorMethod<[], Array<public_request_group>>,
   'get_requests' : ActorMethod<[], Array<file_metadata>>,
@@ -139,24 +204,39 @@ export interface _SERVICE {
   'get_user_templates' : ActorMethod<[], Array<template>>,
   'get_users' : ActorMethod<[], get_users_response>,
   'hello_world' : ActorMethod<[], string>,
-  'multi_request' : ActorMethod<[multi_request_input], multi_request_response>,
+  'multi_request' : ActorMethod<
+    [multi_request_input],
+    { 'Ok' : multi_request_response } |
+      { 'Err' : alias_error }
+  >,
   'register_canister' : ActorMethod<
     [Principal, string],
     RegisterCanisterResponse
   >,
   'rename_canister' : ActorMethod<[Principal, string], RenameCanisterResponse>,
   'rename_file' : ActorMethod<[file_id, string], share_file_response>,
-  'request_file' : ActorMethod<[string], string>,
+  'request_file' : ActorMethod<
+    [string],
+    { 'Ok' : string } |
+      { 'Err' : alias_error }
+  >,
+  'reserve_file_id' : ActorMethod<
+    [],
+    { 'Ok' : file_key_info } |
+      { 'Err' : upload_file_error }
+  >,
   'revoke_share' : ActorMethod<[Principal, file_id], share_file_response>,
   'set_user' : ActorMethod<[string, Uint8Array | number[]], set_user_response>,
   'share_file' : ActorMethod<[Principal, file_id], share_file_response>,
-  'share_file_with_users' : ActorMethod<[Array<Principal>, file_id], undefined>,
   'unregister_canister' : ActorMethod<[Principal], DeleteCanisterResponse>,
   'upload_file' : ActorMethod<[upload_file_request], upload_file_response>,
-  'upload_file_atomic' : ActorMethod<[upload_file_atomic_request], file_id>,
+  'upload_file_atomic' : ActorMethod<
+    [upload_file_atomic_request],
+    upload_file_atomic_response
+  >,
   'upload_file_continue' : ActorMethod<
     [upload_file_continue_request],
-    undefined
+    upload_file_response
   >,
   'username_exists' : ActorMethod<[string], boolean>,
   'vetkd_encrypted_key' : ActorMethod<
@@ -20,13 +20,18 @@ export const idlFactory = ({ IDL }) => {
     'public_key' : IDL.Vec(IDL.Nat8),
     'ic_principal' : IDL.Principal,
   });
+  const get_alias_info_error = IDL.Variant({
+    'closed' : IDL.Null,
+    'expired' : IDL.Null,
+    'not_found' : IDL.Null,
+  });
   const get_alias_info_response = IDL.Variant({
     'Ok' : IDL.Record({
       'user' : user,
       'file_name' : IDL.Text,
       'file_id' : file_id,
     }),
-    'Err' : IDL.Variant({ 'not_found' : IDL.Null }),
+    'Err' : get_alias_info_error,
   });
   const file_info = IDL.Record({
     'alias' : IDL.Text,
@@ -42,6 +47,11 @@ export const idlFactory = ({ IDL }) => {
   const file_status = IDL.Variant({
     'partially_uploaded' : IDL.Null,
     'pending' : IDL.Record({ 'alias' : IDL.Text, 'requested_at' : IDL.Nat64 }),
+    'overdue' : IDL.Record({
+      'alias' : IDL.Text,
+      'requested_at' : IDL.Nat64,
+      'due_at' : IDL.Nat64,
+    }),
     'uploaded' : IDL.Record({ 'uploaded_at' : IDL.Nat64 }),
   });
   const file_metadata = IDL.Record({
@@ -80,6 +90,12 @@ export const idlFactory = ({ IDL }) => {
     'save_as_template' : IDL.Bool,
     'group_name' : IDL.Text,
   });
+  const alias_error = IDL.Variant({
+    'not_found' : IDL.Null,
+    'permission_error' : IDL.Null,
+    'already_uploaded' : IDL.Null,
+    'unavailable' : IDL.Null,
+  });
   const multi_request_response = IDL.Record({
     'group_alias' : IDL.Text,
     'group_id' : IDL.Nat64,
@@ -110,13 +126,29 @@ export const idlFactory = ({ IDL }) => {
   });
   const upload_file_request = IDL.Record({
     'file_type' : IDL.Text,
+    'alias' : IDL.Text,
     'num_chunks' : IDL.Nat64,
+    'total_size' : IDL.Opt(IDL.Nat64),
     'file_content' : IDL.Vec(IDL.Nat8),
+    'chunk_size' : IDL.Opt(IDL.Nat64),
     'file_id' : file_id,
   });
   const upload_file_error = IDL.Variant({
-    'not_requested' : IDL.Null,
     'already_uploaded' : IDL.Null,
+    'not_requested' : IDL.Null,
+    'hash_mismatch' : IDL.Null,
+    'upload_not_started' : IDL.Null,
+    'chunk_out_of_range' : IDL.Null,
+    'chunk_already_uploaded' : IDL.Null,
+    'chunk_too_large' : IDL.Null,
+    'invalid_chunk_size' : IDL.Null,
+    'invalid_file_size' : IDL.Null,
+    'permission_error' : IDL.Null,
+    'quota_exceeded' : IDL.Null,
+    'no_key_rotation' : IDL.Null,
+    'missing_chunks' : IDL.Null,
+    'alias_mismatch' : IDL.Null,
+    'alias_expired' : IDL.Null,
   });
   const upload_file_response = IDL.Variant({
     'Ok' : IDL.Null,
@@ -127,15 +159,46 @@ export const idlFactory = ({ IDL }) => {
     'name' : IDL.Text,
     'file_type' : IDL.Text,
     'num_chunks' : IDL.Nat64,
+    'total_size' : IDL.Opt(IDL.Nat64),
+    'chunk_size' : IDL.Opt(IDL.Nat64),
+    'file_id' : file_id,
+  });
+  const upload_file_atomic_response = IDL.Variant({
+    'Ok' : file_id,
+    'Err' : upload_file_error,
   });
   const upload_file_continue_request = IDL.Record({
     'contents' : IDL.Vec(IDL.Nat8),
     'chunk_id' : IDL.Nat64,
+    'alias' : IDL.Opt(IDL.Text),
+    'sha256' : IDL.Opt(IDL.Vec(IDL.Nat8)),
+    'file_id' : file_id,
+  });
+  const key_derivation = IDL.Variant({
+    'owner' : IDL.Null,
+    'per_file' : IDL.Record({ 'key_version' : IDL.Nat32 }),
+  });
+  const file_key_info = IDL.Record({
+    'key_derivation' : key_derivation,
+    'derivation_id' : IDL.Vec(IDL.Nat8),
+    'reencryption_derivation_id' : IDL.Opt(IDL.Vec(IDL.Nat8)),
     'file_id' : file_id,
   });
+  const alias_key_info = IDL.Record({
+    'key_derivation' : key_derivation,
+    'public_key' : IDL.Vec(IDL.Nat8),
+    'derivation_id' : IDL.Vec(IDL.Nat8),
+    'file_id' : file_id,
+  });
+  const vetkd_error = IDL.Variant({
+    'anonymous_caller' : IDL.Null,
+    'not_found' : IDL.Null,
+    'permission_error' : IDL.Null,
+    'system_api_error' : IDL.Text,
+  });
   const VetkdEncryptedKeyResponse = IDL.Variant({
     'Ok' : IDL.Vec(IDL.Nat8),
-    'Err' : IDL.Text,
+    'Err' : vetkd_error,
   });
   const VetkdPublicKeyResponse = IDL.Variant({
     'Ok' : IDL.Vec(IDL.Nat8),
@@ -158,6 +221,16 @@ export const idlFactory = ({ IDL }) => {
         [get_alias_info_response],
         ['query'],
       ),
+    'get_alias_key_info' : IDL.Func(
+        [IDL.Text, IDL.Opt(file_id)],
+        [IDL.Variant({ 'Ok' : alias_key_info, 'Err' : vetkd_error })],
+        [],
+      ),
+    'get_file_key_info' : IDL.Func(
+        [file_id],
+        [IDL.Variant({ 'Ok' : file_key_info, 'Err' : vetkd_error })],
+        ['query'],
+      ),
     'get_file_owner_principal' : IDL.Func(
         [IDL.Nat64],
         [IDL.Variant({ 'Ok' : IDL.Vec(IDL.Nat8), 'Err' : IDL.Text })],
@@ -166,10 +239,7 @@ export const idlFactory = ({ IDL }) => {
     'get_group_by_alias' : IDL.Func(
         [IDL.Text],
         [
-          IDL.Variant({
-            'Ok' : group_info,
-            'Err' : IDL.Variant({ 'not_found' : IDL.Null }),
-          }),
+          IDL.Variant({ 'Ok' : group_info, 'Err' : get_alias_info_error }),
         ],
         ['query'],
       ),
@@ -188,7 +258,7 @@ export const idlFactory = ({ IDL }) => {
     'hello_world' : IDL.Func([], [IDL.Text], []),
     'multi_request' : IDL.Func(
         [multi_request_input],
-        [multi_request_response],
+        [IDL.Variant({ 'Ok' : multi_request_response, 'Err' : alias_error })],
         [],
       ),
     'register_canister' : IDL.Func(
@@ -202,7 +272,16 @@ export const idlFactory = ({ IDL }) => {
         [],
       ),
     'rename_file' : IDL.Func([file_id, IDL.Text], [share_file_response], []),
-    'request_file' : IDL.Func([IDL.Text], [IDL.Text], []),
+    'request_file' : IDL.Func(
+        [IDL.Text],
+        [IDL.Variant({ 'Ok' : IDL.Text, 'Err' : alias_error })],
+        [],
+      ),
+    'reserve_file_id' : IDL.Func(
+        [],
+        [IDL.Variant({ 'Ok' : file_key_info, 'Err' : upload_file_error })],
+        [],
+      ),
     'revoke_share' : IDL.Func(
         [IDL.Principal, file_id],
         [share_file_response],
@@ -218,11 +297,6 @@ export const idlFactory = ({ IDL }) => {
         [share_file_response],
         [],
       ),
-    'share_file_with_users' : IDL.Func(
-        [IDL.Vec(IDL.Principal), file_id],
-        [],
-        [],
-      ),
     'unregister_canister' : IDL.Func(
         [IDL.Principal],
         [DeleteCanisterResponse],
@@ -231,10 +305,14 @@ export const idlFactory = ({ IDL }) => {
     'upload_file' : IDL.Func([upload_file_request], [upload_file_response], []),
     'upload_file_atomic' : IDL.Func(
         [upload_file_atomic_request],
-        [file_id],
+        [upload_file_atomic_response],
+        [],
+      ),
+    'upload_file_continue' : IDL.Func(
+        [upload_file_continue_request],
+        [upload_file_response],
         [],
       ),
-    'upload_file_continue' : IDL.Func([upload_file_continue_request], [], []),
     'username_exists' : IDL.Func([IDL.Text], [IDL.Bool], ['query']),
     'vetkd_encrypted_key' : IDL.Func(
         [IDL.Vec(IDL.Nat8), IDL.Opt(IDL.Nat64)],
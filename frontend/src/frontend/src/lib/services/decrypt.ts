@@ -64,7 +64,10 @@ export class DecryptService {
       throw new Error("Error: File not found");
     }
 
-    if (enumIs(maybeFile.file_status, "pending")) {
+    if (
+      enumIs(maybeFile.file_status, "pending") ||
+      enumIs(maybeFile.file_status, "overdue")
+    ) {
       throw new Error("Error: File not uploaded");
     }
 
@@ -125,18 +128,11 @@ export class DecryptService {
 
       let decryptedFile: File;
       try {
-        // Get user principal for decryption
-        const userPrincipal = this.auth.authClient
-          .getIdentity?.()
-          .getPrincipal();
-        const userPrincipalBytes = userPrincipal.toUint8Array();
-
         console.log("fileId", fileId);
 
         // Decrypt the file using vetkd
         const decryptedData = await this.vetkdCryptoService.decrypt(
           downloadedFile.found_file.contents as Uint8Array,
-          userPrincipalBytes,
           fileId,
         );
 
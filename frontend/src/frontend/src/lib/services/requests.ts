@@ -104,7 +104,13 @@ export class RequestsService {
     const uploadedFiles: Request[] = [];
 
     for (const file of requests) {
-      if (enumIs(file.file_status, "pending")) {
+      // Overdue requests are still waiting for their upload.
+      const pending = enumIs(file.file_status, "pending")
+        ? file.file_status.pending
+        : enumIs(file.file_status, "overdue")
+          ? file.file_status.overdue
+          : undefined;
+      if (pending) {
         // Determine the sharing status
         let nShared = file.shared_with ? file.shared_with.length : 0;
         let accessMessage = "";
@@ -123,13 +129,9 @@ export class RequestsService {
           name: file.file_name,
           group_name: file.group_name,
           access: accessMessage,
-          formattedDate: formatUploadDate(
-            file.file_status.pending.requested_at,
-          ),
-          formattedDateShort: formatUploadDateShort(
-            file.file_status.pending.requested_at,
-          ),
-          file_alias: file.file_status.pending.alias,
+          formattedDate: formatUploadDate(pending.requested_at),
+          formattedDateShort: formatUploadDateShort(pending.requested_at),
+          file_alias: pending.alias,
           group_alias: file.group_alias?.[0] ?? "",
         });
       }
@@ -17,6 +17,8 @@ export const uploadInProgress = writable(false);
 export type UploadType =
   | {
       type: "request";
+      // The alias of the request, or of the request group the file belongs to.
+      alias: string;
       fileInfo: Extract<get_alias_info_response, { Ok: any }>["Ok"];
     }
   | {
@@ -51,13 +53,18 @@ export class UploadService {
     onError?: (message: string) => void;
     onAborted?: () => void;
   }) {
-    // Get user principal for encryption
-    const userId =
+    // Get the file id and the per-file key derivation id for encryption
+    const keyInfo =
       uploadType.type === "request"
-        ? uploadType.fileInfo.user.ic_principal
-        : this.auth.authClient.getIdentity?.().getPrincipal();
-
-    const userPrincipalBytes = userId.toUint8Array();
+        ? await this.auth.actor.get_alias_key_info(uploadType.alias, [
+            uploadType.fileInfo.file_id,
+          ])
+        : await this.auth.actor.reserve_file_id();
+    if (enumIs(keyInfo, "Err")) {
+      onError("An error occurred while preparing the upload. Please try again.");
+      return;
+    }
+    const derivationId = new Uint8Array(keyInfo.Ok.derivation_id);
 
     // Redundant, moving to vetkd
     // const userPublicKey =
@@ -92,7 +99,7 @@ export class UploadService {
     onStarted(0); // Show start progress while encrypting
     const encryptedData = await this.vetkdCryptoService.encrypt(
       fileBytes,
-      userPrincipalBytes,
+      derivationId,
     );
 
     if (encryptedData.length > 100 * 1024 * 1024) {
@@ -118,9 +125,12 @@ export class UploadService {
         console.log("fileId for request: ", fileId);
         const res = await this.auth.actor.upload_file({
           file_id: fileId,
+          alias: uploadType.alias,
           file_content: firstChunk,
           file_type: dataType,
           num_chunks: BigInt(numChunks),
+          total_size: [BigInt(encryptedData.length)],
+          chunk_size: [BigInt(CHUNK_SIZE)],
         });
         console.log("res done for request", res);
 
@@ -141,12 +151,22 @@ export class UploadService {
           "numChunks: ",
           numChunks,
         );
-        fileId = await this.auth.actor.upload_file_atomic({
+        const res = await this.auth.actor.upload_file_atomic({
           content: firstChunk,
           name: fileName,
           file_type: dataType,
           num_chunks: BigInt(numChunks),
+          total_size: [BigInt(encryptedData.length)],
+          chunk_size: [BigInt(CHUNK_SIZE)],
+          file_id: keyInfo.Ok.file_id,
         });
+        if (enumIs(res, "Err")) {
+          onError(
+            "An error occurred while uploading the file. Please try again.",
+          );
+          return;
+        }
+        fileId = res.Ok;
         console.log("fileId for self: ", fileId);
       }
 
@@ -157,7 +177,8 @@ export class UploadService {
         return;
       }
 
-      await this.uploadChunks(encryptedData, fileId, onChunkUploaded);
+      const alias = uploadType.type === "request" ? uploadType.alias : null;
+      await this.uploadChunks(encryptedData, fileId, alias, onChunkUploaded);
 
       if (this.aborted) {
         onAborted();
@@ -174,6 +195,7 @@ export class UploadService {
   private async uploadChunks(
     content: Uint8Array,
     fileId: bigint,
+    alias: string | null,
     onChunkUploaded: (chunkId: number, size: number) => void,
   ) {
     const numChunks = Math.ceil(content.length / CHUNK_SIZE);
@@ -191,11 +213,18 @@ export class UploadService {
           return;
         }
         const chunk = content.subarray(i * CHUNK_SIZE, (i + 1) * CHUNK_SIZE);
-        await this.auth.actor.upload_file_continue({
+        const res = await this.auth.actor.upload_file_continue({
           file_id: fileId,
           contents: chunk,
           chunk_id: BigInt(i),
+          sha256: [],
+          alias: alias === null ? [] : [alias],
         });
+        if (enumIs(res, "Err")) {
+          throw new Error(
+            `Uploading chunk ${i} failed: ${Object.keys(res.Err)[0]}`,
+          );
+        }
         onChunkUploaded(i, chunk.length);
       }),
     );
@@ -1,12 +1,13 @@
 import * as vetkd from "ic-vetkd-utils";
 import type { ActorType } from "$lib/shared/actor";
+import { enumIs } from "$lib/shared/enums";
 
 export class VetkdCryptoService {
   constructor(private actor: ActorType) {}
 
   async encrypt(
     data: ArrayBuffer,
-    userPrincipalBytes: Uint8Array,
+    derivationId: Uint8Array,
   ): Promise<Uint8Array> {
     try {
       // Get public key from the backend
@@ -30,7 +31,7 @@ export class VetkdCryptoService {
       // Encrypt the data using vetkd IBE
       const encryptedData = vetkd.IBECiphertext.encrypt(
         publicKey,
-        userPrincipalBytes,
+        derivationId,
         encodedMessage,
         seed,
       );
@@ -44,15 +45,18 @@ export class VetkdCryptoService {
 
   async decrypt(
     encryptedData: Uint8Array,
-    userPrincipalBytes: Uint8Array,
     fileId: bigint,
   ): Promise<Uint8Array> {
     try {
-      // First, check if this is a shared file by checking if file is in shared_files
-      const sharedFiles = await this.actor.get_shared_files();
-      const isSharedFile = sharedFiles.some((file) => file.file_id === fileId);
-
-      console.log("Is this a shared file?", isSharedFile);
+      // Files are encrypted with keys derived per file.
+      const keyInfoResponse = await this.actor.get_file_key_info(fileId);
+      if (enumIs(keyInfoResponse, "Err")) {
+        throw new Error(
+          "Error getting file key info: " +
+            Object.keys(keyInfoResponse.Err)[0],
+        );
+      }
+      const derivationId = new Uint8Array(keyInfoResponse.Ok.derivation_id);
 
       // Generate a random seed for the transport secret key
       const seed = window.crypto.getRandomValues(new Uint8Array(32));
@@ -74,36 +78,6 @@ export class VetkdCryptoService {
       console.log("publicKey: ", publicKey);
 
       console.log("fileId: ", fileId);
-      console.log("[fileId]: ", [fileId]);
-
-      // For shared files, always get the owner's principal
-      let principalToUse = userPrincipalBytes;
-
-      if (isSharedFile) {
-        console.log("Getting owner principal for shared file");
-        try {
-          // Get the file owner's principal directly from our endpoint
-          const ownerPrincipalResponse =
-            await this.actor.get_file_owner_principal(fileId);
-
-          if (!ownerPrincipalResponse || "Err" in ownerPrincipalResponse) {
-            throw new Error("Error getting file owner principal");
-          }
-
-          principalToUse = new Uint8Array(ownerPrincipalResponse.Ok);
-          console.log(
-            "Using owner's principal for shared file:",
-            principalToUse,
-          );
-        } catch (e) {
-          console.error(
-            "Failed to get owner principal, falling back to user principal",
-            e,
-          );
-        }
-      } else {
-        console.log("Using current user's principal (file owner)");
-      }
 
       // Get encrypted key from the backend
       const privateKeyResponse = await this.actor.vetkd_encrypted_key(
@@ -127,7 +101,7 @@ export class VetkdCryptoService {
       const key = transportSecretKey.decrypt(
         encryptedKey,
         publicKey,
-        principalToUse, // Use owner's principal if provided
+        derivationId,
       );
       console.log("key: ", key);
 